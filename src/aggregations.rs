@@ -0,0 +1,205 @@
+use crate::doc_values::{Column, DocValues};
+use std::collections::HashMap;
+
+/// Aggregations are computed from the doc values of a set of matching
+/// documents, typically the hits returned by `InvertedIndex::search`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// Top `size` values of a keyword field by document count
+    Terms { field: String, size: usize },
+    /// Document counts of a numeric field bucketed by `interval`, which
+    /// must be positive
+    Histogram { field: String, interval: i64 },
+    /// Min, max, sum and average of a numeric field
+    Stats { field: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregationResult {
+    /// (term, count) ordered by descending count then term
+    Terms(Vec<(String, usize)>),
+    /// (bucket lower bound, count) ordered by bucket, empty buckets omitted
+    Histogram(Vec<(i64, usize)>),
+    Stats(Stats),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stats {
+    pub count: usize,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// Wider than the values so that it cannot overflow
+    pub sum: i128,
+    pub avg: Option<f64>,
+}
+
+impl Aggregation {
+    pub fn terms(field: &str, size: usize) -> Aggregation {
+        Aggregation::Terms {
+            field: field.to_string(),
+            size,
+        }
+    }
+
+    pub fn histogram(field: &str, interval: i64) -> Aggregation {
+        assert!(interval > 0, "histogram interval must be positive");
+        Aggregation::Histogram {
+            field: field.to_string(),
+            interval,
+        }
+    }
+
+    pub fn stats(field: &str) -> Aggregation {
+        Aggregation::Stats {
+            field: field.to_string(),
+        }
+    }
+
+    /// Runs the aggregation over the matching docs of each segment. Documents
+    /// without a value for the field, or fields of the wrong type, are ignored.
+    pub fn collect(&self, segments: &[(&DocValues, &[usize])]) -> AggregationResult {
+        match self {
+            Aggregation::Terms { field, size } => {
+                let mut counts: HashMap<&str, usize> = HashMap::new();
+                for (doc_values, docs) in segments {
+                    if let Some(Column::Keyword(values)) = doc_values.column(field) {
+                        for v in Self::values(values, docs) {
+                            *counts.entry(v).or_insert(0) += 1;
+                        }
+                    }
+                }
+                let mut terms: Vec<(String, usize)> = counts
+                    .into_iter()
+                    .map(|(t, c)| (t.to_string(), c))
+                    .collect();
+                terms.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                terms.truncate(*size);
+                AggregationResult::Terms(terms)
+            }
+            Aggregation::Histogram { field, interval } => {
+                // the variant can be built without `histogram`
+                assert!(*interval > 0, "histogram interval must be positive");
+                let mut buckets: HashMap<i64, usize> = HashMap::new();
+                for (doc_values, docs) in segments {
+                    if let Some(Column::Numeric(values)) = doc_values.column(field) {
+                        for v in Self::values(values, docs) {
+                            // the lowest bucket may start below i64::MIN
+                            let bucket = v.saturating_sub(v.rem_euclid(*interval));
+                            *buckets.entry(bucket).or_insert(0) += 1;
+                        }
+                    }
+                }
+                let mut buckets: Vec<(i64, usize)> = buckets.into_iter().collect();
+                buckets.sort_unstable();
+                AggregationResult::Histogram(buckets)
+            }
+            Aggregation::Stats { field } => {
+                let mut stats = Stats::default();
+                for (doc_values, docs) in segments {
+                    if let Some(Column::Numeric(values)) = doc_values.column(field) {
+                        for v in Self::values(values, docs) {
+                            stats.count += 1;
+                            stats.sum += i128::from(*v);
+                            stats.min = Some(stats.min.map_or(*v, |m| m.min(*v)));
+                            stats.max = Some(stats.max.map_or(*v, |m| m.max(*v)));
+                        }
+                    }
+                }
+                if stats.count > 0 {
+                    stats.avg = Some(stats.sum as f64 / stats.count as f64);
+                }
+                AggregationResult::Stats(stats)
+            }
+        }
+    }
+
+    fn values<'a, V>(values: &'a [Option<V>], docs: &'a [usize]) -> impl Iterator<Item = &'a V> {
+        docs.iter()
+            .filter_map(move |doc_id| values.get(*doc_id).and_then(|v| v.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let mut dv = DocValues::new();
+        let categories = ["ships", "trees", "ships", "birds", "ships", "trees"];
+        let lengths = [3, 12, 7, 25, -4, 10];
+        for (i, (category, length)) in categories.iter().zip(lengths.iter()).enumerate() {
+            dv.set_keyword("category", i, category.to_string()).unwrap();
+            dv.set_numeric("length", i, *length).unwrap();
+        }
+        let docs = [0, 1, 2, 3, 4];
+        let segments = [(&dv, &docs[..3]), (&dv, &docs[3..])];
+
+        assert_eq!(
+            Aggregation::terms("category", 2).collect(&segments),
+            AggregationResult::Terms(vec![("ships".to_string(), 3), ("birds".to_string(), 1)])
+        );
+        assert_eq!(
+            Aggregation::histogram("length", 10).collect(&segments),
+            AggregationResult::Histogram(vec![(-10, 1), (0, 2), (10, 1), (20, 1)])
+        );
+        assert_eq!(
+            Aggregation::stats("length").collect(&segments),
+            AggregationResult::Stats(Stats {
+                count: 5,
+                min: Some(-4),
+                max: Some(25),
+                sum: 43,
+                avg: Some(8.6),
+            })
+        );
+        assert_eq!(
+            Aggregation::stats("category").collect(&segments),
+            AggregationResult::Stats(Stats::default())
+        );
+
+        let mut dv = DocValues::new();
+        dv.set_numeric("length", 0, i64::MAX).unwrap();
+        dv.set_numeric("length", 1, i64::MAX).unwrap();
+        let docs = [0, 1];
+        let stats = Aggregation::stats("length").collect(&[(&dv, &docs[..])]);
+        assert_eq!(
+            stats,
+            AggregationResult::Stats(Stats {
+                count: 2,
+                min: Some(i64::MAX),
+                max: Some(i64::MAX),
+                sum: 2 * i128::from(i64::MAX),
+                avg: Some(i64::MAX as f64),
+            })
+        );
+    }
+
+    #[test]
+    fn test_histogram_bounds() {
+        let mut dv = DocValues::new();
+        for (i, v) in [i64::MIN, i64::MIN + 1, i64::MIN + 2, i64::MAX]
+            .iter()
+            .enumerate()
+        {
+            dv.set_numeric("length", i, *v).unwrap();
+        }
+        let docs = [0, 1, 2, 3];
+        // i64::MIN and i64::MAX are 1 past a multiple of 3, the bucket of
+        // i64::MIN starts below it
+        assert_eq!(
+            Aggregation::histogram("length", 3).collect(&[(&dv, &docs[..])]),
+            AggregationResult::Histogram(vec![(i64::MIN, 2), (i64::MIN + 2, 1), (i64::MAX - 1, 1)])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "histogram interval must be positive")]
+    fn test_histogram_zero_interval() {
+        let histogram = Aggregation::Histogram {
+            field: "length".to_string(),
+            interval: 0,
+        };
+        histogram.collect(&[]);
+    }
+}
@@ -0,0 +1,29 @@
+//! Turns the text of documents into the terms they are indexed under
+
+/// Splits `phrase` on the characters that are not alphanumeric
+pub fn tokenizer(phrase: &str) -> Vec<String> {
+    phrase
+        .split_terminator(|c: char| !c.is_alphanumeric())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+pub fn lowercase_filter(tokens: Vec<String>) -> Vec<String> {
+    let mut res: Vec<String> = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        res.push(token.to_lowercase());
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_analysis() {
+        let tokens = lowercase_filter(tokenizer("The Boat red-ish."));
+        assert_eq!(tokens, vec!["the", "boat", "red", "ish"]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::alloc::{self, Layout};
+use std::mem;
+use std::ptr::NonNull;
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Bump allocator handing out memory from 4 KiB blocks, like LevelDB's
+/// `Arena`
+///
+/// Allocations are never freed on their own, all of them are released at
+/// once when the arena is dropped. Values placed in the arena are not
+/// dropped by it.
+pub struct Arena {
+    alloc_ptr: *mut u8,
+    alloc_bytes_remaining: usize,
+    blocks: Vec<(NonNull<u8>, Layout)>,
+    memory_usage: usize,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            alloc_ptr: std::ptr::null_mut(),
+            alloc_bytes_remaining: 0,
+            blocks: Vec::new(),
+            memory_usage: 0,
+        }
+    }
+
+    /// Returns uninitialized memory fitting `layout`, valid until the arena
+    /// is dropped
+    pub fn allocate(&mut self, layout: Layout) -> NonNull<u8> {
+        assert!(layout.size() > 0, "zero sized allocation");
+        let padding = (self.alloc_ptr as usize).wrapping_neg() & (layout.align() - 1);
+        if !self.alloc_ptr.is_null() && layout.size() + padding <= self.alloc_bytes_remaining {
+            let result = unsafe { self.alloc_ptr.add(padding) };
+            self.alloc_ptr = unsafe { result.add(layout.size()) };
+            self.alloc_bytes_remaining -= layout.size() + padding;
+            return unsafe { NonNull::new_unchecked(result) };
+        }
+        if layout.size() > BLOCK_SIZE / 4 {
+            // large objects get their own block so as not to waste the
+            // remaining of the current block
+            return self.allocate_new_block(layout.size(), layout.align());
+        }
+        let block = self.allocate_new_block(BLOCK_SIZE, layout.align());
+        unsafe {
+            self.alloc_ptr = block.as_ptr().add(layout.size());
+        }
+        self.alloc_bytes_remaining = BLOCK_SIZE - layout.size();
+        block
+    }
+
+    /// Bytes allocated by the arena, blocks and bookkeeping included
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    fn allocate_new_block(&mut self, size: usize, align: usize) -> NonNull<u8> {
+        let layout = Layout::from_size_align(size, align.max(mem::align_of::<usize>()))
+            .expect("invalid block layout");
+        let block = match NonNull::new(unsafe { alloc::alloc(layout) }) {
+            Some(block) => block,
+            None => alloc::handle_alloc_error(layout),
+        };
+        self.blocks.push((block, layout));
+        self.memory_usage += size + mem::size_of::<(NonNull<u8>, Layout)>();
+        block
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (block, layout) in self.blocks.drain(..) {
+            unsafe { alloc::dealloc(block.as_ptr(), layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate() {
+        let mut arena = Arena::new();
+        assert_eq!(arena.memory_usage(), 0);
+        let mut allocated: Vec<(NonNull<u8>, usize)> = Vec::new();
+        let mut total = 0;
+        for i in 0..1000 {
+            let size = match i % 3 {
+                0 => 1 + i % 7,
+                1 => 8 * (1 + i % 10),
+                _ => 2000 + i,
+            };
+            let align = 1 << (i % 5);
+            let ptr = arena.allocate(Layout::from_size_align(size, align).unwrap());
+            assert_eq!(ptr.as_ptr() as usize % align, 0);
+            // write a pattern to check allocations don't overlap
+            unsafe { std::ptr::write_bytes(ptr.as_ptr(), (i % 256) as u8, size) };
+            allocated.push((ptr, size));
+            total += size;
+            assert!(arena.memory_usage() >= total);
+        }
+        for (i, (ptr, size)) in allocated.iter().enumerate() {
+            let bytes = unsafe { std::slice::from_raw_parts(ptr.as_ptr(), *size) };
+            assert!(bytes.iter().all(|b| *b == (i % 256) as u8));
+        }
+        assert!(arena.memory_usage() < total * 2);
+    }
+}
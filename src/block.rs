@@ -1,40 +1,493 @@
+use crate::coding;
+use crate::comparator::Comparator;
+use crate::crc;
+use crate::cursor::Cursor;
 use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
 
-/// `Block` is the smallest unit of data
-/// it contains a meta data about the block, the key & the value
+/// Default number of entries between restart points
+pub const RESTART_INTERVAL: usize = 16;
+
+/// compression type (1 byte), crc (4 bytes)
+pub const TRAILER_SIZE: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    None = 0,
+    Snappy = 1,
+}
+
+impl CompressionType {
+    fn from_u8(b: u8) -> Option<CompressionType> {
+        match b {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Snappy),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a block of entries added in increasing key order
 ///
-/// Layout :
+/// Keys are prefix compressed: an entry only stores the part of its key
+/// that differs from the previous key, except at restart points where the
+/// key is stored in full. Layout:
 ///
 /// ```text
-/// +------------+----------------+--------------+---------------+-------------+
-/// | CRC () | deleted (bool) | version(u32) | key (Vec<u8>) | value (u32) |
-/// +------------+----------------+--------------+---------------+-------------+
+/// +---------+-----+---------+----------------------+--------------------+
+/// | entry 1 | ... | entry n | restarts (u32 * num) | num_restarts (u32) |
+/// +---------+-----+---------+----------------------+--------------------+
+///
+/// entry := shared (varint) | non_shared (varint) | value_len (varint)
+///          | key[shared..] | value
 /// ```
-struct DataBlock {
-    pub id: u128,
-    pub deleted: bool,
-    pub key: Vec<u8>,
-    pub value: Option<Vec<u8>>,
+///
+/// Restarts are the offsets of the entries storing their key in full,
+/// lookups binary search them before scanning at most `restart_interval`
+/// entries. Fewer restarts make smaller blocks and slower lookups.
+pub struct BlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    /// Entries since the last restart point
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> BlockBuilder {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        BlockBuilder {
+            buffer: Vec::new(),
+            restarts: vec![0],
+            restart_interval,
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// `key` must be greater than every key added since the last reset
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut shared = 0;
+        if self.counter < self.restart_interval {
+            shared = self
+                .last_key
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count();
+        } else {
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        coding::put_varint(&mut self.buffer, shared as u64);
+        coding::put_varint(&mut self.buffer, (key.len() - shared) as u64);
+        coding::put_varint(&mut self.buffer, value.len() as u64);
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    /// Returns the contents of the block and resets the builder
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut contents = std::mem::take(&mut self.buffer);
+        for restart in self.restarts.iter() {
+            contents.extend_from_slice(&restart.to_le_bytes());
+        }
+        contents.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+        self.reset();
+        contents
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.restarts.clear();
+        self.restarts.push(0);
+        self.counter = 0;
+        self.last_key.clear();
+    }
+
+    /// Size of the block if finished now
+    pub fn current_size_estimate(&self) -> usize {
+        self.buffer.len() + 4 * self.restarts.len() + 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        Self::new(RESTART_INTERVAL)
+    }
+}
+
+/// Compresses `contents` if worth it and appends the trailer, returns the
+/// block as written to a file
+pub fn pack(contents: &[u8], compression: CompressionType) -> Vec<u8> {
+    let mut packed = match compression {
+        CompressionType::None => None,
+        CompressionType::Snappy => snap::raw::Encoder::new()
+            .compress_vec(contents)
+            .ok()
+            // keep the block uncompressed unless it saves at least 12.5%
+            .filter(|compressed| compressed.len() < contents.len() - contents.len() / 8),
+    }
+    .map(|compressed| (compressed, CompressionType::Snappy))
+    .unwrap_or_else(|| (contents.to_vec(), CompressionType::None));
+    packed.0.reserve(TRAILER_SIZE);
+    packed.0.push(packed.1 as u8);
+    let crc = crc::value(&packed.0);
+    packed.0.extend_from_slice(&crc::mask(crc).to_le_bytes());
+    packed.0
+}
+
+/// Reverse of `pack`
+pub fn unpack(mut packed: Vec<u8>, verify_checksums: bool) -> io::Result<Block> {
+    let corruption = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if packed.len() < TRAILER_SIZE {
+        return Err(corruption("truncated block"));
+    }
+    let size = packed.len() - TRAILER_SIZE;
+    if verify_checksums {
+        let expected = crc::unmask(u32::from_le_bytes(packed[size + 1..].try_into().unwrap()));
+        if crc::value(&packed[..size + 1]) != expected {
+            return Err(corruption("block checksum mismatch"));
+        }
+    }
+    match CompressionType::from_u8(packed[size]) {
+        Some(CompressionType::None) => {
+            packed.truncate(size);
+            Block::new(packed)
+        }
+        Some(CompressionType::Snappy) => {
+            let contents = snap::raw::Decoder::new()
+                .decompress_vec(&packed[..size])
+                .map_err(|_| corruption("corrupted compressed block"))?;
+            Block::new(contents)
+        }
+        None => Err(corruption("bad block compression type")),
+    }
+}
+
+/// Block built by a `BlockBuilder`, cheap to clone
+#[derive(Clone)]
+pub struct Block {
+    data: Arc<Vec<u8>>,
+    /// Offset of the restart array, where the entries end
+    restart_offset: usize,
+    num_restarts: usize,
 }
 
-impl Eq for Block {}
+impl Block {
+    pub fn new(data: Vec<u8>) -> io::Result<Block> {
+        let corruption = || io::Error::new(io::ErrorKind::InvalidData, "bad block contents");
+        if data.len() < 4 {
+            return Err(corruption());
+        }
+        let num_restarts = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap()) as usize;
+        let max_restarts = (data.len() - 4) / 4;
+        if num_restarts > max_restarts {
+            return Err(corruption());
+        }
+        Ok(Block {
+            restart_offset: data.len() - 4 - 4 * num_restarts,
+            num_restarts,
+            data: Arc::new(data),
+        })
+    }
 
-impl PartialEq for Block {
-    fn eq(&self, other: &Self) -> bool {
-        self.key == other.key
+    pub fn cursor(&self, comparator: Arc<dyn Comparator<[u8]>>) -> BlockCursor {
+        BlockCursor {
+            block: self.clone(),
+            comparator,
+            current: self.restart_offset,
+            next_offset: 0,
+            key: Vec::new(),
+            value: (0, 0),
+            err: None,
+        }
     }
 }
 
-impl Ord for Block {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.key.cmp(&other.key)
+pub struct BlockCursor {
+    block: Block,
+    comparator: Arc<dyn Comparator<[u8]>>,
+    /// Offset of the current entry, `restart_offset` if invalid
+    current: usize,
+    next_offset: usize,
+    key: Vec<u8>,
+    /// Range of the value of the current entry
+    value: (usize, usize),
+    err: Option<io::Error>,
+}
+
+impl BlockCursor {
+    fn restart_point(&self, index: usize) -> usize {
+        let offset = self.block.restart_offset + 4 * index;
+        u32::from_le_bytes(self.block.data[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    fn seek_to_restart_point(&mut self, index: usize) {
+        self.key.clear();
+        self.next_offset = self.restart_point(index);
+        self.current = self.block.restart_offset;
+    }
+
+    fn corrupted(&mut self) {
+        self.current = self.block.restart_offset;
+        self.err = Some(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad entry in block",
+        ));
+    }
+
+    // Moves to the entry at `next_offset`, keys are decoded relative to the
+    // current key
+    fn parse_next_entry(&mut self) {
+        let restart_offset = self.block.restart_offset;
+        self.current = self.next_offset;
+        if self.current >= restart_offset {
+            self.current = restart_offset;
+            return;
+        }
+        let mut input = &self.block.data[self.current..restart_offset];
+        let header = (|| {
+            let shared = coding::get_varint(&mut input)? as usize;
+            let non_shared = coding::get_varint(&mut input)? as usize;
+            let value_len = coding::get_varint(&mut input)? as usize;
+            Some((shared, non_shared, value_len))
+        })();
+        match header {
+            Some((shared, non_shared, value_len))
+                if shared <= self.key.len() && non_shared + value_len <= input.len() =>
+            {
+                let key_start = restart_offset - input.len();
+                self.key.truncate(shared);
+                self.key
+                    .extend_from_slice(&self.block.data[key_start..key_start + non_shared]);
+                self.value = (key_start + non_shared, key_start + non_shared + value_len);
+                self.next_offset = self.value.1;
+            }
+            _ => self.corrupted(),
+        }
     }
 }
 
-impl PartialOrd for Block {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl Cursor for BlockCursor {
+    fn valid(&self) -> bool {
+        self.current < self.block.restart_offset
+    }
+
+    fn seek_to_first(&mut self) {
+        self.key.clear();
+        self.next_offset = 0;
+        self.parse_next_entry();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        if self.block.num_restarts == 0 {
+            self.current = self.block.restart_offset;
+            return;
+        }
+        // binary search for the last restart point with a key less than
+        // `target`, keys at restart points are stored in full
+        let (mut left, mut right) = (0, self.block.num_restarts - 1);
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            self.seek_to_restart_point(mid);
+            self.parse_next_entry();
+            if !self.valid() {
+                // restart points are the offsets of entries
+                if self.err.is_none() {
+                    self.corrupted();
+                }
+                return;
+            }
+            if self.comparator.compare(&self.key, target) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+        self.seek_to_restart_point(left);
+        self.parse_next_entry();
+        while self.valid() && self.comparator.compare(&self.key, target) == Ordering::Less {
+            self.parse_next_entry();
+        }
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid(), "next on an invalid cursor");
+        self.parse_next_entry();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid(), "key of an invalid cursor");
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid(), "value of an invalid cursor");
+        &self.block.data[self.value.0..self.value.1]
+    }
+
+    fn status(&mut self) -> io::Result<()> {
+        self.err.take().map_or(Ok(()), Err)
     }
 }
 
-impl Default for Block {}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_block() {
+        let mut builder = BlockBuilder::default();
+        assert!(builder.is_empty());
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:04}", i * 2)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            builder.add(key.as_bytes(), i.to_string().as_bytes());
+        }
+        let estimate = builder.current_size_estimate();
+        let contents = builder.finish();
+        assert_eq!(contents.len(), estimate);
+        assert!(builder.is_empty());
+        // keys share their first 5 bytes, except at the 7 restart points
+        assert!(contents.len() < 100 * (3 + 3 + 2) + 7 * 5 + 8 * 4);
+
+        let block = Block::new(contents).unwrap();
+        let mut cursor = block.cursor(Arc::new(BytewiseComparator));
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(cursor.valid());
+            assert_eq!(cursor.key(), key.as_bytes());
+            assert_eq!(cursor.value(), i.to_string().as_bytes());
+            cursor.next();
+        }
+        assert!(!cursor.valid());
+        cursor.seek(b"key0101");
+        assert_eq!(cursor.key(), b"key0102");
+        cursor.seek(b"key0102");
+        assert_eq!(cursor.value(), b"51");
+        cursor.seek(b"");
+        assert_eq!(cursor.key(), b"key0000");
+        cursor.seek(b"key9");
+        assert!(!cursor.valid());
+        assert!(cursor.status().is_ok());
+
+        let empty = Block::new(BlockBuilder::default().finish()).unwrap();
+        let mut cursor = empty.cursor(Arc::new(BytewiseComparator));
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+    }
+
+    #[test]
+    fn test_corruption() {
+        assert!(Block::new(vec![1, 2]).is_err());
+        assert!(Block::new(vec![0, 0, 0, 0, 9, 0, 0, 0]).is_err());
+        let mut builder = BlockBuilder::default();
+        builder.add(b"a", b"1");
+        builder.add(b"b", b"2");
+        let mut contents = builder.finish();
+        // the second entry claims to share 5 bytes with the first key
+        contents[5] = 5;
+        let block = Block::new(contents).unwrap();
+        let mut cursor = block.cursor(Arc::new(BytewiseComparator));
+        cursor.seek_to_first();
+        cursor.next();
+        assert!(!cursor.valid());
+        assert_eq!(
+            cursor.status().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        let keys: Vec<String> = (0..500).map(|i| format!("k{}", i * 3)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        let mut sizes = Vec::new();
+        for interval in [1, 2, 16, 1000] {
+            let mut builder = BlockBuilder::new(interval);
+            for key in sorted.iter() {
+                builder.add(key.as_bytes(), key.as_bytes());
+            }
+            let block = Block::new(builder.finish()).unwrap();
+            sizes.push(block.data.len());
+            let mut cursor = block.cursor(Arc::new(BytewiseComparator));
+            for key in sorted.iter() {
+                cursor.seek(key.as_bytes());
+                assert_eq!(cursor.key(), key.as_bytes());
+                // targets between entries find the next entry
+                let mut target = key.as_bytes().to_vec();
+                target.push(0);
+                cursor.seek(&target);
+                let next = sorted.iter().find(|k| k.as_bytes() > key.as_bytes());
+                assert_eq!(cursor.valid(), next.is_some());
+                if let Some(next) = next {
+                    assert_eq!(cursor.key(), next.as_bytes());
+                }
+            }
+            cursor.seek(b"l");
+            assert!(!cursor.valid());
+            assert!(cursor.status().is_ok());
+        }
+        // fewer restarts make smaller blocks
+        assert!(sizes.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn test_pack() {
+        let mut builder = BlockBuilder::default();
+        for i in 0..100u32 {
+            builder.add(format!("key{:03}", i).as_bytes(), &[b'x'; 50]);
+        }
+        let contents = builder.finish();
+        let read = |packed: Vec<u8>| {
+            let block = unpack(packed, true).unwrap();
+            let mut cursor = block.cursor(Arc::new(BytewiseComparator));
+            cursor.seek(b"key042");
+            (block.data.len(), cursor.value().to_vec())
+        };
+
+        let packed = pack(&contents, CompressionType::None);
+        assert_eq!(packed.len(), contents.len() + TRAILER_SIZE);
+        assert_eq!(read(packed), (contents.len(), vec![b'x'; 50]));
+        let packed = pack(&contents, CompressionType::Snappy);
+        assert!(packed.len() < contents.len() / 2);
+        assert_eq!(
+            packed[packed.len() - TRAILER_SIZE],
+            CompressionType::Snappy as u8
+        );
+        assert_eq!(read(packed.clone()), (contents.len(), vec![b'x'; 50]));
+
+        // incompressible blocks are stored as is
+        let mut rng = StdRng::seed_from_u64(42);
+        let random: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
+        let packed = pack(&random, CompressionType::Snappy);
+        assert_eq!(packed[..random.len()], random[..]);
+
+        // flip a bit of a value
+        let mut corrupted = pack(&contents, CompressionType::None);
+        corrupted[20] ^= 1;
+        assert!(unpack(corrupted.clone(), true).is_err());
+        // checksums are only verified when asked to
+        assert!(unpack(corrupted, false).is_ok());
+        let mut bad_type = pack(&contents, CompressionType::None);
+        let type_offset = bad_type.len() - TRAILER_SIZE;
+        bad_type[type_offset] = 7;
+        assert!(unpack(bad_type, false).is_err());
+        assert!(unpack(vec![0; 3], false).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Varint and length prefixed encodings of LevelDB's on-disk formats
+//!
+//! Decoding functions advance `input` past what they decoded and return
+//! `None` on truncated or malformed input.
+
+pub fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub fn get_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut result = 0;
+    for (i, b) in input.iter().enumerate().take(10) {
+        result |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Some(result);
+        }
+    }
+    None
+}
+
+pub fn put_length_prefixed(buf: &mut Vec<u8>, data: &[u8]) {
+    put_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+pub fn get_length_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = get_varint(input)? as usize;
+    if input.len() < len {
+        return None;
+    }
+    let (data, rest) = input.split_at(len);
+    *input = rest;
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_coding() {
+        let values = [0, 1, 127, 128, 300, 1 << 21, u32::MAX as u64, u64::MAX];
+        let mut buf = Vec::new();
+        for v in values.iter() {
+            put_varint(&mut buf, *v);
+        }
+        put_length_prefixed(&mut buf, b"hello");
+        assert_eq!(buf[..4], [0, 1, 127, 0x80]);
+
+        let mut input = buf.as_slice();
+        for v in values.iter() {
+            assert_eq!(get_varint(&mut input), Some(*v));
+        }
+        assert_eq!(get_length_prefixed(&mut input), Some(&b"hello"[..]));
+        assert!(input.is_empty());
+
+        // truncated inputs are left untouched
+        let mut input = &[0x80, 0x80][..];
+        assert_eq!(get_varint(&mut input), None);
+        assert_eq!(input.len(), 2);
+        let mut input = &[5, b'a'][..];
+        assert_eq!(get_length_prefixed(&mut input), None);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::live_docs::LiveDocs;
+use crate::segment::Segment;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Lists the segments of a commit of an index directory
+///
+/// A commit is published by atomically renaming its commit point file to
+/// `segments_<generation>`, once every file it references is durably written.
+/// Files are never modified once written: live docs get a new file on every
+/// commit, so older commits stay loadable until they are deleted.
+#[derive(Serialize, Deserialize)]
+pub struct CommitPoint {
+    pub generation: u64,
+    pub key_field: String,
+    pub next_segment_id: u64,
+    pub segments: Vec<u64>,
+}
+
+const COMMIT_PREFIX: &str = "segments_";
+const TMP_SUFFIX: &str = ".tmp";
+
+impl CommitPoint {
+    pub fn file_name(generation: u64) -> String {
+        format!("{}{}", COMMIT_PREFIX, generation)
+    }
+
+    pub fn segment_file_name(id: u64) -> String {
+        format!("seg_{}.json", id)
+    }
+
+    pub fn live_docs_file_name(&self, id: u64) -> String {
+        format!("seg_{}_{}.liv", id, self.generation)
+    }
+
+    /// Generations of the commit points found in `dir`, newest first
+    pub fn generations(dir: &Path) -> io::Result<Vec<u64>> {
+        let mut generations: Vec<u64> = Vec::new();
+        for name in file_names(dir)? {
+            if let Some(IndexFile::Commit(generation)) = IndexFile::parse(&name) {
+                generations.push(generation);
+            }
+        }
+        generations.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(generations)
+    }
+
+    /// One past the largest segment id used by the files of `dir`
+    pub fn next_segment_id(dir: &Path) -> io::Result<u64> {
+        let mut next_id = 0;
+        for name in file_names(dir)? {
+            match IndexFile::parse(&name) {
+                Some(IndexFile::Segment(id)) | Some(IndexFile::LiveDocs(id, _)) => {
+                    next_id = next_id.max(id + 1)
+                }
+                _ => (),
+            }
+        }
+        Ok(next_id)
+    }
+
+    /// Reads commit `generation` and the segments it references, fails if
+    /// any of its files is missing or corrupted
+    pub fn load(
+        dir: &Path,
+        generation: u64,
+    ) -> io::Result<(CommitPoint, Vec<(Segment, LiveDocs)>)> {
+        let commit: CommitPoint = read_file(&dir.join(CommitPoint::file_name(generation)))?;
+        if commit.generation != generation {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "commit point {} has generation {}",
+                    generation, commit.generation
+                ),
+            ));
+        }
+        let mut segments = Vec::with_capacity(commit.segments.len());
+        for id in &commit.segments {
+            let segment: Segment = read_file(&dir.join(CommitPoint::segment_file_name(*id)))?;
+            let live_docs: LiveDocs = read_file(&dir.join(commit.live_docs_file_name(*id)))?;
+            if segment.id() != *id || live_docs.len() > segment.max_doc() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("segment {} doesn't match commit {}", id, generation),
+                ));
+            }
+            segments.push((segment, live_docs));
+        }
+        Ok((commit, segments))
+    }
+
+    /// Writes the files of this commit that don't exist yet, then publishes
+    /// the commit point
+    pub fn write(&self, dir: &Path, segments: &[(&Segment, &LiveDocs)]) -> io::Result<()> {
+        for (segment, live_docs) in segments {
+            let segment_path = dir.join(CommitPoint::segment_file_name(segment.id()));
+            if !segment_path.exists() {
+                write_file_atomic(&segment_path, *segment)?;
+            }
+            write_file_atomic(
+                &dir.join(self.live_docs_file_name(segment.id())),
+                *live_docs,
+            )?;
+        }
+        // the commit point must not be renamed before its files are on disk
+        sync_dir(dir)?;
+        write_file_atomic(&dir.join(CommitPoint::file_name(self.generation)), self)?;
+        sync_dir(dir)
+    }
+
+    /// Deletes the index files of `dir` that this commit doesn't reference
+    /// and that older commits wrote: older commits, merged away segments and
+    /// temporary files. Files of newer commits, which failed to load, are
+    /// left for inspection.
+    pub fn delete_unreferenced(&self, dir: &Path) -> io::Result<()> {
+        let mut referenced: HashSet<String> = HashSet::new();
+        referenced.insert(CommitPoint::file_name(self.generation));
+        for id in &self.segments {
+            referenced.insert(CommitPoint::segment_file_name(*id));
+            referenced.insert(self.live_docs_file_name(*id));
+        }
+        for name in file_names(dir)? {
+            let older = match IndexFile::parse(&name) {
+                Some(IndexFile::Commit(generation)) => generation < self.generation,
+                Some(IndexFile::Segment(id)) => id < self.next_segment_id,
+                Some(IndexFile::LiveDocs(id, generation)) => {
+                    id < self.next_segment_id && generation <= self.generation
+                }
+                None => false,
+            };
+            if older && !referenced.contains(&name) {
+                fs::remove_file(dir.join(&name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Index file, temporary or not, named after a commit generation or a
+/// segment id
+enum IndexFile {
+    Commit(u64),
+    Segment(u64),
+    LiveDocs(u64, u64),
+}
+
+impl IndexFile {
+    fn parse(name: &str) -> Option<IndexFile> {
+        let name = name.strip_suffix(TMP_SUFFIX).unwrap_or(name);
+        if let Some(generation) = name.strip_prefix(COMMIT_PREFIX) {
+            return generation.parse().ok().map(IndexFile::Commit);
+        }
+        let name = name.strip_prefix("seg_")?;
+        if let Some(id) = name.strip_suffix(".json") {
+            return id.parse().ok().map(IndexFile::Segment);
+        }
+        let (id, generation) = name.strip_suffix(".liv")?.split_once('_')?;
+        Some(IndexFile::LiveDocs(
+            id.parse().ok()?,
+            generation.parse().ok()?,
+        ))
+    }
+}
+
+fn file_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        if let Ok(name) = entry?.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Writes `value` next to `path` then renames it over `path`, so that `path`
+/// either holds its previous content or the whole new content after a crash.
+/// The parent directory must be synced for the rename itself to be durable.
+pub fn write_file_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(TMP_SUFFIX);
+    let tmp_path = PathBuf::from(tmp_name);
+    let mut bw = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut bw, value)?;
+    bw.flush()?;
+    bw.into_inner()?.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn read_file<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let br = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(br)?)
+}
+
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::document::Document;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_commit_point() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut segment = Segment::new(3);
+        let mut doc = Document::new();
+        doc.add_text("body", "red boat").unwrap();
+        segment.add_document(&doc, "id").unwrap();
+        let mut live_docs = LiveDocs::new();
+        live_docs.add(0);
+
+        for generation in 1..=2 {
+            let commit = CommitPoint {
+                generation,
+                key_field: "id".to_string(),
+                next_segment_id: 4,
+                segments: vec![3],
+            };
+            commit.write(&dir, &[(&segment, &live_docs)]).unwrap();
+        }
+        fs::write(dir.join("segments_3"), "{\"generation\":3,").unwrap();
+        fs::write(dir.join("seg_9.json.tmp"), "").unwrap();
+        fs::write(dir.join("seg_2.json.tmp"), "").unwrap();
+        assert_eq!(CommitPoint::generations(&dir).unwrap(), vec![3, 2, 1]);
+        assert!(CommitPoint::load(&dir, 3).is_err());
+
+        let (commit, segments) = CommitPoint::load(&dir, 2).unwrap();
+        assert_eq!(commit.segments, vec![3]);
+        assert_eq!(segments[0].0.max_doc(), 1);
+        commit.delete_unreferenced(&dir).unwrap();
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        // files of the broken newer commit are kept
+        assert_eq!(
+            files,
+            vec![
+                "seg_3.json",
+                "seg_3_2.liv",
+                "seg_9.json.tmp",
+                "segments_2",
+                "segments_3"
+            ]
+        );
+        assert_eq!(CommitPoint::next_segment_id(&dir).unwrap(), 10);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
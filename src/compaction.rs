@@ -0,0 +1,599 @@
+//! Compaction strategies, choosing which tables to merge
+//!
+//! `LeveledCompaction`, as in LevelDB, compacts level 0 once it holds
+//! `level0_compaction_trigger` tables and level `L > 0` once its size
+//! exceeds `max_bytes_for_level(L)`. Tables of the compacted level are
+//! picked round-robin over the key space and merged with the tables of the
+//! next level they overlap. Every byte is rewritten about
+//! `LEVEL_SIZE_MULTIPLIER` times per level, for little space overhead.
+//!
+//! `UniversalCompaction`, as RocksDB's, keeps every table in level 0 as a
+//! sorted run and merges runs of similar sizes together, which rewrites
+//! data far less often at the cost of more space and more tables to read.
+
+use crate::comparator::Comparator;
+use crate::db::Options;
+use crate::internal_key::{self, InternalKeyComparator, SequenceNumber};
+use crate::version::{FileMetaData, Version, NUM_LEVELS};
+use crate::version_edit::VersionEdit;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// Ratio between the size targets of consecutive levels
+pub const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+
+/// Tables of `level` merged with the overlapping tables of `level + 1` into
+/// new tables of `output_level`
+#[derive(Debug, Clone)]
+pub struct Compaction {
+    pub level: usize,
+    pub inputs: [Vec<FileMetaData>; 2],
+    pub output_level: usize,
+}
+
+/// Chooses the compactions of a `Db`, set by `Options::compaction_strategy`
+pub trait CompactionStrategy: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Compaction that `version` needs, if any. `compact_pointers` holds
+    /// the largest key compacted last in each level.
+    fn pick_compaction(
+        &self,
+        version: &Version,
+        options: &Options,
+        comparator: &InternalKeyComparator,
+        compact_pointers: &[Vec<u8>],
+    ) -> Option<Compaction>;
+
+    /// Compaction of the tables of `level` overlapping the user key range
+    /// `[begin, end]`, `None` if there are none. A compaction may cover
+    /// part of the range only, the next one is asked for the range after
+    /// its inputs.
+    fn compact_range(
+        &self,
+        version: &Version,
+        options: &Options,
+        comparator: &InternalKeyComparator,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Option<Compaction>;
+}
+
+/// Bytes written by flushes and compactions and space used by the tables,
+/// to compare compaction strategies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompactionStats {
+    pub num_flushes: u64,
+    pub num_compactions: u64,
+    /// Bytes of the tables written by memtable flushes
+    pub bytes_flushed: u64,
+    pub bytes_compacted_read: u64,
+    pub bytes_compacted_written: u64,
+    /// Size of the live tables
+    pub live_bytes: u64,
+    /// Size of the largest sorted run, which holds most of the live data
+    pub largest_run_bytes: u64,
+}
+
+impl CompactionStats {
+    /// Bytes written to tables per byte flushed
+    pub fn write_amplification(&self) -> f64 {
+        if self.bytes_flushed == 0 {
+            return 0.0;
+        }
+        (self.bytes_flushed + self.bytes_compacted_written) as f64 / self.bytes_flushed as f64
+    }
+
+    /// Bytes of tables per byte of the largest sorted run, estimates the
+    /// space used per byte of live data
+    pub fn space_amplification(&self) -> f64 {
+        if self.largest_run_bytes == 0 {
+            return 0.0;
+        }
+        self.live_bytes as f64 / self.largest_run_bytes as f64
+    }
+}
+
+impl Compaction {
+    /// Compaction of `inputs` of `level` into the next level
+    fn leveled(
+        version: &Version,
+        level: usize,
+        inputs: Vec<FileMetaData>,
+        comparator: &InternalKeyComparator,
+    ) -> Compaction {
+        let (smallest, largest) = key_range(&inputs, comparator);
+        let next_inputs = version.overlapping_inputs(
+            level + 1,
+            Some(internal_key::user_key(&smallest)),
+            Some(internal_key::user_key(&largest)),
+            comparator.user_comparator(),
+        );
+        Compaction {
+            level,
+            inputs: [inputs, next_inputs],
+            output_level: level + 1,
+        }
+    }
+
+    /// Compaction of sorted runs of level 0 into a single one
+    fn universal(runs: &[&FileMetaData]) -> Compaction {
+        let runs = runs.iter().map(|file| (*file).clone()).collect();
+        Compaction {
+            level: 0,
+            inputs: [runs, Vec::new()],
+            output_level: 0,
+        }
+    }
+
+    /// A single table without overlap in the next level is moved there
+    /// instead of being rewritten
+    pub fn is_trivial_move(&self) -> bool {
+        self.inputs[0].len() == 1 && self.inputs[1].is_empty() && self.output_level != self.level
+    }
+
+    /// Deletes the input tables in `edit`
+    pub fn add_input_deletions(&self, edit: &mut VersionEdit) {
+        for (i, inputs) in self.inputs.iter().enumerate() {
+            for file in inputs {
+                edit.delete_file(self.level + i, file.number);
+            }
+        }
+    }
+
+    pub fn input_size(&self) -> u64 {
+        self.inputs
+            .iter()
+            .flatten()
+            .map(|file| file.file_size)
+            .sum()
+    }
+
+    /// Whether no table left out of the compaction may hold an entry of
+    /// `user_key` older than `sequence`, in which case its deletion at
+    /// `sequence` can be dropped
+    pub fn is_base_level_for_key(
+        &self,
+        version: &Version,
+        user_key: &[u8],
+        sequence: SequenceNumber,
+        user_comparator: &dyn Comparator<[u8]>,
+    ) -> bool {
+        let in_deeper_level = (self.output_level + 1..NUM_LEVELS).any(|level| {
+            !version
+                .tables_for_key(level, user_key, user_comparator)
+                .is_empty()
+        });
+        if in_deeper_level {
+            return false;
+        }
+        if self.output_level > 0 {
+            return true;
+        }
+        // older sorted runs of level 0 left out of the compaction
+        version
+            .tables_for_key(0, user_key, user_comparator)
+            .iter()
+            .all(|file| {
+                file.smallest_sequence > sequence
+                    || self.inputs[0]
+                        .iter()
+                        .any(|input| input.number == file.number)
+            })
+    }
+}
+
+/// LevelDB's compaction, see the module documentation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LeveledCompaction;
+
+impl CompactionStrategy for LeveledCompaction {
+    fn name(&self) -> &str {
+        "leveled"
+    }
+
+    fn pick_compaction(
+        &self,
+        version: &Version,
+        options: &Options,
+        comparator: &InternalKeyComparator,
+        compact_pointers: &[Vec<u8>],
+    ) -> Option<Compaction> {
+        let (level, score) = compaction_score(version, options);
+        if score < 1.0 {
+            return None;
+        }
+        let files = &version.files[level];
+        let pointer = &compact_pointers[level];
+        let first = files
+            .iter()
+            .find(|file| {
+                pointer.is_empty()
+                    || comparator.compare(&file.largest, pointer) == Ordering::Greater
+            })
+            .unwrap_or(&files[0]);
+        let mut inputs = vec![first.clone()];
+        if level == 0 {
+            let (smallest, largest) = key_range(&inputs, comparator);
+            inputs = version.overlapping_inputs(
+                0,
+                Some(internal_key::user_key(&smallest)),
+                Some(internal_key::user_key(&largest)),
+                comparator.user_comparator(),
+            );
+        }
+        Some(Compaction::leveled(version, level, inputs, comparator))
+    }
+
+    /// Above level 0 the inputs are limited to about
+    /// `options.max_file_size` bytes
+    fn compact_range(
+        &self,
+        version: &Version,
+        options: &Options,
+        comparator: &InternalKeyComparator,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Option<Compaction> {
+        let mut inputs =
+            version.overlapping_inputs(level, begin, end, comparator.user_comparator());
+        if inputs.is_empty() {
+            return None;
+        }
+        if level > 0 {
+            let mut total = 0;
+            let count = inputs
+                .iter()
+                .position(|file| {
+                    total += file.file_size;
+                    total >= options.max_file_size
+                })
+                .map_or(inputs.len(), |i| i + 1);
+            inputs.truncate(count);
+        }
+        Some(Compaction::leveled(version, level, inputs, comparator))
+    }
+}
+
+/// Size-tiered compaction of the sorted runs of level 0, see the module
+/// documentation
+///
+/// Runs are only merged with runs of adjacent ages, so that the runs of
+/// level 0 keep disjoint sequence numbers. Once there are
+/// `level0_compaction_trigger` runs, all of them are merged if the space
+/// amplification is too high, else the most recent runs of similar sizes.
+#[derive(Debug, Clone, Copy)]
+pub struct UniversalCompaction {
+    /// Percentage by which a run may be larger than the runs merged before
+    /// it and still be merged with them
+    pub size_ratio: u64,
+    pub min_merge_width: usize,
+    pub max_merge_width: usize,
+    /// Percentage of the size of the oldest run past which the size of the
+    /// other runs triggers the merge of all runs
+    pub max_size_amplification_percent: u64,
+}
+
+impl Default for UniversalCompaction {
+    fn default() -> Self {
+        UniversalCompaction {
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: usize::MAX,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+impl UniversalCompaction {
+    /// All the runs if the others take too much space compared to the
+    /// oldest one, holding most of the data
+    fn pick_for_space_amplification(&self, runs: &[&FileMetaData]) -> Option<Range<usize>> {
+        let (oldest, newer) = runs.split_last()?;
+        let newer_size: u64 = newer.iter().map(|file| file.file_size).sum();
+        if newer_size * 100 >= oldest.file_size * self.max_size_amplification_percent {
+            return Some(0..runs.len());
+        }
+        None
+    }
+
+    /// Most recent runs of similar sizes, each run is merged if it's not
+    /// much larger than the runs before it together
+    fn pick_by_size_ratio(&self, runs: &[&FileMetaData]) -> Option<Range<usize>> {
+        for start in 0..runs.len() {
+            let mut size = runs[start].file_size;
+            let mut end = start + 1;
+            while end < runs.len()
+                && end - start < self.max_merge_width
+                && runs[end].file_size * 100 <= size * (100 + self.size_ratio)
+            {
+                size += runs[end].file_size;
+                end += 1;
+            }
+            if end - start >= self.min_merge_width {
+                return Some(start..end);
+            }
+        }
+        None
+    }
+}
+
+impl CompactionStrategy for UniversalCompaction {
+    fn name(&self) -> &str {
+        "universal"
+    }
+
+    fn pick_compaction(
+        &self,
+        version: &Version,
+        options: &Options,
+        _comparator: &InternalKeyComparator,
+        _compact_pointers: &[Vec<u8>],
+    ) -> Option<Compaction> {
+        let runs = version.level0_by_age();
+        let trigger = options.level0_compaction_trigger.max(2);
+        if runs.len() < trigger {
+            return None;
+        }
+        let picked = self
+            .pick_for_space_amplification(&runs)
+            .or_else(|| self.pick_by_size_ratio(&runs))
+            .unwrap_or_else(|| {
+                // runs of too different sizes, merges the most recent ones to
+                // bring their number back under the trigger
+                let count = (runs.len() - trigger + 1).max(self.min_merge_width.max(2));
+                0..count.min(runs.len())
+            });
+        Some(Compaction::universal(&runs[picked]))
+    }
+
+    /// Merges all the runs of level 0 if one of them overlaps the range
+    fn compact_range(
+        &self,
+        version: &Version,
+        _options: &Options,
+        comparator: &InternalKeyComparator,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Option<Compaction> {
+        let overlapping =
+            version.overlapping_inputs(level, begin, end, comparator.user_comparator());
+        if level > 0 || overlapping.is_empty() {
+            return None;
+        }
+        Some(Compaction::universal(&version.level0_by_age()))
+    }
+}
+
+/// Size target of `level > 0`, level 0 is limited by its number of tables
+pub fn max_bytes_for_level(options: &Options, level: usize) -> u64 {
+    let mut max_bytes = options.max_bytes_for_level_base;
+    for _ in 1..level {
+        max_bytes *= LEVEL_SIZE_MULTIPLIER;
+    }
+    max_bytes
+}
+
+/// Level needing a leveled compaction the most with its score, a level
+/// needs one once its score reaches 1
+pub fn compaction_score(version: &Version, options: &Options) -> (usize, f64) {
+    let mut best = (0, 0.0);
+    // the last level cannot be compacted to a lower one
+    for level in 0..NUM_LEVELS - 1 {
+        let score = if level == 0 {
+            version.num_files(0) as f64 / options.level0_compaction_trigger as f64
+        } else {
+            version.level_size(level) as f64 / max_bytes_for_level(options, level) as f64
+        };
+        if score > best.1 {
+            best = (level, score);
+        }
+    }
+    best
+}
+
+/// Smallest and largest internal keys of `files`
+pub fn key_range(files: &[FileMetaData], comparator: &InternalKeyComparator) -> (Vec<u8>, Vec<u8>) {
+    let mut smallest = &files[0].smallest;
+    let mut largest = &files[0].largest;
+    for file in &files[1..] {
+        if comparator.compare(&file.smallest, smallest) == Ordering::Less {
+            smallest = &file.smallest;
+        }
+        if comparator.compare(&file.largest, largest) == Ordering::Greater {
+            largest = &file.largest;
+        }
+    }
+    (smallest.clone(), largest.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::internal_key::ValueType;
+
+    fn file(number: u64, smallest: &str, largest: &str, file_size: u64) -> FileMetaData {
+        FileMetaData {
+            number,
+            file_size,
+            smallest: internal_key::encode(smallest.as_bytes(), number * 10, ValueType::Value),
+            largest: internal_key::encode(largest.as_bytes(), number * 10, ValueType::Value),
+            smallest_sequence: number * 10,
+            largest_sequence: number * 10,
+        }
+    }
+
+    fn numbers(files: &[FileMetaData]) -> Vec<u64> {
+        files.iter().map(|file| file.number).collect()
+    }
+
+    #[test]
+    fn test_leveled_compaction() {
+        let leveled = LeveledCompaction;
+        let pick_compaction = |version: &Version,
+                               options: &Options,
+                               comparator: &InternalKeyComparator,
+                               pointers: &[Vec<u8>]| {
+            leveled.pick_compaction(version, options, comparator, pointers)
+        };
+        let comparator = InternalKeyComparator::default();
+        let options = Options {
+            level0_compaction_trigger: 2,
+            max_bytes_for_level_base: 1000,
+            ..Options::default()
+        };
+        let mut version = Version::new();
+        version.files[0] = vec![file(10, "c", "e", 100)];
+        version.files[1] = vec![
+            file(1, "a", "b", 300),
+            file(2, "d", "f", 300),
+            file(3, "g", "h", 300),
+        ];
+        version.files[2] = vec![file(4, "a", "d", 4000), file(5, "e", "z", 4000)];
+        let pointers = vec![Vec::new(); NUM_LEVELS];
+        assert_eq!(compaction_score(&version, &options), (1, 0.9));
+        assert!(pick_compaction(&version, &options, &comparator, &pointers).is_none());
+
+        // level 0 pulls in the level 0 tables it overlaps, transitively
+        version.files[0].push(file(11, "a", "c", 100));
+        version.files[0].push(file(12, "x", "y", 100));
+        let compaction = pick_compaction(&version, &options, &comparator, &pointers).unwrap();
+        assert_eq!(compaction.level, 0);
+        assert_eq!(numbers(&compaction.inputs[0]), vec![10, 11]);
+        assert_eq!(numbers(&compaction.inputs[1]), vec![1, 2]);
+        assert!(!compaction.is_trivial_move());
+        assert_eq!(compaction.input_size(), 800);
+        let mut edit = VersionEdit::new();
+        compaction.add_input_deletions(&mut edit);
+        assert_eq!(edit.deleted_files, vec![(0, 10), (0, 11), (1, 1), (1, 2)]);
+
+        // level 1 tables are picked after the compact pointer
+        version.files[0].clear();
+        version.files[1].push(file(6, "i", "j", 300));
+        let mut pointers = pointers;
+        pointers[1] = version.files[1][1].largest.clone();
+        let compaction = pick_compaction(&version, &options, &comparator, &pointers).unwrap();
+        assert_eq!(compaction.level, 1);
+        assert_eq!(numbers(&compaction.inputs[0]), vec![3]);
+        assert_eq!(numbers(&compaction.inputs[1]), vec![5]);
+        pointers[1] = version.files[1][3].largest.clone();
+        let compaction = pick_compaction(&version, &options, &comparator, &pointers).unwrap();
+        assert_eq!(numbers(&compaction.inputs[0]), vec![1]);
+
+        let user_comparator = comparator.user_comparator();
+        let compaction = Compaction::leveled(&version, 0, vec![file(7, "g", "g", 1)], &comparator);
+        assert!(!compaction.is_base_level_for_key(&version, b"g", 100, user_comparator));
+        let compaction = Compaction::leveled(&version, 2, vec![file(5, "e", "z", 1)], &comparator);
+        assert!(compaction.is_trivial_move());
+        assert!(compaction.is_base_level_for_key(&version, b"g", 100, user_comparator));
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let comparator = InternalKeyComparator::default();
+        let options = Options {
+            max_file_size: 500,
+            ..Options::default()
+        };
+        let compact_range = |version: &Version,
+                             options: &Options,
+                             comparator: &InternalKeyComparator,
+                             level: usize,
+                             begin: Option<&[u8]>,
+                             end: Option<&[u8]>| {
+            LeveledCompaction.compact_range(version, options, comparator, level, begin, end)
+        };
+        let mut version = Version::new();
+        version.files[1] = vec![
+            file(1, "a", "b", 300),
+            file(2, "d", "f", 300),
+            file(3, "g", "h", 300),
+        ];
+        let compaction =
+            compact_range(&version, &options, &comparator, 1, Some(b"c"), None).unwrap();
+        assert_eq!(numbers(&compaction.inputs[0]), vec![2, 3]);
+        let compaction =
+            compact_range(&version, &options, &comparator, 1, None, Some(b"e")).unwrap();
+        assert_eq!(numbers(&compaction.inputs[0]), vec![1, 2]);
+        assert!(compact_range(&version, &options, &comparator, 1, Some(b"i"), None).is_none());
+        assert!(compact_range(&version, &options, &comparator, 2, None, None).is_none());
+        assert_eq!(max_bytes_for_level(&options, 3), 1000 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_universal_compaction() {
+        let comparator = InternalKeyComparator::default();
+        let options = Options {
+            level0_compaction_trigger: 4,
+            ..Options::default()
+        };
+        let universal = UniversalCompaction::default();
+        let pick = |version: &Version| -> Option<Vec<u64>> {
+            let compaction = universal.pick_compaction(version, &options, &comparator, &[])?;
+            assert_eq!((compaction.level, compaction.output_level), (0, 0));
+            assert!(compaction.inputs[1].is_empty());
+            Some(numbers(&compaction.inputs[0]))
+        };
+        // runs by age, most recent first
+        let mut version = Version::new();
+        version.files[0] = vec![
+            file(1, "a", "z", 10000),
+            file(2, "a", "z", 1000),
+            file(3, "a", "z", 100),
+        ];
+        assert_eq!(pick(&version), None);
+
+        // the two most recent runs have similar sizes
+        version.files[0].push(file(4, "a", "z", 100));
+        assert_eq!(pick(&version), Some(vec![4, 3]));
+        version.files[0].push(file(5, "a", "z", 150));
+        assert_eq!(pick(&version), Some(vec![5, 4, 3]));
+
+        // runs of different sizes, the most recent ones are merged
+        version.files[0] = vec![
+            file(1, "a", "z", 100_000),
+            file(2, "a", "z", 10000),
+            file(3, "a", "z", 1000),
+            file(4, "a", "z", 100),
+            file(5, "a", "z", 10),
+        ];
+        assert_eq!(pick(&version), Some(vec![5, 4]));
+
+        // the runs on top of the oldest one take twice its size
+        version.files[0] = vec![
+            file(1, "a", "z", 1000),
+            file(2, "a", "z", 1000),
+            file(3, "a", "z", 600),
+            file(4, "a", "z", 400),
+        ];
+        assert_eq!(pick(&version), Some(vec![4, 3, 2, 1]));
+
+        // deletions are kept while an older run may hold their key
+        let user_comparator = comparator.user_comparator();
+        let runs = version.level0_by_age();
+        let compaction = Compaction::universal(&runs[..2]);
+        assert!(!compaction.is_trivial_move());
+        assert!(!compaction.is_base_level_for_key(&version, b"b", 40, user_comparator));
+        let compaction = universal
+            .compact_range(&version, &options, &comparator, 0, Some(b"x"), None)
+            .unwrap();
+        assert_eq!(numbers(&compaction.inputs[0]), vec![4, 3, 2, 1]);
+        assert!(compaction.is_base_level_for_key(&version, b"b", 40, user_comparator));
+        assert!(universal
+            .compact_range(&version, &options, &comparator, 1, None, None)
+            .is_none());
+
+        let stats = CompactionStats {
+            bytes_flushed: 100,
+            bytes_compacted_written: 250,
+            live_bytes: 300,
+            largest_run_bytes: 200,
+            ..CompactionStats::default()
+        };
+        assert_eq!(stats.write_amplification(), 3.5);
+        assert_eq!(stats.space_amplification(), 1.5);
+    }
+}
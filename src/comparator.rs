@@ -0,0 +1,41 @@
+use std::cmp::Ordering;
+
+/// Total order over keys
+///
+/// The name of a comparator is persisted along with the data it sorted, so
+/// that a database can't be opened with a comparator ordering keys
+/// differently. Change the name whenever the order changes.
+pub trait Comparator<T: ?Sized>: Send + Sync {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+
+    fn name(&self) -> &str;
+}
+
+/// Orders keys by their `Ord` implementation, that is lexicographically
+/// by bytes for byte strings, like LevelDB's default comparator
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytewiseComparator;
+
+/// Reverse of `BytewiseComparator`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReverseBytewiseComparator;
+
+impl<T: Ord + ?Sized> Comparator<T> for BytewiseComparator {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &str {
+        "leveldb.BytewiseComparator"
+    }
+}
+
+impl<T: Ord + ?Sized> Comparator<T> for ReverseBytewiseComparator {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        b.cmp(a)
+    }
+
+    fn name(&self) -> &str {
+        "rocksdb.ReverseBytewiseComparator"
+    }
+}
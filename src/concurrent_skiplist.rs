@@ -0,0 +1,643 @@
+use crate::comparator::{BytewiseComparator, Comparator};
+use rand::Rng;
+use std::borrow::Borrow;
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU16, AtomicUsize, Ordering};
+
+/// Estimated bytes that the allocator adds to each allocation, for its
+/// header and rounding
+pub const ALLOCATION_OVERHEAD: usize = 2 * mem::size_of::<usize>();
+
+/// A SkipList that can be written to and read from many threads at once
+///
+/// Like RocksDB's `InlineSkipList`, elements can only be inserted: nodes are
+/// never unlinked while the list is shared, so readers never wait and never
+/// see freed memory. Inserts link nodes bottom-up with compare-and-swap,
+/// retrying on the level where another insert won the race. An element is
+/// visible to readers once linked on the bottom level.
+pub struct ConcurrentSkipList<T, C = BytewiseComparator> {
+    /// Next pointers of the head, which holds no element
+    head: Box<[AtomicPtr<Node<T>>]>,
+    k_max_height: u16,
+    max_height: AtomicU16,
+    branching_factor: u16,
+    inverse_branching: f64,
+    len: AtomicUsize,
+    /// See `approximate_memory_usage`
+    memory_usage: AtomicUsize,
+    comparator: C,
+    marker: PhantomData<Box<Node<T>>>,
+}
+
+struct Node<T> {
+    element: T,
+    next: Box<[AtomicPtr<Node<T>>]>,
+}
+
+pub struct Iter<'a, T> {
+    node: *mut Node<T>,
+    marker: PhantomData<&'a Node<T>>,
+}
+
+/// A position in a `ConcurrentSkipList`, see `skiplist::Cursor`
+///
+/// The cursor sees elements inserted concurrently if they are inserted
+/// ahead of it.
+pub struct Cursor<'a, T, C = BytewiseComparator> {
+    list: &'a ConcurrentSkipList<T, C>,
+    node: *mut Node<T>,
+}
+
+impl<T> Node<T> {
+    fn new(element: T, height: u16) -> Self {
+        Node {
+            element,
+            next: null_links(height),
+        }
+    }
+}
+
+/// Bytes allocated for a node of `height` links: the node and its tower
+fn node_size<T>(height: u16) -> usize {
+    mem::size_of::<Node<T>>()
+        + height as usize * mem::size_of::<AtomicPtr<Node<T>>>()
+        + 2 * ALLOCATION_OVERHEAD
+}
+
+fn null_links<T>(height: u16) -> Box<[AtomicPtr<Node<T>>]> {
+    (0..height)
+        .map(|_| AtomicPtr::new(ptr::null_mut()))
+        .collect()
+}
+
+// private methods
+impl<T, C> ConcurrentSkipList<T, C>
+where
+    C: Comparator<T>,
+{
+    fn random_height(&self) -> u16 {
+        let mut lvl = 1;
+        while lvl < self.k_max_height && rand::thread_rng().gen::<f64>() < self.inverse_branching {
+            lvl += 1;
+        }
+        lvl
+    }
+
+    fn compare<Q>(&self, element: &T, elt: &Q) -> cmp::Ordering
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.comparator.compare(element.borrow(), elt)
+    }
+
+    // next pointer of `node` on `level`, a null node stands for the head
+    fn link(&self, node: *mut Node<T>, level: usize) -> &AtomicPtr<Node<T>> {
+        if node.is_null() {
+            &self.head[level]
+        } else {
+            unsafe { &(*node).next[level] }
+        }
+    }
+
+    // Starting from `before` on `level`, returns the last node before `elt`
+    // and the node following it
+    fn find_splice_for_level<Q>(
+        &self,
+        elt: &Q,
+        mut before: *mut Node<T>,
+        level: usize,
+    ) -> (*mut Node<T>, *mut Node<T>)
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        loop {
+            let next = self.link(before, level).load(Ordering::Acquire);
+            if next.is_null()
+                || self.compare(unsafe { &(*next).element }, elt) != cmp::Ordering::Less
+            {
+                return (before, next);
+            }
+            before = next;
+        }
+    }
+
+    // last node before `elt`, null for the head
+    fn find_less_than_node<Q>(&self, elt: &Q) -> *mut Node<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut x = ptr::null_mut();
+        for level in (0..self.max_height.load(Ordering::Relaxed) as usize).rev() {
+            x = self.find_splice_for_level(elt, x, level).0;
+        }
+        x
+    }
+
+    fn find_greater_or_equal_node<Q>(&self, elt: &Q) -> *mut Node<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        // the successor found by the search, reloading the link of the last
+        // node before `elt` could return a node inserted before `elt` since
+        let mut x = ptr::null_mut();
+        let mut next = ptr::null_mut();
+        for level in (0..self.max_height.load(Ordering::Relaxed) as usize).rev() {
+            (x, next) = self.find_splice_for_level(elt, x, level);
+        }
+        next
+    }
+
+    fn last_node(&self) -> *mut Node<T> {
+        let mut x: *mut Node<T> = ptr::null_mut();
+        for level in (0..self.max_height.load(Ordering::Relaxed) as usize).rev() {
+            loop {
+                let next = self.link(x, level).load(Ordering::Acquire);
+                if next.is_null() {
+                    break;
+                }
+                x = next;
+            }
+        }
+        x
+    }
+}
+
+impl<T, C> Default for ConcurrentSkipList<T, C>
+where
+    C: Comparator<T> + Default,
+{
+    /// Creates an empty `ConcurrentSkipList<T>`
+    #[inline]
+    fn default() -> Self {
+        // supposed to be good up to 2^16 elements
+        Self::new(16, 4, C::default())
+    }
+}
+
+impl<T, C> ConcurrentSkipList<T, C>
+where
+    C: Comparator<T>,
+{
+    /// Panics unless `max_height` is at least 1 and `branching_factor` at
+    /// least 2, like `SkipList::new`
+    pub fn new(max_height: u16, branching_factor: u16, comparator: C) -> ConcurrentSkipList<T, C> {
+        assert!(max_height >= 1, "skip list max height must be at least 1");
+        assert!(
+            branching_factor >= 2,
+            "skip list branching factor must be at least 2"
+        );
+        ConcurrentSkipList {
+            head: null_links(max_height),
+            k_max_height: max_height,
+            max_height: AtomicU16::new(1),
+            branching_factor,
+            inverse_branching: 1.0 / branching_factor as f64,
+            len: AtomicUsize::new(0),
+            memory_usage: AtomicUsize::new(
+                max_height as usize * mem::size_of::<AtomicPtr<Node<T>>>() + ALLOCATION_OVERHEAD,
+            ),
+            comparator,
+            marker: PhantomData,
+        }
+    }
+
+    /// Number of elements, may lag behind inserts running concurrently
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn comparator(&self) -> &C {
+        &self.comparator
+    }
+
+    /// Approximate bytes allocated by the list for its head, nodes and
+    /// their towers of links. Heap memory owned by the elements is not
+    /// counted.
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Returns `false` and leaves the list untouched if an equal element is
+    /// already in the list
+    pub fn insert(&self, elt: T) -> bool {
+        let height = self.random_height();
+        let mut max_height = self.max_height.load(Ordering::Relaxed);
+        while height > max_height {
+            match self.max_height.compare_exchange_weak(
+                max_height,
+                height,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => max_height = height,
+                Err(current) => max_height = current,
+            }
+        }
+
+        let mut prev = vec![ptr::null_mut(); max_height as usize];
+        let mut next = vec![ptr::null_mut(); max_height as usize];
+        let mut x = ptr::null_mut();
+        for level in (0..max_height as usize).rev() {
+            let (before, after) = self.find_splice_for_level(&elt, x, level);
+            prev[level] = before;
+            next[level] = after;
+            x = before;
+        }
+        if !next[0].is_null() && self.compare(unsafe { &(*next[0]).element }, &elt).is_eq() {
+            return false;
+        }
+
+        let node = Box::into_raw(Box::new(Node::new(elt, height)));
+        let element = unsafe { &(*node).element };
+        for level in 0..height as usize {
+            loop {
+                unsafe { &(*node).next[level] }.store(next[level], Ordering::Relaxed);
+                // release publishes the node to readers that acquire the link
+                if self
+                    .link(prev[level], level)
+                    .compare_exchange(next[level], node, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+                // another insert changed the splice, nodes are never removed
+                // so the previous node is still before the element
+                let (before, after) = self.find_splice_for_level(element, prev[level], level);
+                if level == 0
+                    && !after.is_null()
+                    && self.compare(unsafe { &(*after).element }, element).is_eq()
+                {
+                    // not linked anywhere yet
+                    drop(unsafe { Box::from_raw(node) });
+                    return false;
+                }
+                prev[level] = before;
+                next[level] = after;
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.memory_usage
+            .fetch_add(node_size::<T>(height), Ordering::Relaxed);
+        true
+    }
+
+    pub fn contains<Q>(&self, elt: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.get(elt).is_some()
+    }
+
+    /// Returns the element equal to `elt`, if any
+    pub fn get<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find_greater_or_equal(elt)
+            .filter(|element| self.compare(element, elt).is_eq())
+    }
+
+    /// Returns the first element greater than or equal to `elt`
+    pub fn find_greater_or_equal<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let node = self.find_greater_or_equal_node(elt);
+        unsafe { node.as_ref() }.map(|node| &node.element)
+    }
+
+    /// Returns the last element less than `elt`
+    pub fn find_less_than<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let node = self.find_less_than_node(elt);
+        unsafe { node.as_ref() }.map(|node| &node.element)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        unsafe { self.last_node().as_ref() }.map(|node| &node.element)
+    }
+
+    /// Returns an invalid cursor, to be positioned with one of its seeks
+    pub fn cursor(&self) -> Cursor<'_, T, C> {
+        Cursor {
+            list: self,
+            node: ptr::null_mut(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            node: self.head[0].load(Ordering::Acquire),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Drop for ConcurrentSkipList<T, C> {
+    fn drop(&mut self) {
+        let mut x = *self.head[0].get_mut();
+        while !x.is_null() {
+            let mut node = unsafe { Box::from_raw(x) };
+            x = *node.next[0].get_mut();
+        }
+    }
+}
+
+// elements are shared between the threads reading the list, and moved into
+// it by the thread inserting them
+unsafe impl<T: Send, C: Send> Send for ConcurrentSkipList<T, C> {}
+unsafe impl<T: Send + Sync, C: Sync> Sync for ConcurrentSkipList<T, C> {}
+unsafe impl<T: Sync> Send for Iter<'_, T> {}
+unsafe impl<T: Sync> Sync for Iter<'_, T> {}
+unsafe impl<T: Send + Sync, C: Sync> Send for Cursor<'_, T, C> {}
+unsafe impl<T: Send + Sync, C: Sync> Sync for Cursor<'_, T, C> {}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = unsafe { self.node.as_ref() }?;
+        self.node = node.next[0].load(Ordering::Acquire);
+        Some(&node.element)
+    }
+}
+
+impl<'a, T, C> Cursor<'a, T, C>
+where
+    C: Comparator<T>,
+{
+    pub fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    /// Element the cursor is positioned on, `None` if invalid
+    pub fn key(&self) -> Option<&'a T> {
+        unsafe { self.node.as_ref() }.map(|node| &node.element)
+    }
+
+    pub fn next(&mut self) {
+        if let Some(node) = unsafe { self.node.as_ref() } {
+            self.node = node.next[0].load(Ordering::Acquire);
+        }
+    }
+
+    pub fn prev(&mut self) {
+        if let Some(node) = unsafe { self.node.as_ref() } {
+            self.node = self.list.find_less_than_node(&node.element);
+        }
+    }
+
+    /// Positions the cursor on the first element greater than or equal to
+    /// `target`
+    pub fn seek<Q>(&mut self, target: &Q)
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.node = self.list.find_greater_or_equal_node(target);
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.node = self.list.head[0].load(Ordering::Acquire);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.last_node();
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a ConcurrentSkipList<T, C>
+where
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, C> fmt::Debug for ConcurrentSkipList<T, C>
+where
+    T: fmt::Debug,
+    C: Comparator<T>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self).finish()
+    }
+}
+
+impl<T, C> Clone for ConcurrentSkipList<T, C>
+where
+    T: Clone,
+    C: Comparator<T> + Clone,
+{
+    fn clone(&self) -> Self {
+        let list = ConcurrentSkipList::new(
+            self.k_max_height,
+            self.branching_factor,
+            self.comparator.clone(),
+        );
+        for elt in self.iter() {
+            list.insert(elt.clone());
+        }
+        list
+    }
+}
+
+// these tests are meant to pass `cargo +nightly miri test concurrent_skiplist`
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comparator::ReverseBytewiseComparator;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    #[should_panic(expected = "skip list branching factor must be at least 2")]
+    fn test_branching_factor_one() {
+        let _: ConcurrentSkipList<u8> = ConcurrentSkipList::new(12, 1, BytewiseComparator);
+    }
+
+    #[test]
+    fn test_lookups() {
+        let sl: ConcurrentSkipList<u32> = ConcurrentSkipList::default();
+        assert_eq!(sl.last(), None);
+        let empty = sl.approximate_memory_usage();
+        for elt in [10, 30, 20, 50, 40] {
+            assert!(sl.insert(elt));
+        }
+        let usage = sl.approximate_memory_usage();
+        assert!(!sl.insert(30));
+        assert_eq!(sl.len(), 5);
+        assert_eq!(sl.approximate_memory_usage(), usage);
+        assert!(usage >= empty + 5 * node_size::<u32>(1));
+        assert!(usage <= empty + 5 * node_size::<u32>(sl.k_max_height));
+        assert!(sl.contains(&30));
+        assert!(!sl.contains(&35));
+        assert_eq!(sl.get(&40), Some(&40));
+        assert_eq!(sl.find_greater_or_equal(&31), Some(&40));
+        assert_eq!(sl.find_less_than(&30), Some(&20));
+        assert_eq!(sl.find_less_than(&10), None);
+        assert_eq!(sl.last(), Some(&50));
+        assert_eq!(format!("{:?}", sl.clone()), "[10, 20, 30, 40, 50]");
+
+        let mut cursor = sl.cursor();
+        cursor.seek(&25);
+        assert_eq!(cursor.key(), Some(&30));
+        cursor.prev();
+        assert_eq!(cursor.key(), Some(&20));
+        cursor.seek_to_last();
+        cursor.next();
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        cursor.prev();
+        assert!(!cursor.valid());
+
+        let sl = ConcurrentSkipList::new(12, 4, ReverseBytewiseComparator);
+        for elt in [10, 30, 20] {
+            assert!(sl.insert(elt));
+        }
+        assert!(!sl.insert(20));
+        assert_eq!(sl.find_greater_or_equal(&25), Some(&20));
+        assert_eq!(format!("{:?}", sl), "[30, 20, 10]");
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let (threads, per_thread) = if cfg!(miri) { (4, 50) } else { (8, 5_000) };
+        let sl: Arc<ConcurrentSkipList<u64>> =
+            Arc::new(ConcurrentSkipList::new(12, 4, BytewiseComparator));
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let sl = sl.clone();
+                thread::spawn(move || {
+                    let mut inserted = 0;
+                    for i in 0..per_thread {
+                        // half of the elements are inserted by two threads
+                        let elt = if i % 2 == 0 {
+                            i * threads + t
+                        } else {
+                            i * threads + t / 2
+                        };
+                        if sl.insert(elt) {
+                            inserted += 1;
+                        }
+                    }
+                    inserted
+                })
+            })
+            .collect();
+        let reader = {
+            let sl = sl.clone();
+            thread::spawn(move || {
+                while sl.len() < threads as usize * per_thread as usize / 2 {
+                    // readers always see a sorted list
+                    let elements: Vec<u64> = sl.iter().copied().collect();
+                    assert!(elements.windows(2).all(|w| w[0] < w[1]));
+                }
+            })
+        };
+        let inserted: usize = writers.into_iter().map(|w| w.join().unwrap()).sum();
+        reader.join().unwrap();
+
+        let mut expected = BTreeSet::new();
+        for t in 0..threads {
+            for i in 0..per_thread {
+                expected.insert(if i % 2 == 0 {
+                    i * threads + t
+                } else {
+                    i * threads + t / 2
+                });
+            }
+        }
+        assert_eq!(inserted, expected.len());
+        assert_eq!(sl.len(), expected.len());
+        assert!(sl.iter().eq(expected.iter()));
+        for elt in expected.iter().step_by(7) {
+            assert_eq!(sl.get(elt), Some(elt));
+        }
+    }
+
+    #[test]
+    fn test_find_greater_or_equal_while_inserting() {
+        let (threads, per_thread) = if cfg!(miri) { (2, 50) } else { (4, 50_000) };
+        let target = u64::MAX;
+        let sl: ConcurrentSkipList<u64> = ConcurrentSkipList::new(12, 4, BytewiseComparator);
+        sl.insert(target);
+        let done = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for t in 0..threads {
+                let (sl, done) = (&sl, &done);
+                scope.spawn(move || {
+                    // each insert lands right before the target, between the
+                    // node a concurrent search stops at and its successor
+                    for i in 0..per_thread {
+                        sl.insert(i * threads + t);
+                    }
+                    done.fetch_add(1, Ordering::Release);
+                });
+            }
+            for _ in 0..2 {
+                let (sl, done) = (&sl, &done);
+                scope.spawn(move || {
+                    while done.load(Ordering::Acquire) < threads as usize {
+                        assert_eq!(sl.find_greater_or_equal(&target), Some(&target));
+                    }
+                });
+            }
+        });
+        assert_eq!(sl.len(), (threads * per_thread) as usize + 1);
+    }
+
+    #[test]
+    fn test_drop() {
+        let tracker = Arc::new(());
+        let sl: ConcurrentSkipList<(u32, Arc<()>)> = ConcurrentSkipList::default();
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let (sl, tracker) = (&sl, &tracker);
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        sl.insert((i * 4 + t, tracker.clone()));
+                        // duplicates are dropped right away
+                        sl.insert((i * 4, tracker.clone()));
+                    }
+                });
+            }
+        });
+        assert_eq!(sl.len(), 80);
+        assert_eq!(Arc::strong_count(&tracker), 81);
+        drop(sl);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+    }
+}
@@ -0,0 +1,42 @@
+//! CRC32C checksums of the on-disk formats, masked like LevelDB's
+
+const MASK_DELTA: u32 = 0xa282_ead8;
+
+pub fn value(data: &[u8]) -> u32 {
+    crc32c::crc32c(data)
+}
+
+/// CRC of the concatenation of the data of `crc` and `data`
+pub fn extend(crc: u32, data: &[u8]) -> u32 {
+    crc32c::crc32c_append(crc, data)
+}
+
+/// Masks a CRC before storing it: computing the CRC of data holding
+/// unmasked CRCs gives poor results
+pub fn mask(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
+}
+
+pub fn unmask(masked: u32) -> u32 {
+    masked.wrapping_sub(MASK_DELTA).rotate_left(15)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc() {
+        // from RFC 3720 section B.4
+        assert_eq!(value(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(value(&[0xff; 32]), 0x62a8_ab43);
+        let ascending: Vec<u8> = (0..32).collect();
+        assert_eq!(value(&ascending), 0x46dd_794e);
+        assert_eq!(extend(value(b"hello "), b"world"), value(b"hello world"));
+        let crc = value(b"foo");
+        assert_ne!(mask(crc), crc);
+        assert_ne!(mask(mask(crc)), crc);
+        assert_eq!(unmask(mask(crc)), crc);
+        assert_eq!(unmask(unmask(mask(mask(crc)))), crc);
+    }
+}
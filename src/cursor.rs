@@ -0,0 +1,27 @@
+use std::io;
+
+/// Position in a sorted sequence of key-value pairs stored by the storage
+/// engine, like LevelDB's `Iterator`
+///
+/// A cursor is invalid until positioned by one of its seeks, and once moved
+/// past the last entry. `key` and `value` must only be called on a valid
+/// cursor. A cursor that fails to read its entries becomes invalid and
+/// reports the error through `status`.
+pub trait Cursor {
+    fn valid(&self) -> bool;
+
+    fn seek_to_first(&mut self);
+
+    /// Positions the cursor on the first entry with a key greater than or
+    /// equal to `target`
+    fn seek(&mut self, target: &[u8]);
+
+    fn next(&mut self);
+
+    fn key(&self) -> &[u8];
+
+    fn value(&self) -> &[u8];
+
+    /// Takes the first error met by the cursor
+    fn status(&mut self) -> io::Result<()>;
+}
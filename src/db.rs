@@ -1,13 +1,1776 @@
-mod memtable;
+use crate::block::CompressionType;
+use crate::commit;
+use crate::compaction::{self, Compaction, CompactionStats, CompactionStrategy, LeveledCompaction};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::cursor::Cursor;
+use crate::filename::{self, FileType};
+use crate::internal_key::{
+    self, InternalKeyComparator, SequenceNumber, ValueType, MAX_SEQUENCE_NUMBER,
+};
+use crate::memtable::{GetResult, MemTable, MAX_CAPACITY};
+use crate::merger::MergingCursor;
+use crate::table::{TableBuilder, TableOptions};
+use crate::table_cache::TableCache;
+use crate::version::{FileMetaData, Version, NUM_LEVELS};
+use crate::version_edit::VersionEdit;
+use crate::version_set::VersionSet;
+use crate::wal;
+use crate::write_batch::WriteBatch;
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use memtable::MemTable;
+#[derive(Clone)]
+pub struct Options {
+    pub create_if_missing: bool,
+    pub error_if_exists: bool,
+    /// Fail to open a database whose logs are corrupted instead of skipping
+    /// the corrupted records
+    pub paranoid_checks: bool,
+    /// Orders user keys, a database must always be opened with the same
+    /// comparator
+    pub comparator: Arc<dyn Comparator<[u8]>>,
+    /// Approximate size in bytes of the writes kept in a memtable before it
+    /// is flushed to a table
+    pub write_buffer_size: usize,
+    /// Full memtables waiting to be flushed past which writes stall
+    pub max_immutable_memtables: usize,
+    /// Approximate size in bytes of the data blocks of tables before
+    /// compression
+    pub block_size: usize,
+    /// Entries between restart points in data blocks
+    pub block_restart_interval: usize,
+    pub compression: CompressionType,
+    /// Chooses the tables to compact
+    pub compaction_strategy: Arc<dyn CompactionStrategy>,
+    /// Tables of level 0 past which level 0 is compacted, or its sorted runs
+    /// merged with `UniversalCompaction`
+    pub level0_compaction_trigger: usize,
+    /// Tables of level 0 past which every write is delayed by a
+    /// millisecond, to let compactions catch up
+    pub level0_slowdown_writes_trigger: usize,
+    /// Tables of level 0 past which writes stall until a compaction is done
+    pub level0_stop_writes_trigger: usize,
+    /// Size in bytes of level 1 past which it is compacted, the size target
+    /// of each next level is `compaction::LEVEL_SIZE_MULTIPLIER` times
+    /// larger
+    pub max_bytes_for_level_base: u64,
+    /// Size in bytes past which compactions start a new table
+    pub max_file_size: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let table_options = TableOptions::default();
+        Options {
+            create_if_missing: false,
+            error_if_exists: false,
+            paranoid_checks: false,
+            comparator: Arc::new(BytewiseComparator),
+            write_buffer_size: 4 * 1024 * 1024,
+            max_immutable_memtables: 2,
+            block_size: table_options.block_size,
+            block_restart_interval: table_options.block_restart_interval,
+            compression: table_options.compression,
+            compaction_strategy: Arc::new(LeveledCompaction),
+            level0_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 12,
+            max_bytes_for_level_base: 10 * 1024 * 1024,
+            max_file_size: 2 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Sync the log before acknowledging the write. Writes that are not
+    /// synced survive a crash of the process but may be lost if the
+    /// machine crashes.
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Read the state of the database as of the snapshot, the latest state
+    /// if `None`
+    pub snapshot: Option<Snapshot>,
+    /// Verify the checksums of all the data read from disk
+    pub verify_checksums: bool,
+}
+
+/// Consistent read-only view of a `Db`, see `Db::snapshot`
+///
+/// The snapshot is released once it and all its clones are dropped.
+#[derive(Debug, Clone)]
+pub struct Snapshot(Arc<SequenceNumber>);
+
+impl Snapshot {
+    pub fn sequence(&self) -> SequenceNumber {
+        *self.0
+    }
+}
+
+/// Full memtable waiting to be flushed
+#[derive(Clone)]
+struct ImmutableMemTable {
+    mem: Arc<MemTable>,
+    last_sequence: SequenceNumber,
+    /// Log of the memtable that replaced it
+    next_log_number: u64,
+}
 
+/// Compaction of a key range requested by `Db::compact_range`
+struct ManualCompaction {
+    level: usize,
+    /// User keys, `begin` moves forward as the range is compacted
+    begin: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    /// No round left to pick
+    done: bool,
+    /// A round was picked and is not installed yet
+    running: bool,
+}
+
+struct DbState {
+    mem: Arc<MemTable>,
+    /// Oldest first
+    imm: VecDeque<ImmutableMemTable>,
+    log: wal::Writer<File>,
+    log_number: u64,
+    versions: VersionSet,
+    /// Tables being written, not in a version yet
+    pending_outputs: HashSet<u64>,
+    manual_compaction: Option<ManualCompaction>,
+    stats: CompactionStats,
+    /// Set by failed background work or log writes, stops background work
+    /// and fails writes
+    background_error: Option<String>,
+}
+
+struct DbInner {
+    path: PathBuf,
+    options: Options,
+    comparator: InternalKeyComparator,
+    table_cache: TableCache,
+    /// Held by writers and while installing flushes and compactions
+    state: Mutex<DbState>,
+    /// Notified when background work is needed and when it is done
+    background_cv: Condvar,
+    /// Read by compactions to stop early
+    shutting_down: AtomicBool,
+    /// Whether `DbState::imm` holds a memtable, read by compactions to
+    /// flush it without waiting for them to finish
+    has_imm: AtomicBool,
+    /// Published once the entries up to it are in the memtable
+    last_sequence: AtomicU64,
+    snapshots: Mutex<Vec<Weak<SequenceNumber>>>,
+}
+
+/// Key-value store keeping its most recent writes in a `MemTable`, logged
+/// to a write-ahead log before being applied
+///
+/// A full memtable is replaced by an empty one and a new log, then flushed
+/// by a background thread to a table of level 0, after which its log is
+/// deleted. It stays readable until the table is installed, writes stall
+/// while `Options::max_immutable_memtables` memtables wait to be flushed.
+/// The same thread merges the tables of each level into the next one, see
+/// `compaction`, flushing the memtables that fill up meanwhile, and writes
+/// slow down then stall while level 0 holds too many tables. Each flush
+/// and compaction is logged to the manifest of the `VersionSet` before its
+/// tables are read.
+///
+/// Opening a database replays the logs that were not flushed, writing their
+/// entries to tables of level 0 whenever the memtable fills up and once
+/// they are all read, then starts a new log. The replayed logs, with their
+/// possibly torn tail, are deleted once the tables are in the manifest.
+///
+/// A `Db` can be shared between threads: writes are serialized, reads
+/// never wait for them.
 pub struct Db {
-    memtable: MemTable,
+    inner: Arc<DbInner>,
+    background_thread: Option<JoinHandle<()>>,
 }
 
 impl Db {
-    pub fn get(ro: ReadOptions, key: Vec<u8>) {}
-    pub fn put(wo: WriteOptions, key: Vec<u8>, value: Vec<u8>) {}
-    pub fn delete(wo: WriteOptions, key: Vec<u8>) {}
+    /// Opens the database in the directory `path`
+    pub fn open<P: AsRef<Path>>(path: P, options: Options) -> io::Result<Db> {
+        let path = path.as_ref().to_path_buf();
+        if options.create_if_missing {
+            fs::create_dir_all(&path)?;
+        }
+        let files = filename::numbered_files(&path)?;
+        let current_path = filename::current_file_name(&path);
+        let exists = current_path.exists()
+            || files
+                .iter()
+                .any(|(file_type, _)| *file_type == FileType::Log);
+        if !exists && !options.create_if_missing {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: database does not exist", path.display()),
+            ));
+        }
+        if exists && options.error_if_exists {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{}: database already exists", path.display()),
+            ));
+        }
+
+        let comparator = InternalKeyComparator::new(options.comparator.clone());
+        let mut versions = VersionSet::new(path.clone(), comparator.clone());
+        if current_path.exists() {
+            versions.recover()?;
+        }
+        for file in versions.current().files.iter().flatten() {
+            if !files.contains(&(FileType::Table, file.number)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: missing table {}", path.display(), file.number),
+                ));
+            }
+        }
+
+        let table_options = TableOptions {
+            comparator: Arc::new(comparator.clone()),
+            block_size: options.block_size,
+            block_restart_interval: options.block_restart_interval,
+            compression: options.compression,
+        };
+        for (_, number) in &files {
+            versions.mark_file_number_used(*number);
+        }
+        let capacity = options.write_buffer_size.min(MAX_CAPACITY);
+        let mut mem = MemTable::with_capacity(comparator.clone(), capacity);
+        let mut last_sequence = versions.last_sequence();
+        let min_log_number = versions.log_number();
+        let mut edit = VersionEdit::new();
+        let mut flush = |mem: &MemTable| {
+            let number = versions.new_file_number();
+            edit.add_file(0, write_memtable(&path, &table_options, number, mem)?);
+            Ok(())
+        };
+        for (file_type, number) in files {
+            if file_type == FileType::Log && number >= min_log_number {
+                let log_path = filename::log_file_name(&path, number);
+                let sequence =
+                    recover_log(&log_path, &mut mem, options.paranoid_checks, &mut flush)?;
+                last_sequence = last_sequence.max(sequence);
+            }
+        }
+        if !mem.is_empty() {
+            flush(&mem)?;
+            mem = MemTable::with_capacity(comparator.clone(), capacity);
+        }
+        let log_number = versions.new_file_number();
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(filename::log_file_name(&path, log_number))?;
+        commit::sync_dir(&path)?;
+        // starts a new manifest, the recovered logs are deleted once it
+        // holds their tables
+        edit.log_number = Some(log_number);
+        edit.last_sequence = Some(last_sequence);
+        versions.log_and_apply(edit)?;
+        let inner = Arc::new(DbInner {
+            table_cache: TableCache::new(path.clone(), table_options),
+            path,
+            options,
+            comparator,
+            state: Mutex::new(DbState {
+                mem: Arc::new(mem),
+                imm: VecDeque::new(),
+                log: wal::Writer::new(file),
+                log_number,
+                versions,
+                pending_outputs: HashSet::new(),
+                manual_compaction: None,
+                stats: CompactionStats::default(),
+                background_error: None,
+            }),
+            background_cv: Condvar::new(),
+            shutting_down: AtomicBool::new(false),
+            has_imm: AtomicBool::new(false),
+            last_sequence: AtomicU64::new(last_sequence),
+            snapshots: Mutex::new(Vec::new()),
+        });
+        inner.delete_obsolete_files(&inner.lock_state());
+        let background_thread = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("db-background".to_string())
+                .spawn(move || inner.background_work())?
+        };
+        Ok(Db {
+            inner,
+            background_thread: Some(background_thread),
+        })
+    }
+
+    /// Value of `key`, `None` if it is not set
+    pub fn get(&self, options: &ReadOptions, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.inner.get(options, key)
+    }
+
+    pub fn put(&self, options: &WriteOptions, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(options, batch)
+    }
+
+    /// Deleting a key that is not set is not an error
+    pub fn delete(&self, options: &WriteOptions, key: &[u8]) -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(options, batch)
+    }
+
+    /// Applies the entries of `batch` atomically: readers see all of them
+    /// or none, and they are logged as a single record so that a crash
+    /// cannot recover part of them
+    pub fn write(&self, options: &WriteOptions, batch: WriteBatch) -> io::Result<()> {
+        self.inner.write(options, batch)
+    }
+
+    /// Flushes the memtable to a table, returns once it and the memtables
+    /// waiting before it are flushed
+    pub fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Compacts the tables holding the user keys in `[begin, end]` down to
+    /// the deepest level they overlap, dropping overwritten entries and
+    /// deletions. `None` leaves the range unbounded on its side, so
+    /// `compact_range(None, None)` compacts the whole database.
+    pub fn compact_range(&self, begin: Option<&[u8]>, end: Option<&[u8]>) -> io::Result<()> {
+        self.flush()?;
+        let max_level = {
+            let version = self.inner.lock_state().versions.current().clone();
+            let comparator = self.inner.options.comparator.as_ref();
+            (1..NUM_LEVELS)
+                .rev()
+                .find(|level| {
+                    !version
+                        .overlapping_inputs(*level, begin, end, comparator)
+                        .is_empty()
+                })
+                .unwrap_or(1)
+        };
+        for level in 0..max_level {
+            self.inner.manual_compaction(level, begin, end)?;
+        }
+        Ok(())
+    }
+
+    /// Snapshot of the current state, to read with `ReadOptions::snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = &self.inner;
+        let mut snapshots = inner.snapshots.lock().expect("snapshots lock poisoned");
+        let snapshot = Snapshot(Arc::new(inner.last_sequence.load(Ordering::Acquire)));
+        snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+        snapshots.push(Arc::downgrade(&snapshot.0));
+        snapshot
+    }
+
+    /// Work done by flushes and compactions since the database was opened,
+    /// and space used by its tables
+    pub fn compaction_stats(&self) -> CompactionStats {
+        let (mut stats, version) = {
+            let state = self.inner.lock_state();
+            (state.stats, state.versions.current().clone())
+        };
+        let run_sizes = version.sorted_run_sizes();
+        stats.live_bytes = run_sizes.iter().sum();
+        stats.largest_run_bytes = run_sizes.into_iter().max().unwrap_or(0);
+        stats
+    }
+
+    /// Number of tables of each level
+    pub fn num_files_per_level(&self) -> [usize; NUM_LEVELS] {
+        let version = self.inner.lock_state().versions.current().clone();
+        let mut num_files = [0; NUM_LEVELS];
+        for (level, num) in num_files.iter_mut().enumerate() {
+            *num = version.num_files(level);
+        }
+        num_files
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.inner.options
+    }
+}
+
+impl Drop for Db {
+    /// Stops the background thread, interrupting its compaction. The
+    /// memtables that were not flushed are recovered from their logs on the
+    /// next open.
+    fn drop(&mut self) {
+        {
+            let _state = self.inner.lock_state();
+            self.inner.shutting_down.store(true, Ordering::Release);
+        }
+        self.inner.background_cv.notify_all();
+        if let Some(background_thread) = self.background_thread.take() {
+            if background_thread.join().is_err() {
+                log::warn!("{}: background thread panicked", self.inner.path.display());
+            }
+        }
+    }
+}
+
+impl DbInner {
+    fn lock_state(&self) -> MutexGuard<'_, DbState> {
+        self.state.lock().expect("db state lock poisoned")
+    }
+
+    fn get(&self, options: &ReadOptions, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let sequence = match &options.snapshot {
+            Some(snapshot) => snapshot.sequence(),
+            None => self.last_sequence.load(Ordering::Acquire),
+        };
+        let (mem, imm, version) = {
+            let state = self.lock_state();
+            let imm: Vec<_> = state.imm.iter().map(|imm| imm.mem.clone()).collect();
+            (state.mem.clone(), imm, state.versions.current().clone())
+        };
+        // newest first, the first entry found shadows the older ones
+        let memtables = std::iter::once(&mem).chain(imm.iter().rev());
+        for mem in memtables {
+            match mem.get(key, sequence) {
+                GetResult::Found(value) => return Ok(Some(value)),
+                GetResult::Deleted => return Ok(None),
+                GetResult::NotFound => {}
+            }
+        }
+        let lookup_key = internal_key::encode(key, sequence, internal_key::VALUE_TYPE_FOR_SEEK);
+        let user_comparator = self.comparator.user_comparator();
+        for level in 0..NUM_LEVELS {
+            for file in version.tables_for_key(level, key, user_comparator) {
+                let table = self.table_cache.get(file.number, file.file_size)?;
+                let (found_key, value) = match table.seek(&lookup_key, options.verify_checksums)? {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                let parsed = internal_key::parse(&found_key).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("table {}: malformed internal key", file.number),
+                    )
+                })?;
+                if user_comparator.compare(parsed.user_key, key) != cmp::Ordering::Equal {
+                    continue;
+                }
+                return Ok(match parsed.value_type {
+                    ValueType::Value => Some(value),
+                    ValueType::Deletion => None,
+                });
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(&self, options: &WriteOptions, mut batch: WriteBatch) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.make_room_for_write(self.lock_state())?;
+        batch.set_sequence(self.last_sequence.load(Ordering::Relaxed) + 1);
+        let logged = state.log.add_record(batch.contents()).and_then(|()| {
+            if options.sync {
+                state.log.get_ref().sync_data()?;
+            }
+            Ok(())
+        });
+        if let Err(err) = logged {
+            // the record may still reach the log, its sequence numbers must
+            // never be reused
+            log::error!("{}: log write error: {}", self.path.display(), err);
+            state.background_error = Some(err.to_string());
+            self.background_cv.notify_all();
+            return Err(err);
+        }
+        let last_sequence = batch
+            .insert_into(&state.mem)
+            .expect("sequence numbers reused");
+        self.last_sequence.store(last_sequence, Ordering::Release);
+        Ok(())
+    }
+
+    /// Switches to a new memtable if the current one is full, stalling
+    /// while too many memtables wait to be flushed or level 0 holds too
+    /// many tables
+    fn make_room_for_write<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DbState>,
+    ) -> io::Result<MutexGuard<'a, DbState>> {
+        let mut allow_delay = true;
+        loop {
+            background_error(&state)?;
+            let level0_files = state.versions.current().num_files(0);
+            if allow_delay && level0_files >= self.options.level0_slowdown_writes_trigger {
+                // delays every write a little rather than a few a lot
+                drop(state);
+                thread::sleep(Duration::from_millis(1));
+                allow_delay = false;
+                state = self.lock_state();
+                continue;
+            }
+            if !state.mem.is_full() {
+                return Ok(state);
+            }
+            if state.imm.len() >= self.options.max_immutable_memtables.max(1) {
+                log::warn!("{}: waiting for memtable flush", self.path.display());
+                state = self.wait(state);
+                continue;
+            }
+            if level0_files >= self.options.level0_stop_writes_trigger {
+                log::warn!("{}: too many level 0 tables, waiting", self.path.display());
+                state = self.wait(state);
+                continue;
+            }
+            self.switch_memtable(&mut state)?;
+        }
+    }
+
+    fn switch_memtable(&self, state: &mut DbState) -> io::Result<()> {
+        let log_number = state.versions.new_file_number();
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(filename::log_file_name(&self.path, log_number))?;
+        state.log = wal::Writer::new(file);
+        state.log_number = log_number;
+        let capacity = state.mem.capacity();
+        let mem = MemTable::with_capacity(self.comparator.clone(), capacity);
+        let full = std::mem::replace(&mut state.mem, Arc::new(mem));
+        state.imm.push_back(ImmutableMemTable {
+            mem: full,
+            last_sequence: self.last_sequence.load(Ordering::Relaxed),
+            next_log_number: log_number,
+        });
+        self.has_imm.store(true, Ordering::Release);
+        self.background_cv.notify_all();
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut state = self.lock_state();
+        if !state.mem.is_empty() {
+            while state.imm.len() >= self.options.max_immutable_memtables.max(1) {
+                background_error(&state)?;
+                state = self.wait(state);
+            }
+            self.switch_memtable(&mut state)?;
+        }
+        while !state.imm.is_empty() {
+            background_error(&state)?;
+            state = self.wait(state);
+        }
+        Ok(())
+    }
+
+    /// Has the background thread compact the tables of `level` in the user
+    /// key range `[begin, end]`, returns once done
+    fn manual_compaction(
+        &self,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let mut state = self.lock_state();
+        while state.manual_compaction.is_some() {
+            state = self.wait(state);
+        }
+        state.manual_compaction = Some(ManualCompaction {
+            level,
+            begin: begin.map(<[u8]>::to_vec),
+            end: end.map(<[u8]>::to_vec),
+            done: false,
+            running: false,
+        });
+        self.background_cv.notify_all();
+        let result = loop {
+            if let Err(err) = background_error(&state) {
+                break Err(err);
+            }
+            if self.shutting_down.load(Ordering::Acquire) {
+                break Err(io::Error::new(io::ErrorKind::Interrupted, "shutting down"));
+            }
+            if state
+                .manual_compaction
+                .as_ref()
+                .is_some_and(|manual| manual.done && !manual.running)
+            {
+                break Ok(());
+            }
+            state = self.wait(state);
+        };
+        state.manual_compaction = None;
+        self.background_cv.notify_all();
+        result
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, DbState>) -> MutexGuard<'a, DbState> {
+        self.background_cv
+            .wait(state)
+            .expect("db state lock poisoned")
+    }
+
+    /// Body of the background thread until the database is dropped: flushes
+    /// the immutable memtables oldest first, then runs the requested
+    /// compactions and those that the levels need
+    fn background_work(&self) {
+        let mut state = self.lock_state();
+        loop {
+            if self.shutting_down.load(Ordering::Acquire) {
+                return;
+            }
+            if state.background_error.is_some() {
+                state = self.wait(state);
+                continue;
+            }
+            let result = if let Some(imm) = state.imm.front().cloned() {
+                self.flush_memtable(state, imm)
+            } else if let Some(compaction) = self.pick_compaction(&mut state) {
+                self.compact(state, compaction).map(|mut state| {
+                    // while requested, the manual compaction is the only one picked
+                    if let Some(manual) = state.manual_compaction.as_mut() {
+                        manual.running = false;
+                    }
+                    state
+                })
+            } else {
+                state = self.wait(state);
+                continue;
+            };
+            state = match result {
+                Ok(state) => state,
+                Err(err) => {
+                    let mut state = self.lock_state();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        log::error!("{}: background error: {}", self.path.display(), err);
+                        state.background_error = Some(err.to_string());
+                    }
+                    state
+                }
+            };
+            self.background_cv.notify_all();
+        }
+    }
+
+    fn flush_memtable<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DbState>,
+        imm: ImmutableMemTable,
+    ) -> io::Result<MutexGuard<'a, DbState>> {
+        let number = self.new_file_number(&mut state);
+        drop(state);
+        let file = write_memtable(&self.path, self.table_cache.options(), number, &imm.mem)?;
+
+        let mut state = self.lock_state();
+        state.pending_outputs.remove(&number);
+        state.stats.num_flushes += 1;
+        state.stats.bytes_flushed += file.file_size;
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, file);
+        edit.log_number = Some(imm.next_log_number);
+        edit.last_sequence = Some(imm.last_sequence);
+        state.versions.log_and_apply(edit)?;
+        state.imm.pop_front();
+        self.has_imm.store(!state.imm.is_empty(), Ordering::Release);
+        self.delete_obsolete_files(&state);
+        Ok(state)
+    }
+
+    /// Manual compaction if one is requested, else the compaction needed by
+    /// the levels if any
+    fn pick_compaction(&self, state: &mut DbState) -> Option<Compaction> {
+        let options = &self.options;
+        let comparator = &self.comparator;
+        let strategy = options.compaction_strategy.as_ref();
+        if let Some(manual) = state
+            .manual_compaction
+            .as_mut()
+            .filter(|manual| !manual.done)
+        {
+            let compaction = strategy.compact_range(
+                state.versions.current(),
+                options,
+                comparator,
+                manual.level,
+                manual.begin.as_deref(),
+                manual.end.as_deref(),
+            );
+            match &compaction {
+                // the next round starts after the compacted tables
+                Some(compaction) => {
+                    let (_, largest) = compaction::key_range(&compaction.inputs[0], comparator);
+                    let largest = internal_key::user_key(&largest);
+                    let reached_end = manual.end.as_deref().is_some_and(|end| {
+                        comparator.user_comparator().compare(largest, end) != cmp::Ordering::Less
+                    });
+                    manual.done = compaction.level == 0 || reached_end;
+                    manual.begin = Some(largest.to_vec());
+                    manual.running = true;
+                }
+                None => manual.done = true,
+            }
+            if compaction.is_some() {
+                return compaction;
+            }
+            self.background_cv.notify_all();
+        }
+        let versions = &state.versions;
+        strategy.pick_compaction(
+            versions.current(),
+            options,
+            comparator,
+            versions.compact_pointers(),
+        )
+    }
+
+    fn compact<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DbState>,
+        compaction: Compaction,
+    ) -> io::Result<MutexGuard<'a, DbState>> {
+        let mut edit = VersionEdit::new();
+        let (_, largest) = compaction::key_range(&compaction.inputs[0], &self.comparator);
+        edit.compact_pointers.push((compaction.level, largest));
+        // a manual compaction must rewrite its tables to drop overwritten
+        // entries and deletions
+        let is_manual = state
+            .manual_compaction
+            .as_ref()
+            .is_some_and(|manual| manual.running);
+        let outputs = if !is_manual && compaction.is_trivial_move() {
+            compaction.inputs[0].clone()
+        } else {
+            let smallest_snapshot = self.oldest_snapshot();
+            let version = state.versions.current().clone();
+            drop(state);
+            let mut numbers = Vec::new();
+            let outputs =
+                self.run_compaction(&version, &compaction, smallest_snapshot, &mut numbers);
+            state = self.lock_state();
+            for number in &numbers {
+                state.pending_outputs.remove(number);
+            }
+            let outputs = outputs.inspect_err(|_| {
+                // the tables written before the failure are not installed
+                for number in numbers {
+                    let path = filename::table_file_name(&self.path, number);
+                    if let Err(err) = fs::remove_file(&path) {
+                        log::warn!("{}: cannot delete: {}", path.display(), err);
+                    }
+                }
+            })?;
+            state.stats.num_compactions += 1;
+            state.stats.bytes_compacted_read += compaction.input_size();
+            state.stats.bytes_compacted_written +=
+                outputs.iter().map(|file| file.file_size).sum::<u64>();
+            outputs
+        };
+        log::info!(
+            "{}: compacted {} tables of level {} and {} of level {} to {} tables",
+            self.path.display(),
+            compaction.inputs[0].len(),
+            compaction.level,
+            compaction.inputs[1].len(),
+            compaction.output_level,
+            outputs.len()
+        );
+        compaction.add_input_deletions(&mut edit);
+        for file in outputs {
+            edit.add_file(compaction.output_level, file);
+        }
+        state.versions.log_and_apply(edit)?;
+        self.delete_obsolete_files(&state);
+        Ok(state)
+    }
+
+    /// Merges the input tables, keeping the most recent entry of each key
+    /// and the entries that a snapshot may read. Deletions are dropped once
+    /// no older entry of their key may remain outside of the compaction.
+    /// The numbers of the output tables are pushed to `numbers` as they are
+    /// allocated, whether the compaction succeeds or not.
+    fn run_compaction(
+        &self,
+        version: &Version,
+        compaction: &Compaction,
+        smallest_snapshot: SequenceNumber,
+        numbers: &mut Vec<u64>,
+    ) -> io::Result<Vec<FileMetaData>> {
+        let tables = compaction
+            .inputs
+            .iter()
+            .flatten()
+            .map(|file| self.table_cache.get(file.number, file.file_size))
+            .collect::<io::Result<Vec<_>>>()?;
+        let children = tables
+            .iter()
+            .map(|table| Box::new(table.cursor(self.options.paranoid_checks)) as Box<dyn Cursor>)
+            .collect();
+        let mut input = MergingCursor::new(Arc::new(self.comparator.clone()), children);
+        let user_comparator = self.comparator.user_comparator();
+        let mut outputs = Vec::new();
+        let mut output: Option<TableOutput> = None;
+        let mut current_user_key: Option<Vec<u8>> = None;
+        let mut last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+        input.seek_to_first();
+        while input.valid() {
+            if self.shutting_down.load(Ordering::Acquire) {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "shutting down"));
+            }
+            // a full memtable is flushed first, writes may be waiting for it
+            if self.has_imm.load(Ordering::Acquire) {
+                let state = self.lock_state();
+                if let Some(imm) = state.imm.front().cloned() {
+                    drop(self.flush_memtable(state, imm)?);
+                    self.background_cv.notify_all();
+                }
+            }
+            let parsed = internal_key::parse(input.key()).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed internal key")
+            })?;
+            let is_new_user_key = current_user_key.as_deref().is_none_or(|user_key| {
+                user_comparator.compare(user_key, parsed.user_key) != cmp::Ordering::Equal
+            });
+            if is_new_user_key {
+                // the entries of a user key are never split between tables,
+                // and a sorted run of level 0 is a single table
+                if compaction.output_level > 0
+                    && output
+                        .as_ref()
+                        .is_some_and(|output| output.file_size() >= self.options.max_file_size)
+                {
+                    outputs.push(output.take().unwrap().finish()?);
+                }
+                current_user_key = Some(parsed.user_key.to_vec());
+                last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+            }
+            let drop_entry = if last_sequence_for_key <= smallest_snapshot {
+                // hidden by a newer entry of the key that all readers see
+                true
+            } else {
+                parsed.value_type == ValueType::Deletion
+                    && parsed.sequence <= smallest_snapshot
+                    && compaction.is_base_level_for_key(
+                        version,
+                        parsed.user_key,
+                        parsed.sequence,
+                        user_comparator,
+                    )
+            };
+            last_sequence_for_key = parsed.sequence;
+            if !drop_entry {
+                if output.is_none() {
+                    let number = self.new_file_number(&mut self.lock_state());
+                    numbers.push(number);
+                    let options = self.table_cache.options();
+                    output = Some(TableOutput::create(&self.path, options, number)?);
+                }
+                output.as_mut().unwrap().add(input.key(), input.value())?;
+            }
+            input.next();
+        }
+        input.status()?;
+        if let Some(output) = output {
+            outputs.push(output.finish()?);
+        }
+        Ok(outputs)
+    }
+
+    /// Number of a new table, kept until it is installed or the database is
+    /// reopened
+    fn new_file_number(&self, state: &mut DbState) -> u64 {
+        let number = state.versions.new_file_number();
+        state.pending_outputs.insert(number);
+        number
+    }
+
+    /// Sequence number of the oldest snapshot, the last sequence number if
+    /// there are none
+    fn oldest_snapshot(&self) -> SequenceNumber {
+        let mut snapshots = self.snapshots.lock().expect("snapshots lock poisoned");
+        snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+        let last_sequence = self.last_sequence.load(Ordering::Acquire);
+        snapshots
+            .iter()
+            .filter_map(|snapshot| snapshot.upgrade())
+            .map(|sequence| *sequence)
+            .fold(last_sequence, cmp::min)
+    }
+
+    /// Deletes the logs that were flushed, the tables that no version that
+    /// may be read holds, compacted or left over by a crash, and the
+    /// previous manifests
+    fn delete_obsolete_files(&self, state: &DbState) {
+        let files = match filename::numbered_files(&self.path) {
+            Ok(files) => files,
+            Err(err) => {
+                log::warn!("{}: cannot list files: {}", self.path.display(), err);
+                return;
+            }
+        };
+        let mut live = state.versions.live_files();
+        live.extend(&state.pending_outputs);
+        for (file_type, number) in files {
+            let path = match file_type {
+                FileType::Log if number < state.versions.log_number() => {
+                    filename::log_file_name(&self.path, number)
+                }
+                FileType::Table if !live.contains(&number) => {
+                    self.table_cache.evict(number);
+                    filename::table_file_name(&self.path, number)
+                }
+                FileType::Manifest if number < state.versions.manifest_number() => {
+                    filename::manifest_file_name(&self.path, number)
+                }
+                // written under the state lock, only left over by a crash
+                FileType::Temp => filename::temp_file_name(&self.path, number),
+                _ => continue,
+            };
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("{}: cannot delete: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Table being written by a flush or a compaction
+struct TableOutput {
+    number: u64,
+    builder: TableBuilder<BufWriter<File>>,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+    smallest_sequence: SequenceNumber,
+    largest_sequence: SequenceNumber,
+}
+
+impl TableOutput {
+    fn create(dir: &Path, options: &TableOptions, number: u64) -> io::Result<TableOutput> {
+        let file = File::create(filename::table_file_name(dir, number))?;
+        Ok(TableOutput {
+            number,
+            builder: TableBuilder::new(options.clone(), BufWriter::new(file)),
+            smallest: Vec::new(),
+            largest: Vec::new(),
+            smallest_sequence: MAX_SEQUENCE_NUMBER,
+            largest_sequence: 0,
+        })
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let parsed = internal_key::parse(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed internal key"))?;
+        self.smallest_sequence = self.smallest_sequence.min(parsed.sequence);
+        self.largest_sequence = self.largest_sequence.max(parsed.sequence);
+        if self.builder.num_entries() == 0 {
+            self.smallest = key.to_vec();
+        }
+        self.largest.clear();
+        self.largest.extend_from_slice(key);
+        self.builder.add(key, value)
+    }
+
+    fn file_size(&self) -> u64 {
+        self.builder.file_size()
+    }
+
+    fn finish(self) -> io::Result<FileMetaData> {
+        assert!(self.builder.num_entries() > 0, "empty table");
+        let (bw, file_size) = self.builder.finish()?;
+        bw.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        Ok(FileMetaData {
+            number: self.number,
+            file_size,
+            smallest: self.smallest,
+            largest: self.largest,
+            smallest_sequence: self.smallest_sequence,
+            largest_sequence: self.largest_sequence,
+        })
+    }
+}
+
+fn background_error(state: &DbState) -> io::Result<()> {
+    match &state.background_error {
+        Some(err) => Err(io::Error::other(format!("background error: {}", err))),
+        None => Ok(()),
+    }
+}
+
+/// Writes the entries of `mem` to table `number`
+fn write_memtable(
+    dir: &Path,
+    options: &TableOptions,
+    number: u64,
+    mem: &MemTable,
+) -> io::Result<FileMetaData> {
+    let mut output = TableOutput::create(dir, options, number)?;
+    for (key, value) in mem.iter() {
+        output.add(key, value)?;
+    }
+    output.finish()
+}
+
+/// Replays the log at `path` into `mem`, returns the last sequence number
+/// found. Whenever `mem` is full it is passed to `flush` and replaced by an
+/// empty one. Entries already in `mem` are skipped, with paranoid checks
+/// they are an error.
+fn recover_log(
+    path: &Path,
+    mem: &mut MemTable,
+    paranoid_checks: bool,
+    flush: &mut dyn FnMut(&MemTable) -> io::Result<()>,
+) -> io::Result<SequenceNumber> {
+    let mut reader = wal::Reader::new(BufReader::new(File::open(path)?));
+    let mut last_sequence = 0;
+    while let Some(record) = reader.read_record()? {
+        let batch = WriteBatch::from_contents(record)?;
+        if batch.is_empty() {
+            continue;
+        }
+        last_sequence = batch.sequence() + u64::from(batch.count()) - 1;
+        if let Err(err) = batch.insert_into(mem) {
+            if paranoid_checks {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("{}: {}", path.display(), err),
+                ));
+            }
+            log::warn!("{}: {}", path.display(), err);
+        }
+        if mem.is_full() {
+            flush(mem)?;
+            *mem = MemTable::with_capacity(mem.comparator().clone(), mem.capacity());
+        }
+    }
+    if reader.dropped_bytes() > 0 && paranoid_checks {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: corrupted log", path.display()),
+        ));
+    }
+    if reader.dropped_bytes() > 0 {
+        log::warn!(
+            "{}: dropped {} corrupted bytes",
+            path.display(),
+            reader.dropped_bytes()
+        );
+    }
+    Ok(last_sequence)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compaction::UniversalCompaction;
+    use crate::comparator::ReverseBytewiseComparator;
+    use std::env;
+    use std::thread;
+    use uuid::Uuid;
+
+    fn options() -> Options {
+        Options {
+            create_if_missing: true,
+            ..Options::default()
+        }
+    }
+
+    /// Keeps the flushed tables in level 0
+    fn without_compactions() -> Options {
+        Options {
+            level0_compaction_trigger: 1000,
+            level0_slowdown_writes_trigger: 1000,
+            level0_stop_writes_trigger: 1000,
+            ..options()
+        }
+    }
+
+    #[test]
+    fn test_recovery() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        {
+            let db = Db::open(&dir, options()).unwrap();
+            assert_eq!(db.get(&ro, b"a").unwrap(), None);
+            db.put(&wo, b"a", b"1").unwrap();
+            db.put(&wo, b"b", b"2").unwrap();
+            db.put(&wo, b"a", b"3").unwrap();
+            db.delete(&wo, b"b").unwrap();
+            db.put(&WriteOptions { sync: true }, b"big", &vec![7; 100_000])
+                .unwrap();
+            assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"3".to_vec()));
+            assert_eq!(db.get(&ro, b"b").unwrap(), None);
+        }
+        {
+            let db = Db::open(&dir, Options::default()).unwrap();
+            assert_eq!(db.snapshot().sequence(), 5);
+            assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"3".to_vec()));
+            assert_eq!(db.get(&ro, b"b").unwrap(), None);
+            assert_eq!(db.get(&ro, b"big").unwrap(), Some(vec![7; 100_000]));
+            db.put(&wo, b"b", b"4").unwrap();
+        }
+        let db = Db::open(&dir, Options::default()).unwrap();
+        // each open flushes the replayed log to a table, then starts a log
+        // and a manifest, the previous ones are deleted once replaced
+        assert_eq!(db.num_files_per_level()[0], 2);
+        assert_eq!(db.inner.lock_state().log_number, 7);
+        assert_eq!(db.inner.lock_state().versions.manifest_number(), 8);
+        let files: Vec<_> = filename::numbered_files(&dir)
+            .unwrap()
+            .into_iter()
+            .filter(|(file_type, _)| *file_type != FileType::Table)
+            .collect();
+        assert_eq!(files, vec![(FileType::Log, 7), (FileType::Manifest, 8)]);
+        assert_eq!(filename::read_current_file(&dir).unwrap(), 8);
+        assert_eq!(db.snapshot().sequence(), 6);
+        assert_eq!(db.get(&ro, b"b").unwrap(), Some(b"4".to_vec()));
+        drop(db);
+
+        let error_if_exists = Options {
+            error_if_exists: true,
+            ..options()
+        };
+        let err = Db::open(&dir, error_if_exists).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        fs::remove_dir_all(&dir).unwrap();
+        let err = Db::open(&dir, Options::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_recovery_flushes_full_memtables() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let value = |i: u32| format!("{}-{}", i, "x".repeat(100));
+        {
+            let db = Db::open(&dir, without_compactions()).unwrap();
+            for i in 0..2000u32 {
+                db.put(&wo, format!("key-{:05}", i).as_bytes(), value(i).as_bytes())
+                    .unwrap();
+            }
+            assert_eq!(db.num_files_per_level()[0], 0);
+        }
+        let small_buffer = Options {
+            write_buffer_size: 16 * 1024,
+            ..without_compactions()
+        };
+        let db = Db::open(&dir, small_buffer).unwrap();
+        assert!(db.num_files_per_level()[0] > 1);
+        {
+            let state = db.inner.lock_state();
+            assert!(state.mem.is_empty());
+            assert_eq!(state.versions.last_sequence(), 2000);
+            assert_eq!(state.versions.log_number(), state.log_number);
+        }
+        assert_eq!(db.snapshot().sequence(), 2000);
+        for i in 0..2000u32 {
+            let key = format!("key-{:05}", i);
+            assert_eq!(
+                db.get(&ro, key.as_bytes()).unwrap(),
+                Some(value(i).into_bytes())
+            );
+        }
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let log_path = {
+            let db = Db::open(&dir, options()).unwrap();
+            db.put(&wo, b"a", b"1").unwrap();
+            db.put(&wo, b"b", b"2").unwrap();
+            let number = db.inner.lock_state().log_number;
+            filename::log_file_name(db.path(), number)
+        };
+        // a crash in the middle of the last write
+        let len = fs::metadata(&log_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log_path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+        let db = Db::open(&dir, options()).unwrap();
+        assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&ro, b"b").unwrap(), None);
+        assert_eq!(db.snapshot().sequence(), 1);
+        db.put(&wo, b"c", b"3").unwrap();
+        drop(db);
+        let log_path = {
+            let db = Db::open(&dir, options()).unwrap();
+            assert_eq!(db.get(&ro, b"c").unwrap(), Some(b"3".to_vec()));
+            assert_eq!(db.get(&ro, b"b").unwrap(), None);
+            // the replayed log with the torn tail is flushed and deleted
+            assert!(!log_path.exists());
+            db.put(&wo, b"d", b"4").unwrap();
+            let number = db.inner.lock_state().log_number;
+            filename::log_file_name(db.path(), number)
+        };
+
+        // corrupted records are an error with paranoid checks
+        let mut log = fs::read(&log_path).unwrap();
+        log[wal::HEADER_SIZE] ^= 1;
+        fs::write(&log_path, log).unwrap();
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..options()
+        };
+        let err = Db::open(&dir, paranoid).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let db = Db::open(&dir, options()).unwrap();
+        assert_eq!(db.get(&ro, b"d").unwrap(), None);
+        assert_eq!(db.get(&ro, b"c").unwrap(), Some(b"3".to_vec()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_write_error() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let db = Db::open(&dir, options()).unwrap();
+        db.put(&wo, b"a", b"1").unwrap();
+        let log_path = filename::log_file_name(db.path(), db.inner.lock_state().log_number);
+        // a log that cannot be written to
+        db.inner.lock_state().log = wal::Writer::new(File::open(&log_path).unwrap());
+        assert!(db.put(&wo, b"b", b"2").is_err());
+        // the failed record may have reached the log, a retry must not reuse
+        // its sequence number
+        let err = db.put(&wo, b"b", b"2").unwrap_err();
+        assert!(err.to_string().contains("background error"), "{}", err);
+        drop(db);
+
+        // a record logged twice by a retried write before this was enforced
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        batch.set_sequence(2);
+        let len = fs::metadata(&log_path).unwrap().len();
+        let file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        let mut log = wal::Writer::with_offset(file, len);
+        log.add_record(batch.contents()).unwrap();
+        log.add_record(batch.contents()).unwrap();
+        drop(log);
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..options()
+        };
+        let err = Db::open(&dir, paranoid).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let db = Db::open(&dir, options()).unwrap();
+        assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&ro, b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.snapshot().sequence(), 2);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let wo = WriteOptions::default();
+        let reverse = Options {
+            comparator: Arc::new(ReverseBytewiseComparator),
+            ..options()
+        };
+        let db = Db::open(&dir, reverse).unwrap();
+        db.put(&wo, b"a", b"1").unwrap();
+        let snapshot = db.snapshot();
+        db.put(&wo, b"a", b"2").unwrap();
+        db.delete(&wo, b"a").unwrap();
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot.clone()),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.get(&at_snapshot, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&ReadOptions::default(), b"a").unwrap(), None);
+        drop(at_snapshot);
+        assert_eq!(db.inner.snapshots.lock().unwrap()[0].strong_count(), 1);
+        drop(snapshot);
+        db.snapshot();
+        assert_eq!(db.inner.snapshots.lock().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let db = Db::open(&dir, options()).unwrap();
+        thread::scope(|s| {
+            for t in 0..4u32 {
+                let db = &db;
+                s.spawn(move || {
+                    let ro = ReadOptions::default();
+                    for i in 0..100u32 {
+                        let key = format!("{}-{}", t, i);
+                        db.put(&WriteOptions::default(), key.as_bytes(), &i.to_le_bytes())
+                            .unwrap();
+                        let value = db.get(&ro, key.as_bytes()).unwrap();
+                        assert_eq!(value, Some(i.to_le_bytes().to_vec()));
+                    }
+                });
+            }
+        });
+        assert_eq!(db.snapshot().sequence(), 400);
+        drop(db);
+        let db = Db::open(&dir, Options::default()).unwrap();
+        let value = db.get(&ReadOptions::default(), b"3-99").unwrap();
+        assert_eq!(value, Some(99u32.to_le_bytes().to_vec()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let db = Db::open(&dir, options()).unwrap();
+        db.put(&wo, b"count", b"1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"postings", b"1,2");
+        batch.put(b"doc", b"text");
+        batch.delete(b"count");
+        batch.put(b"count", b"2");
+        db.write(&WriteOptions { sync: true }, batch).unwrap();
+        db.write(&wo, WriteBatch::new()).unwrap();
+        assert_eq!(db.snapshot().sequence(), 5);
+        assert_eq!(db.get(&ro, b"count").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(&ro, b"doc").unwrap(), Some(b"text".to_vec()));
+
+        // readers see the whole batch or nothing of it
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..200u32 {
+                    let mut batch = WriteBatch::new();
+                    batch.put(b"a", &i.to_le_bytes());
+                    batch.put(b"b", &i.to_le_bytes());
+                    db.write(&wo, batch).unwrap();
+                }
+            });
+            s.spawn(|| loop {
+                let ro = ReadOptions {
+                    snapshot: Some(db.snapshot()),
+                    ..ReadOptions::default()
+                };
+                let a = db.get(&ro, b"a").unwrap();
+                assert_eq!(a, db.get(&ro, b"b").unwrap());
+                if a == Some(199u32.to_le_bytes().to_vec()) {
+                    break;
+                }
+            });
+        });
+        drop(db);
+
+        let db = Db::open(&dir, options()).unwrap();
+        assert_eq!(db.snapshot().sequence(), 405);
+        assert_eq!(db.get(&ro, b"postings").unwrap(), Some(b"1,2".to_vec()));
+        assert_eq!(
+            db.get(&ro, b"b").unwrap(),
+            Some(199u32.to_le_bytes().to_vec())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let small_buffer = Options {
+            write_buffer_size: 16 * 1024,
+            ..without_compactions()
+        };
+        let db = Db::open(&dir, small_buffer.clone()).unwrap();
+        let value = |i: u32| format!("value-{}-{}", i, "x".repeat(100)).into_bytes();
+        for i in 0..1000u32 {
+            db.put(&wo, format!("key-{:04}", i).as_bytes(), &value(i))
+                .unwrap();
+        }
+        db.put(&wo, b"key-0007", b"new").unwrap();
+        db.delete(&wo, b"key-0008").unwrap();
+        db.flush().unwrap();
+        let num_tables = db.num_files_per_level()[0];
+        assert!(num_tables > 5, "{} tables", num_tables);
+        let snapshot = db.snapshot();
+        db.put(&wo, b"key-0009", b"new").unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.num_files_per_level()[0], num_tables + 1);
+        assert!(db.inner.lock_state().mem.is_empty());
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.get(&at_snapshot, b"key-0009").unwrap(), Some(value(9)));
+        drop(db);
+
+        // the flushed logs are deleted
+        let logs = filename::numbered_files(&dir)
+            .unwrap()
+            .into_iter()
+            .filter(|(file_type, _)| *file_type == FileType::Log)
+            .count();
+        assert_eq!(logs, 1);
+        let db = Db::open(&dir, small_buffer).unwrap();
+        assert_eq!(db.snapshot().sequence(), 1003);
+        assert_eq!(db.num_files_per_level()[0], num_tables + 1);
+        for i in (0..1000u32).filter(|i| ![7, 8, 9].contains(i)) {
+            let key = format!("key-{:04}", i);
+            assert_eq!(db.get(&ro, key.as_bytes()).unwrap(), Some(value(i)));
+        }
+        assert_eq!(db.get(&ro, b"key-0007").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(db.get(&ro, b"key-0008").unwrap(), None);
+        assert_eq!(db.get(&ro, b"key-0009").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(db.get(&ro, b"key-1000").unwrap(), None);
+        drop(db);
+
+        // a table left over by a crash during a flush is deleted on open
+        let leftover = filename::table_file_name(&dir, 1_000_000);
+        fs::write(&leftover, b"partial").unwrap();
+        Db::open(&dir, options()).unwrap();
+        assert!(!leftover.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_stall() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let wo = WriteOptions::default();
+        let stalling = Options {
+            write_buffer_size: 4 * 1024,
+            max_immutable_memtables: 1,
+            ..without_compactions()
+        };
+        let db = Db::open(&dir, stalling).unwrap();
+        db.put(&wo, &0u32.to_be_bytes(), &[1; 100]).unwrap();
+        thread::scope(|s| {
+            let writer = s.spawn(|| {
+                for i in 1..2000u32 {
+                    db.put(&wo, &i.to_be_bytes(), &[1; 100]).unwrap();
+                    assert!(db.inner.lock_state().imm.len() <= 1);
+                }
+            });
+            while !writer.is_finished() {
+                let value = db.get(&ReadOptions::default(), &0u32.to_be_bytes());
+                assert_eq!(value.unwrap(), Some(vec![1; 100]));
+            }
+        });
+        db.flush().unwrap();
+        assert!(db.num_files_per_level()[0] > 10);
+        for i in 0..2000u32 {
+            let value = db.get(&ReadOptions::default(), &i.to_be_bytes());
+            assert_eq!(value.unwrap(), Some(vec![1; 100]));
+        }
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let small_levels = Options {
+            write_buffer_size: 8 * 1024,
+            level0_compaction_trigger: 2,
+            max_bytes_for_level_base: 32 * 1024,
+            max_file_size: 8 * 1024,
+            compression: CompressionType::None,
+            ..options()
+        };
+        let db = Db::open(&dir, small_levels.clone()).unwrap();
+        let value = |i: u32, round: u32| format!("{}-{}-{}", i, round, "x".repeat(50));
+        // every key is overwritten, then one key out of three deleted
+        for round in 0..3 {
+            for i in 0..2000u32 {
+                let key = format!("key-{:05}", (i * 7919) % 2000);
+                let i = (i * 7919) % 2000;
+                db.put(&wo, key.as_bytes(), value(i, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        let snapshot = db.snapshot();
+        for i in (0..2000u32).step_by(3) {
+            db.delete(&wo, format!("key-{:05}", i).as_bytes()).unwrap();
+        }
+        db.flush().unwrap();
+        let num_files = db.num_files_per_level();
+        assert!(num_files[1..].iter().sum::<usize>() > 0, "{:?}", num_files);
+
+        let check = |db: &Db| {
+            for i in 0..2000u32 {
+                let key = format!("key-{:05}", i);
+                let expected = if i % 3 == 0 {
+                    None
+                } else {
+                    Some(value(i, 2).into_bytes())
+                };
+                assert_eq!(db.get(&ro, key.as_bytes()).unwrap(), expected);
+            }
+        };
+        check(&db);
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot.clone()),
+            ..ReadOptions::default()
+        };
+        db.compact_range(None, None).unwrap();
+        let value_3 = db.get(&at_snapshot, b"key-00003").unwrap();
+        assert_eq!(value_3, Some(value(3, 2).into_bytes()));
+        check(&db);
+
+        drop(at_snapshot);
+        drop(snapshot);
+        let num_files = db.num_files_per_level();
+        drop(db);
+
+        // only the tables of the last version are left
+        let tables = filename::numbered_files(&dir)
+            .unwrap()
+            .into_iter()
+            .filter(|(file_type, _)| *file_type == FileType::Table)
+            .count();
+        assert_eq!(tables, num_files.iter().sum::<usize>());
+        let db = Db::open(&dir, small_levels).unwrap();
+        assert_eq!(db.num_files_per_level(), num_files);
+        check(&db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_range_rewrites_tables() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let db = Db::open(&dir, options()).unwrap();
+        db.put(&wo, b"a", b"1").unwrap();
+        db.put(&wo, b"a", b"2").unwrap();
+        db.put(&wo, b"b", b"3").unwrap();
+        db.delete(&wo, b"b").unwrap();
+        db.flush().unwrap();
+        let file = db.inner.lock_state().versions.current().files[0][0].clone();
+        // the single table of level 0 is not just moved to level 1
+        db.compact_range(None, None).unwrap();
+        assert_eq!(db.num_files_per_level()[..2], [0, 1]);
+        let version = db.inner.lock_state().versions.current().clone();
+        let compacted = &version.files[1][0];
+        assert_ne!(compacted.number, file.number);
+        let table = db
+            .inner
+            .table_cache
+            .get(compacted.number, compacted.file_size)
+            .unwrap();
+        let mut cursor = table.cursor(true);
+        cursor.seek_to_first();
+        assert_eq!(internal_key::user_key(cursor.key()), b"a");
+        assert_eq!(cursor.value(), b"2");
+        cursor.next();
+        assert!(!cursor.valid());
+        assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(&ro, b"b").unwrap(), None);
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_compaction() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..without_compactions()
+        };
+        let db = Db::open(&dir, paranoid).unwrap();
+        for key in [&b"a"[..], b"b"].iter() {
+            db.put(&wo, key, b"1").unwrap();
+            db.flush().unwrap();
+        }
+        let inputs: Vec<u64> = db.inner.lock_state().versions.current().files[0]
+            .iter()
+            .map(|file| file.number)
+            .collect();
+        // the data block of the table of b is corrupted, the compaction
+        // fails after writing a
+        let path = filename::table_file_name(&dir, inputs[1]);
+        let mut contents = fs::read(&path).unwrap();
+        contents[0] ^= 0xff;
+        fs::write(&path, contents).unwrap();
+        assert!(db.compact_range(None, None).is_err());
+        assert!(db.inner.lock_state().pending_outputs.is_empty());
+        let tables: Vec<u64> = filename::numbered_files(&dir)
+            .unwrap()
+            .into_iter()
+            .filter(|(file_type, _)| *file_type == FileType::Table)
+            .map(|(_, number)| number)
+            .collect();
+        assert_eq!(tables.len(), 2);
+        assert!(tables.iter().all(|number| inputs.contains(number)));
+        assert_eq!(db.get(&ro, b"a").unwrap(), Some(b"1".to_vec()));
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_during_compaction() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let db = Db::open(&dir, without_compactions()).unwrap();
+        for key in [&b"a"[..], b"b"].iter() {
+            db.put(&wo, key, b"1").unwrap();
+            db.flush().unwrap();
+        }
+        db.put(&wo, b"c", b"2").unwrap();
+        let version = {
+            let mut state = db.inner.lock_state();
+            // the background thread leaves the memtable to the compaction
+            state.background_error = Some("paused".to_string());
+            db.inner.switch_memtable(&mut state).unwrap();
+            state.versions.current().clone()
+        };
+        let inner = &db.inner;
+        let compaction = inner
+            .options
+            .compaction_strategy
+            .compact_range(&version, &inner.options, &inner.comparator, 0, None, None)
+            .unwrap();
+        let outputs = inner
+            .run_compaction(
+                &version,
+                &compaction,
+                inner.oldest_snapshot(),
+                &mut Vec::new(),
+            )
+            .unwrap();
+        assert_eq!(outputs.len(), 1);
+        let mut state = inner.lock_state();
+        assert!(state.imm.is_empty());
+        assert!(!inner.has_imm.load(Ordering::Acquire));
+        assert_eq!(state.versions.current().num_files(0), 3);
+        state.background_error = None;
+        drop(state);
+        assert_eq!(db.get(&ro, b"c").unwrap(), Some(b"2".to_vec()));
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_universal_compaction() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let universal = Options {
+            compaction_strategy: Arc::new(UniversalCompaction::default()),
+            write_buffer_size: 8 * 1024,
+            compression: CompressionType::None,
+            ..options()
+        };
+        let db = Db::open(&dir, universal.clone()).unwrap();
+        let value = |i: u32, round: u32| format!("{}-{}-{}", i, round, "x".repeat(50));
+        for round in 0..3 {
+            for i in 0..1000u32 {
+                let key = format!("key-{:05}", i);
+                db.put(&wo, key.as_bytes(), value(i, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        for i in (0..1000u32).step_by(3) {
+            db.delete(&wo, format!("key-{:05}", i).as_bytes()).unwrap();
+        }
+        db.flush().unwrap();
+        let check = |db: &Db| {
+            for i in 0..1000u32 {
+                let key = format!("key-{:05}", i);
+                let expected = if i % 3 == 0 {
+                    None
+                } else {
+                    Some(value(i, 2).into_bytes())
+                };
+                assert_eq!(db.get(&ro, key.as_bytes()).unwrap(), expected);
+            }
+        };
+        check(&db);
+        // sorted runs are merged within level 0
+        let num_files = db.num_files_per_level();
+        assert_eq!(num_files[1..].iter().sum::<usize>(), 0, "{:?}", num_files);
+        let stats = db.compaction_stats();
+        assert!(stats.num_flushes > 0);
+        assert!(stats.num_compactions > 0);
+        assert!(stats.write_amplification() >= 1.0);
+        assert!(stats.space_amplification() >= 1.0);
+
+        db.compact_range(None, None).unwrap();
+        assert_eq!(db.num_files_per_level()[0], 1);
+        let stats = db.compaction_stats();
+        assert_eq!(stats.live_bytes, stats.largest_run_bytes);
+        check(&db);
+        drop(db);
+        let db = Db::open(&dir, universal).unwrap();
+        assert_eq!(db.num_files_per_level()[0], 1);
+        check(&db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let (ro, wo) = (ReadOptions::default(), WriteOptions::default());
+        let db = Db::open(&dir, without_compactions()).unwrap();
+        for prefix in ["a", "b", "c"] {
+            for i in 0..10u32 {
+                db.put(&wo, format!("{}{}", prefix, i).as_bytes(), b"1")
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+        let snapshot = db.snapshot();
+        db.delete(&wo, b"b5").unwrap();
+        // level 0 pulls in all the tables overlapping the range
+        db.compact_range(Some(b"b3"), Some(b"b4")).unwrap();
+        assert_eq!(db.num_files_per_level()[..2], [2, 1]);
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.get(&at_snapshot, b"b5").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(&ro, b"b5").unwrap(), None);
+
+        // without snapshots, only the most recent value of each key is kept
+        drop(at_snapshot);
+        db.put(&wo, b"c9", b"2").unwrap();
+        db.compact_range(None, None).unwrap();
+        assert_eq!(db.num_files_per_level()[..3], [0, 1, 0]);
+        assert_eq!(db.get(&ro, b"b5").unwrap(), None);
+        assert_eq!(db.get(&ro, b"c9").unwrap(), Some(b"2".to_vec()));
+        let version = db.inner.lock_state().versions.current().clone();
+        let file = &version.files[1][0];
+        let table = db
+            .inner
+            .table_cache
+            .get(file.number, file.file_size)
+            .unwrap();
+        let mut cursor = table.cursor(true);
+        cursor.seek_to_first();
+        let mut user_keys = Vec::new();
+        while cursor.valid() {
+            let parsed = internal_key::parse(cursor.key()).unwrap();
+            assert_eq!(parsed.value_type, ValueType::Value);
+            user_keys.push(parsed.user_key.to_vec());
+            cursor.next();
+        }
+        assert_eq!(user_keys.len(), 29);
+        assert!(!user_keys.contains(&b"b5".to_vec()));
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,276 @@
+use crate::document::{Document, FieldValue};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+
+/// A `Column` holds the values of a single field for every document,
+/// indexed by doc id. Documents without a value are `None`.
+#[derive(Serialize, Deserialize)]
+pub enum Column {
+    Keyword(Vec<Option<String>>),
+    Numeric(Vec<Option<i64>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortField {
+    pub field: String,
+    pub order: SortOrder,
+}
+
+/// Column oriented storage of per document values
+///
+/// Unlike the inverted index that maps terms to documents, doc values map
+/// documents to the values of their fields, which is what sorting needs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct DocValues {
+    columns: HashMap<String, Column>,
+}
+
+/// Value of a document used to compare it with others
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue<'a> {
+    Keyword(&'a str),
+    Numeric(i64),
+}
+
+impl Column {
+    fn value(&self, doc_id: usize) -> Option<SortValue<'_>> {
+        match self {
+            Column::Keyword(values) => values.get(doc_id)?.as_deref().map(SortValue::Keyword),
+            Column::Numeric(values) => values
+                .get(doc_id)
+                .copied()
+                .flatten()
+                .map(SortValue::Numeric),
+        }
+    }
+
+    // missing values always sort last, whatever the order
+    fn compare_values<V: Ord>(a: Option<V>, b: Option<V>, order: SortOrder) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => match order {
+                SortOrder::Asc => a.cmp(&b),
+                SortOrder::Desc => b.cmp(&a),
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
+
+impl SortField {
+    pub fn new(field: &str, order: SortOrder) -> SortField {
+        SortField {
+            field: field.to_string(),
+            order,
+        }
+    }
+}
+
+impl DocValues {
+    pub fn new() -> DocValues {
+        DocValues {
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Fails if `field` already holds numeric values
+    pub fn set_keyword(&mut self, field: &str, doc_id: usize, value: String) -> io::Result<()> {
+        let column = self
+            .columns
+            .entry(field.to_string())
+            .or_insert_with(|| Column::Keyword(Vec::new()));
+        match column {
+            Column::Keyword(values) => Self::set(values, doc_id, value),
+            Column::Numeric(_) => return Err(type_conflict(field, true)),
+        }
+        Ok(())
+    }
+
+    /// Fails if `field` already holds keyword values
+    pub fn set_numeric(&mut self, field: &str, doc_id: usize, value: i64) -> io::Result<()> {
+        let column = self
+            .columns
+            .entry(field.to_string())
+            .or_insert_with(|| Column::Numeric(Vec::new()));
+        match column {
+            Column::Numeric(values) => Self::set(values, doc_id, value),
+            Column::Keyword(_) => return Err(type_conflict(field, false)),
+        }
+        Ok(())
+    }
+
+    /// Checks that the doc values of `doc` can be set without changing the
+    /// type of a field, neither here nor between the fields of `doc`
+    pub fn check_document(&self, doc: &Document) -> io::Result<()> {
+        let mut types: HashMap<&str, bool> = HashMap::new();
+        for (field, value) in doc.fields() {
+            let is_keyword = match value {
+                FieldValue::Keyword(_) => true,
+                FieldValue::Numeric(_) => false,
+                FieldValue::Text(_) => continue,
+            };
+            let existing = match self.columns.get(field) {
+                Some(Column::Keyword(_)) => Some(true),
+                Some(Column::Numeric(_)) => Some(false),
+                None => types.get(field).copied(),
+            };
+            if existing.is_some_and(|k| k != is_keyword) {
+                return Err(type_conflict(field, is_keyword));
+            }
+            types.insert(field, is_keyword);
+        }
+        Ok(())
+    }
+
+    pub fn keyword(&self, field: &str, doc_id: usize) -> Option<&str> {
+        match self.columns.get(field) {
+            Some(Column::Keyword(values)) => values.get(doc_id)?.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn numeric(&self, field: &str, doc_id: usize) -> Option<i64> {
+        match self.columns.get(field) {
+            Some(Column::Numeric(values)) => *values.get(doc_id)?,
+            _ => None,
+        }
+    }
+
+    pub fn column(&self, field: &str) -> Option<&Column> {
+        self.columns.get(field)
+    }
+
+    /// Sorts `docs` by the given fields, ties are broken on ascending doc id.
+    /// Unknown fields compare as equal.
+    pub fn sort(&self, docs: &mut [usize], sort: &[SortField]) {
+        docs.sort_unstable_by(|a, b| self.compare(*a, *b, sort));
+    }
+
+    pub fn compare(&self, a: usize, b: usize, sort: &[SortField]) -> Ordering {
+        self.compare_with(a, self, b, sort).then(a.cmp(&b))
+    }
+
+    /// Compares doc `a` of `self` with doc `b` of `other`, which may belong
+    /// to another segment. Unlike `compare`, ties are not broken.
+    pub fn compare_with(
+        &self,
+        a: usize,
+        other: &DocValues,
+        b: usize,
+        sort: &[SortField],
+    ) -> Ordering {
+        for sf in sort {
+            let va = self.columns.get(&sf.field).and_then(|c| c.value(a));
+            let vb = other.columns.get(&sf.field).and_then(|c| c.value(b));
+            let ord = Column::compare_values(va, vb, sf.order);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Values of doc `doc_id` for the fields of `sort`, `None` where it has
+    /// none. Unlike doc ids they survive merges.
+    pub fn sort_values(&self, doc_id: usize, sort: &[SortField]) -> Vec<Option<FieldValue>> {
+        sort.iter()
+            .map(|sf| match self.columns.get(&sf.field)?.value(doc_id)? {
+                SortValue::Keyword(v) => Some(FieldValue::Keyword(v.to_string())),
+                SortValue::Numeric(v) => Some(FieldValue::Numeric(v)),
+            })
+            .collect()
+    }
+
+    /// Compares doc `doc_id` with a doc whose `sort_values` are given
+    pub fn compare_to_values(
+        &self,
+        doc_id: usize,
+        values: &[Option<FieldValue>],
+        sort: &[SortField],
+    ) -> Ordering {
+        for (sf, value) in sort.iter().zip(values) {
+            let va = self.columns.get(&sf.field).and_then(|c| c.value(doc_id));
+            let vb = match value {
+                Some(FieldValue::Keyword(v)) => Some(SortValue::Keyword(v)),
+                Some(FieldValue::Numeric(v)) => Some(SortValue::Numeric(*v)),
+                Some(FieldValue::Text(_)) | None => None,
+            };
+            let ord = Column::compare_values(va, vb, sf.order);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Copies every value of doc `from_doc` in `from` to `doc_id`. Fails if
+    /// a field has another type in `from`.
+    pub fn copy_doc(&mut self, doc_id: usize, from: &DocValues, from_doc: usize) -> io::Result<()> {
+        for (field, column) in &from.columns {
+            match column.value(from_doc) {
+                Some(SortValue::Keyword(v)) => self.set_keyword(field, doc_id, v.to_string())?,
+                Some(SortValue::Numeric(v)) => self.set_numeric(field, doc_id, v)?,
+                None => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn set<V>(values: &mut Vec<Option<V>>, doc_id: usize, value: V) {
+        if values.len() <= doc_id {
+            values.resize_with(doc_id + 1, || None);
+        }
+        values[doc_id] = Some(value);
+    }
+}
+
+fn type_conflict(field: &str, is_keyword: bool) -> io::Error {
+    let expected = if is_keyword { "numeric" } else { "keyword" };
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("field {} is a {} field", field, expected),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sort() {
+        let mut dv = DocValues::new();
+        dv.set_keyword("title", 0, "b".to_string()).unwrap();
+        dv.set_keyword("title", 1, "a".to_string()).unwrap();
+        dv.set_keyword("title", 3, "a".to_string()).unwrap();
+        dv.set_numeric("length", 0, 10).unwrap();
+        dv.set_numeric("length", 1, 5).unwrap();
+        dv.set_numeric("length", 2, 7).unwrap();
+        dv.set_numeric("length", 3, 7).unwrap();
+
+        let mut docs = vec![0, 1, 2, 3];
+        dv.sort(&mut docs, &[SortField::new("title", SortOrder::Asc)]);
+        // missing values last, ties on doc id
+        assert_eq!(docs, vec![1, 3, 0, 2]);
+
+        dv.sort(&mut docs, &[SortField::new("title", SortOrder::Desc)]);
+        assert_eq!(docs, vec![0, 1, 3, 2]);
+
+        dv.sort(
+            &mut docs,
+            &[
+                SortField::new("length", SortOrder::Desc),
+                SortField::new("title", SortOrder::Asc),
+            ],
+        );
+        assert_eq!(docs, vec![0, 3, 2, 1]);
+    }
+}
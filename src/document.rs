@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Field names starting with this prefix are reserved for the fields the
+/// index derives from documents
+pub const RESERVED_PREFIX: &str = "_";
+
+/// Numeric doc value of the number of tokens in the text fields of a
+/// document
+pub const LENGTH_FIELD: &str = "_length";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldValue {
+    /// Tokenized and indexed for full text search
+    Text(String),
+    /// Stored as is in doc values, for sorting, faceting and unique keys
+    Keyword(String),
+    Numeric(i64),
+}
+
+/// A `Document` is a list of named fields
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Document {
+    fields: Vec<(String, FieldValue)>,
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document { fields: Vec::new() }
+    }
+
+    pub fn add_text(&mut self, field: &str, value: &str) -> io::Result<()> {
+        check_field_name(field)?;
+        self.fields
+            .push((field.to_string(), FieldValue::Text(value.to_string())));
+        Ok(())
+    }
+
+    pub fn add_keyword(&mut self, field: &str, value: &str) -> io::Result<()> {
+        check_field_name(field)?;
+        self.fields
+            .push((field.to_string(), FieldValue::Keyword(value.to_string())));
+        Ok(())
+    }
+
+    pub fn add_numeric(&mut self, field: &str, value: i64) -> io::Result<()> {
+        check_field_name(field)?;
+        self.fields
+            .push((field.to_string(), FieldValue::Numeric(value)));
+        Ok(())
+    }
+
+    pub fn get(&self, field: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+    }
+
+    pub fn keyword(&self, field: &str) -> Option<&str> {
+        match self.get(field) {
+            Some(FieldValue::Keyword(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &FieldValue)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+fn check_field_name(field: &str) -> io::Result<()> {
+    if field.starts_with(RESERVED_PREFIX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("field name {} is reserved", field),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_field_names() {
+        let mut doc = Document::new();
+        doc.add_numeric("length", 3).unwrap();
+        doc.add_keyword("url_", "u0").unwrap();
+        doc.add_text("body", "red boat").unwrap();
+        assert_eq!(doc.get("length"), Some(&FieldValue::Numeric(3)));
+        assert_eq!(doc.keyword("url_"), Some("u0"));
+        assert_eq!(doc.fields().count(), 3);
+    }
+
+    #[test]
+    fn test_reserved_field() {
+        let mut doc = Document::new();
+        let err = doc.add_numeric(LENGTH_FIELD, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(doc.add_keyword("_id", "a").is_err());
+        assert!(doc.add_text("_body", "red boat").is_err());
+        assert_eq!(doc.fields().count(), 0);
+    }
+}
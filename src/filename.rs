@@ -0,0 +1,147 @@
+//! Names of the files of a `Db` directory
+//!
+//! Logs, tables and manifests are numbered from the same counter, so a
+//! number is never reused by a database. `CURRENT` names the manifest in use.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const CURRENT: &str = "CURRENT";
+const MANIFEST_PREFIX: &str = "MANIFEST-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Log,
+    Table,
+    Manifest,
+    /// Left over by a crash while replacing `CURRENT`
+    Temp,
+}
+
+pub fn log_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.log", number))
+}
+
+pub fn table_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.ldb", number))
+}
+
+pub fn manifest_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{}{:06}", MANIFEST_PREFIX, number))
+}
+
+pub fn temp_file_name(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:06}.dbtmp", number))
+}
+
+pub fn current_file_name(dir: &Path) -> PathBuf {
+    dir.join(CURRENT)
+}
+
+/// Points `CURRENT` at manifest `number`, atomically by renaming a temporary
+/// file numbered after it
+pub fn set_current_file(dir: &Path, number: u64) -> io::Result<()> {
+    let tmp_path = temp_file_name(dir, number);
+    let mut file = File::create(&tmp_path)?;
+    writeln!(file, "{}{:06}", MANIFEST_PREFIX, number)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, current_file_name(dir))?;
+    File::open(dir)?.sync_all()
+}
+
+/// Number of the manifest named by `CURRENT`
+pub fn read_current_file(dir: &Path) -> io::Result<u64> {
+    let contents = fs::read_to_string(current_file_name(dir))?;
+    match contents.strip_suffix('\n').and_then(parse_file_name) {
+        Some((FileType::Manifest, number)) => Ok(number),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: malformed CURRENT file", dir.display()),
+        )),
+    }
+}
+
+/// Type and number of a numbered file, `None` for any other file
+pub fn parse_file_name(name: &str) -> Option<(FileType, u64)> {
+    let (number, file_type) = if let Some(number) = name.strip_suffix(".log") {
+        (number, FileType::Log)
+    } else if let Some(number) = name.strip_suffix(".ldb") {
+        (number, FileType::Table)
+    } else if let Some(number) = name.strip_prefix(MANIFEST_PREFIX) {
+        (number, FileType::Manifest)
+    } else if let Some(number) = name.strip_suffix(".dbtmp") {
+        (number, FileType::Temp)
+    } else {
+        return None;
+    };
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((file_type, number.parse().ok()?))
+}
+
+/// Numbered files of `dir`, by type then number
+pub fn numbered_files(dir: &Path) -> io::Result<Vec<(FileType, u64)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some(file) = name.to_str().and_then(parse_file_name) {
+            files.push(file);
+        }
+    }
+    files.sort_unstable_by_key(|(file_type, number)| (*file_type as u8, *number));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_parse_file_name() {
+        let dir = Path::new("db");
+        let files = [
+            (FileType::Log, 7),
+            (FileType::Table, 1_234_567),
+            (FileType::Manifest, 3),
+            (FileType::Temp, 3),
+        ];
+        for (file_type, number) in files {
+            let path = match file_type {
+                FileType::Log => log_file_name(dir, number),
+                FileType::Table => table_file_name(dir, number),
+                FileType::Manifest => manifest_file_name(dir, number),
+                FileType::Temp => temp_file_name(dir, number),
+            };
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert_eq!(parse_file_name(name), Some((file_type, number)));
+        }
+        assert_eq!(parse_file_name("000001.ldb.tmp"), None);
+        assert_eq!(parse_file_name(".log"), None);
+        assert_eq!(parse_file_name("+1.log"), None);
+        assert_eq!(parse_file_name("MANIFEST-"), None);
+        assert_eq!(parse_file_name("CURRENT"), None);
+    }
+
+    #[test]
+    fn test_current_file() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(read_current_file(&dir).is_err());
+        set_current_file(&dir, 5).unwrap();
+        set_current_file(&dir, 12).unwrap();
+        assert_eq!(read_current_file(&dir).unwrap(), 12);
+        assert_eq!(
+            fs::read_to_string(current_file_name(&dir)).unwrap(),
+            "MANIFEST-000012\n"
+        );
+        assert_eq!(numbered_files(&dir).unwrap(), Vec::new());
+        fs::write(current_file_name(&dir), "MANIFEST-x\n").unwrap();
+        let err = read_current_file(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
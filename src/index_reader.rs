@@ -0,0 +1,96 @@
+use crate::aggregations::{Aggregation, AggregationResult};
+use crate::inverted_index::InvertedIndex;
+use crate::live_docs::LiveDocs;
+use crate::search::{SearchOptions, SegmentsView, TopDocs};
+use crate::segment::Segment;
+use std::sync::Arc;
+
+/// Point in time view of an `InvertedIndex`
+///
+/// A reader sees the segments of the index as of the last refresh of its
+/// writer. Documents added or deleted since then are invisible until the
+/// reader is reopened. Segments are shared, so readers are cheap to clone.
+#[derive(Clone, Default)]
+pub struct IndexReader {
+    segments: Vec<(Arc<Segment>, Arc<LiveDocs>)>,
+    /// Keyword field uniquely identifying documents
+    key_field: String,
+    version: u64,
+}
+
+impl IndexReader {
+    pub fn new(
+        segments: Vec<(Arc<Segment>, Arc<LiveDocs>)>,
+        key_field: &str,
+        version: u64,
+    ) -> IndexReader {
+        IndexReader {
+            segments,
+            key_field: key_field.to_string(),
+            version,
+        }
+    }
+
+    /// Version of the index this reader was opened on
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns a new reader if `writer` was refreshed since this reader was
+    /// opened, `None` if this reader is still current. `writer` refreshes
+    /// first if its refresh interval elapsed.
+    pub fn reopen(&self, writer: &mut InvertedIndex) -> Option<IndexReader> {
+        let reader = writer.reader();
+        if reader.version == self.version {
+            None
+        } else {
+            Some(reader)
+        }
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.view().num_docs()
+    }
+
+    pub fn search(&self, query: &str) -> Option<Vec<usize>> {
+        self.view().search(query)
+    }
+
+    pub fn search_with_options(&self, query: &str, options: &SearchOptions) -> Option<TopDocs> {
+        self.view().search_with_options(query, options)
+    }
+
+    pub fn aggregate(&self, docs: &[usize], aggs: &[Aggregation]) -> Vec<AggregationResult> {
+        self.view().aggregate(docs, aggs)
+    }
+
+    pub fn doc_id(&self, key: &str) -> Option<usize> {
+        self.view().doc_id(key)
+    }
+
+    pub fn is_live(&self, doc_id: usize) -> bool {
+        self.view().is_live(doc_id)
+    }
+
+    pub fn keyword(&self, doc_id: usize, field: &str) -> Option<&str> {
+        self.view().keyword(doc_id, field)
+    }
+
+    pub fn numeric(&self, doc_id: usize, field: &str) -> Option<i64> {
+        self.view().numeric(doc_id, field)
+    }
+
+    fn view(&self) -> SegmentsView<'_> {
+        SegmentsView::new(
+            self.segments
+                .iter()
+                .map(|(segment, live_docs)| (segment.as_ref(), live_docs.as_ref()))
+                .collect(),
+            &self.key_field,
+        )
+    }
+}
@@ -0,0 +1,120 @@
+use crate::document::Document;
+use crate::live_docs::LiveDocs;
+use crate::segment::Segment;
+use std::io;
+use std::thread;
+
+/// Builds segment `id` out of `docs` using `num_threads` worker threads
+///
+/// Documents are split in contiguous shards, one per worker, and each worker
+/// indexes its shard in its own segment. Shards are then merged in order, so
+/// the doc id of a document is its position in `docs` whatever the number of
+/// threads. Fails if a field has different types in different documents.
+pub fn build_segment(
+    id: u64,
+    docs: &[Document],
+    key_field: &str,
+    num_threads: usize,
+) -> io::Result<Segment> {
+    let num_threads = num_threads.clamp(1, docs.len().max(1));
+    if num_threads == 1 {
+        return index_shard(id, docs, key_field);
+    }
+    let shard_size = docs.len().div_ceil(num_threads);
+    let shards = thread::scope(|scope| {
+        let handles: Vec<_> = docs
+            .chunks(shard_size)
+            .enumerate()
+            .map(|(i, shard)| scope.spawn(move || index_shard(i as u64, shard, key_field)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("indexing thread panicked"))
+            .collect::<io::Result<Vec<Segment>>>()
+    })?;
+    let live_docs: Vec<LiveDocs> = shards
+        .iter()
+        .map(|s| LiveDocs::all_live(s.max_doc()))
+        .collect();
+    let sources: Vec<(&Segment, &LiveDocs)> = shards.iter().zip(live_docs.iter()).collect();
+    Ok(Segment::merge(id, &sources)?.0)
+}
+
+fn index_shard(id: u64, docs: &[Document], key_field: &str) -> io::Result<Segment> {
+    let mut segment = Segment::new(id);
+    for doc in docs {
+        segment.add_document(doc, key_field)?;
+    }
+    Ok(segment)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query_parser::QueryParser;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::time::Instant;
+
+    const WORDS: [&str; 12] = [
+        "red", "blue", "green", "boat", "car", "plane", "river", "city", "north", "south", "old",
+        "new",
+    ];
+
+    fn random_docs(n: usize, seed: u64) -> Vec<Document> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|i| {
+                let text: Vec<&str> = (0..rng.gen_range(5..40))
+                    .map(|_| WORDS[rng.gen_range(0..WORDS.len())])
+                    .collect();
+                let mut doc = Document::new();
+                doc.add_keyword("id", &i.to_string()).unwrap();
+                doc.add_text("body", &text.join(" ")).unwrap();
+                doc
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_segment() {
+        let docs = random_docs(1000, 42);
+        let qp = QueryParser::new();
+        let expected = build_segment(1, &docs, "id", 1).unwrap();
+        let live_docs = LiveDocs::all_live(docs.len());
+        for num_threads in [2, 3, 8] {
+            let segment = build_segment(1, &docs, "id", num_threads).unwrap();
+            assert_eq!(segment.max_doc(), docs.len());
+            assert_eq!(segment.doc_id("517"), Some(517));
+            assert_eq!(segment.doc_values().keyword("id", 999), Some("999"));
+            for query in ["red", "boat AND NOT river", "old OR city"] {
+                let tokens = qp.to_postfix(query).unwrap();
+                assert_eq!(
+                    segment.search(&qp, &tokens, &live_docs),
+                    expected.search(&qp, &tokens, &live_docs)
+                );
+            }
+        }
+        assert_eq!(build_segment(1, &[], "id", 4).unwrap().max_doc(), 0);
+    }
+
+    // RUST_LOG=info cargo test --release bench_build_segment -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_build_segment() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let docs = random_docs(200_000, 7);
+        for num_threads in [1, 2, 4, 8] {
+            let start = Instant::now();
+            let segment = build_segment(1, &docs, "id", num_threads).unwrap();
+            let elapsed = start.elapsed();
+            assert_eq!(segment.max_doc(), docs.len());
+            log::info!(
+                "{} threads: {:.0} docs/s ({:?})",
+                num_threads,
+                docs.len() as f64 / elapsed.as_secs_f64(),
+                elapsed
+            );
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::comparator::{BytewiseComparator, Comparator};
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+pub type SequenceNumber = u64;
+
+/// Sequence numbers are packed with the value type in 8 bytes, leaving 56
+/// bits for the sequence number
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+const TAG_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion = 0,
+    Value = 1,
+}
+
+/// Entries sharing a user key and sequence number sort by decreasing value
+/// type, seeking with the highest one finds all of them
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+
+impl ValueType {
+    pub fn from_u8(b: u8) -> Option<ValueType> {
+        match b {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedInternalKey<'a> {
+    pub user_key: &'a [u8],
+    pub sequence: SequenceNumber,
+    pub value_type: ValueType,
+}
+
+/// Appends the internal key of `user_key`: the user key followed by the
+/// sequence number and value type packed in 8 little endian bytes, like
+/// LevelDB's internal keys
+pub fn append(buf: &mut Vec<u8>, user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) {
+    assert!(sequence <= MAX_SEQUENCE_NUMBER, "sequence number overflow");
+    buf.extend_from_slice(user_key);
+    buf.extend_from_slice(&(sequence << 8 | value_type as u64).to_le_bytes());
+}
+
+pub fn encode(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(user_key.len() + TAG_SIZE);
+    append(&mut buf, user_key, sequence, value_type);
+    buf
+}
+
+/// Returns `None` if `internal_key` is corrupted
+pub fn parse(internal_key: &[u8]) -> Option<ParsedInternalKey<'_>> {
+    if internal_key.len() < TAG_SIZE {
+        return None;
+    }
+    let tag = tag(internal_key);
+    Some(ParsedInternalKey {
+        user_key: user_key(internal_key),
+        sequence: tag >> 8,
+        value_type: ValueType::from_u8(tag as u8)?,
+    })
+}
+
+pub fn user_key(internal_key: &[u8]) -> &[u8] {
+    &internal_key[..internal_key.len() - TAG_SIZE]
+}
+
+fn tag(internal_key: &[u8]) -> u64 {
+    let tag = &internal_key[internal_key.len() - TAG_SIZE..];
+    u64::from_le_bytes(tag.try_into().unwrap())
+}
+
+/// Orders internal keys by increasing user key, as ordered by the user
+/// comparator, then by decreasing sequence number and value type, so that
+/// the most recent entry of a user key comes first
+#[derive(Clone)]
+pub struct InternalKeyComparator {
+    user_comparator: Arc<dyn Comparator<[u8]>>,
+}
+
+impl InternalKeyComparator {
+    pub fn new(user_comparator: Arc<dyn Comparator<[u8]>>) -> InternalKeyComparator {
+        InternalKeyComparator { user_comparator }
+    }
+
+    pub fn user_comparator(&self) -> &dyn Comparator<[u8]> {
+        self.user_comparator.as_ref()
+    }
+}
+
+impl Default for InternalKeyComparator {
+    fn default() -> Self {
+        Self::new(Arc::new(BytewiseComparator))
+    }
+}
+
+impl Comparator<[u8]> for InternalKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.user_comparator
+            .compare(user_key(a), user_key(b))
+            .then_with(|| tag(b).cmp(&tag(a)))
+    }
+
+    fn name(&self) -> &str {
+        "leveldb.InternalKeyComparator"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comparator::ReverseBytewiseComparator;
+
+    #[test]
+    fn test_internal_key() {
+        let key = encode(b"foo", 42, ValueType::Deletion);
+        assert_eq!(key.len(), 3 + TAG_SIZE);
+        let parsed = parse(&key).unwrap();
+        assert_eq!(parsed.user_key, b"foo");
+        assert_eq!(parsed.sequence, 42);
+        assert_eq!(parsed.value_type, ValueType::Deletion);
+        let key = encode(b"", MAX_SEQUENCE_NUMBER, ValueType::Value);
+        assert_eq!(parse(&key).unwrap().sequence, MAX_SEQUENCE_NUMBER);
+        assert_eq!(parse(b"short"), None);
+        let mut corrupted = encode(b"foo", 1, ValueType::Value);
+        corrupted[3] = 7;
+        assert_eq!(parse(&corrupted), None);
+    }
+
+    #[test]
+    fn test_comparator() {
+        let cmp = InternalKeyComparator::default();
+        let a1 = encode(b"a", 1, ValueType::Value);
+        let a2 = encode(b"a", 2, ValueType::Value);
+        let a2_deleted = encode(b"a", 2, ValueType::Deletion);
+        let b1 = encode(b"b", 1, ValueType::Value);
+        assert_eq!(cmp.compare(&a2, &a1), Ordering::Less);
+        assert_eq!(cmp.compare(&a2, &a2_deleted), Ordering::Less);
+        assert_eq!(cmp.compare(&a1, &b1), Ordering::Less);
+        assert_eq!(cmp.compare(&a1, &a1), Ordering::Equal);
+        // user keys are ordered by the user comparator only
+        let ab = encode(b"ab", 9, ValueType::Value);
+        assert_eq!(cmp.compare(&a1, &ab), Ordering::Less);
+
+        let cmp = InternalKeyComparator::new(Arc::new(ReverseBytewiseComparator));
+        assert_eq!(cmp.compare(&a1, &b1), Ordering::Greater);
+        assert_eq!(cmp.compare(&a2, &a1), Ordering::Less);
+    }
+}
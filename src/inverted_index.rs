@@ -1,192 +1,1149 @@
-use crate::query_parser::QueryParser;
+use crate::aggregations::{Aggregation, AggregationResult};
+use crate::commit::{self, CommitPoint};
+use crate::document::Document;
+use crate::index_reader::IndexReader;
+use crate::indexer;
+use crate::live_docs::LiveDocs;
+use crate::merge_policy::{MergePolicy, SegmentInfo, TieredMergePolicy};
+use crate::search::{SearchOptions, SegmentsView, TopDocs};
+use crate::searcher::Searcher;
+use crate::segment::{DocMap, Segment};
 use crate::wiki::WikiDoc;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-#[derive(Serialize, Deserialize)]
+/// An index made of immutable segments
+///
+/// New documents are buffered in an in-memory segment that is flushed once
+/// it holds `max_buffered_docs` documents. After each flush the merge policy
+/// is asked for segments to merge, merges run in background threads and are
+/// swapped in once done. Doc ids are global: the doc id of a document is its
+/// doc id in its segment plus the number of documents in previous segments,
+/// hence doc ids change when segments are merged.
+///
+/// Searching the index itself sees every change right away. `IndexReader`s
+/// only see changes once the index is refreshed, and changes only survive a
+/// restart once committed to the index directory.
 pub struct InvertedIndex {
-    idx: HashMap<String, Vec<usize>>,
-    doc_count: usize,
+    segments: Vec<SegmentEntry>,
+    buffer: Segment,
+    buffer_live_docs: LiveDocs,
+    next_segment_id: u64,
+    /// Keyword field uniquely identifying documents
+    key_field: String,
+    max_buffered_docs: usize,
+    merge_policy: Box<dyn MergePolicy>,
+    merges: Vec<RunningMerge>,
+    /// Bumped on every change, readers opened on an older version are stale
+    version: u64,
+    /// Snapshot published by the last refresh, served by searchers
+    snapshot: Arc<IndexReader>,
+    refresh_interval: Option<Duration>,
+    last_refresh: Instant,
+    directory: Option<PathBuf>,
+    /// Generation of the last commit to `directory`, 0 if none
+    generation: u64,
+    /// First merge that failed since the last `wait_for_merges` or `commit`
+    merge_error: Option<io::Error>,
+}
+
+pub const DEFAULT_KEY_FIELD: &str = "url";
+pub const DEFAULT_MAX_BUFFERED_DOCS: usize = 10_000;
+
+struct SegmentEntry {
+    segment: Arc<Segment>,
+    // copied on write so that running merges and readers keep their own view
+    live_docs: Arc<LiveDocs>,
+    merging: bool,
+}
+
+struct RunningMerge {
+    /// Ids of the merged segments and their live docs when the merge started
+    sources: Vec<(u64, Arc<LiveDocs>)>,
+    handle: JoinHandle<io::Result<(Segment, Vec<DocMap>)>>,
+}
+
+#[derive(Serialize)]
+struct SavedIndexRef<'a> {
+    key_field: &'a str,
+    next_segment_id: u64,
+    segments: Vec<(&'a Segment, &'a LiveDocs)>,
+}
+
+#[derive(Deserialize)]
+struct SavedIndex {
+    key_field: String,
+    next_segment_id: u64,
+    segments: Vec<(Segment, LiveDocs)>,
+}
+
+impl SavedIndex {
+    /// Index saved before it was split in segments, as a map of the
+    /// postings of each term of its `doc_count` documents, all live.
+    fn from_postings(
+        postings: HashMap<String, Vec<usize>>,
+        doc_count: usize,
+    ) -> io::Result<SavedIndex> {
+        let segment = Segment::from_postings(1, postings, doc_count)?;
+        let live_docs = LiveDocs::all_live(segment.max_doc());
+        Ok(SavedIndex {
+            key_field: DEFAULT_KEY_FIELD.to_string(),
+            next_segment_id: 2,
+            segments: vec![(segment, live_docs)],
+        })
+    }
 }
 
 impl InvertedIndex {
+    /// Loads the index saved to `fp`, replacing the content of this one.
     pub fn open(&mut self, fp: &str) -> std::io::Result<()> {
-        let file = File::open(fp)?;
-        let br = BufReader::new(file);
-        self.idx = serde_json::from_reader(br)?;
+        let saved = read_json(fp)?;
+        self.load(saved);
         Ok(())
     }
 
-    pub fn save(&self, fp: &str) -> std::io::Result<()> {
-        let file = File::create(fp)?;
-        let mut bw = BufWriter::new(file);
-        serde_json::to_writer(&mut bw, &self.idx)?;
+    /// Loads an index saved before it was split in segments, replacing the
+    /// content of this one. Such files only hold the postings of each term,
+    /// the number of indexed documents is `doc_count` as it was when they
+    /// were saved, and doc ids are positions in the indexed documents.
+    pub fn open_unsegmented(&mut self, fp: &str, doc_count: usize) -> std::io::Result<()> {
+        let saved = SavedIndex::from_postings(read_json(fp)?, doc_count)?;
+        self.load(saved);
         Ok(())
     }
 
-    pub fn add_wiki_doc(&mut self, doc: &WikiDoc, doc_id: usize) {
-        let tokens = InvertedIndex::tokenizer(doc.r#abstract.clone());
-        let lowered_tokens = InvertedIndex::lowercase_filter(tokens);
-        for lt in lowered_tokens {
-            if self.idx.contains_key(&lt) {
-                let postings = match self.idx.get_mut(&lt) {
-                    Some(v) => v,
-                    None => panic!("unintialized postings list"),
-                };
-                if !postings.contains(&doc_id) {
-                    // TODO compare efficiency
-                    postings.push(doc_id);
-                    // InvertedIndex::sorted_insert(postings, doc_id);
+    fn load(&mut self, saved: SavedIndex) {
+        // the merged segments are replaced anyway
+        self.install_merges(true);
+        self.merge_error = None;
+        self.key_field = saved.key_field;
+        self.segments = saved
+            .segments
+            .into_iter()
+            .map(|(segment, live_docs)| SegmentEntry {
+                segment: Arc::new(segment),
+                live_docs: Arc::new(live_docs),
+                merging: false,
+            })
+            .collect();
+        self.next_segment_id = saved.next_segment_id;
+        self.buffer = Segment::new(self.new_segment_id());
+        self.buffer_live_docs = LiveDocs::new();
+        self.version += 1;
+        self.refresh();
+    }
+
+    /// Saves the whole index to a single file. The file is replaced
+    /// atomically, a crash while saving leaves the previous save intact.
+    pub fn save(&self, fp: &str) -> std::io::Result<()> {
+        let saved = SavedIndexRef {
+            key_field: &self.key_field,
+            next_segment_id: self.next_segment_id,
+            segments: self.readers().filter(|(s, _)| s.max_doc() > 0).collect(),
+        };
+        let path = Path::new(fp);
+        commit::write_file_atomic(path, &saved)?;
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => commit::sync_dir(dir),
+            _ => commit::sync_dir(Path::new(".")),
+        }
+    }
+
+    /// Opens the index stored in `dir` as of its last commit, or creates an
+    /// empty one if there is none.
+    ///
+    /// Commits with a torn or corrupted file are rolled back to the newest
+    /// commit that loads, other errors are returned. Files of older commits
+    /// that the loaded commit doesn't reference are deleted, files of newer
+    /// commits are kept until the next commit goes past them.
+    pub fn open_directory(dir: &Path) -> io::Result<InvertedIndex> {
+        fs::create_dir_all(dir)?;
+        let mut index = InvertedIndex::new();
+        let generations = CommitPoint::generations(dir)?;
+        let mut last_err = None;
+        for generation in generations {
+            match CommitPoint::load(dir, generation) {
+                Ok((commit, segments)) => {
+                    index.segments = segments
+                        .into_iter()
+                        .map(|(segment, live_docs)| SegmentEntry {
+                            segment: Arc::new(segment),
+                            live_docs: Arc::new(live_docs),
+                            merging: false,
+                        })
+                        .collect();
+                    index.key_field = commit.key_field.clone();
+                    index.next_segment_id = commit
+                        .next_segment_id
+                        .max(CommitPoint::next_segment_id(dir)?);
+                    index.buffer = Segment::new(index.new_segment_id());
+                    index.generation = commit.generation;
+                    index.version += 1;
+                    commit.delete_unreferenced(dir)?;
+                    last_err = None;
+                    break;
                 }
-            } else {
-                self.idx.insert(lt, vec![doc_id]);
+                Err(err) => match err.kind() {
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                        last_err = Some(err)
+                    }
+                    _ => return Err(err),
+                },
             }
         }
-        self.doc_count += 1;
+        // commits exist but none of them could be loaded
+        if let Some(err) = last_err {
+            return Err(err);
+        }
+        index.directory = Some(dir.to_path_buf());
+        index.refresh();
+        Ok(index)
     }
 
-    pub fn new(doc_count: usize) -> InvertedIndex {
-        InvertedIndex {
-            idx: HashMap::new(),
-            doc_count: doc_count,
+    /// Refreshes the index and durably writes its segments to the index
+    /// directory under a new commit generation. Segment files are written
+    /// once, live docs and the commit point on every commit. Fails without
+    /// committing if a merge failed since the last commit.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let dir = match &self.directory {
+            Some(dir) => dir.clone(),
+            None => return Err(io::Error::other("index has no directory to commit to")),
+        };
+        self.refresh();
+        self.take_merge_error()?;
+        // past the commits that failed to load
+        let newest = CommitPoint::generations(&dir)?
+            .first()
+            .copied()
+            .unwrap_or(0);
+        let commit = CommitPoint {
+            generation: self.generation.max(newest) + 1,
+            key_field: self.key_field.clone(),
+            next_segment_id: self.next_segment_id,
+            segments: self.segments.iter().map(|e| e.segment.id()).collect(),
+        };
+        let segments: Vec<(&Segment, &LiveDocs)> = self
+            .segments
+            .iter()
+            .map(|e| (e.segment.as_ref(), e.live_docs.as_ref()))
+            .collect();
+        commit.write(&dir, &segments)?;
+        self.generation = commit.generation;
+        commit.delete_unreferenced(&dir)
+    }
+
+    /// Generation of the last commit, 0 if the index was never committed
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn add_wiki_doc(&mut self, doc: &WikiDoc) -> io::Result<usize> {
+        self.add_document(&Document::from(doc))
+    }
+
+    /// Adds `doc` and returns its doc id. If a document with the same key
+    /// exists, it is replaced. Fails, leaving the index unchanged, if a
+    /// field of `doc` has another type in indexed documents.
+    pub fn add_document(&mut self, doc: &Document) -> io::Result<usize> {
+        self.install_merges(false);
+        self.check_document(doc)?;
+        if let Some(key) = doc.keyword(&self.key_field) {
+            self.delete_key(key);
+        }
+        let doc_id = self.buffer_base() + self.buffer.add_document(doc, &self.key_field)?;
+        self.buffer_live_docs.add(self.buffer.max_doc() - 1);
+        self.version += 1;
+        if self.buffer.max_doc() >= self.max_buffered_docs {
+            self.flush();
         }
+        self.maybe_refresh();
+        Ok(doc_id)
     }
 
-    // here Option should be a Result in case query parsing errors
-    pub fn search(&self, query: &str) -> Option<Vec<usize>> {
-        let qp = QueryParser::new();
-        let tokens = qp.to_postfix(query);
-        let all_postings: Vec<usize> = (0..self.doc_count).collect();
-        if tokens.is_none() {
-            return None;
-        }
-        let tokens = tokens.unwrap();
-        let mut stack: Vec<Vec<usize>> = Vec::with_capacity(tokens.len());
-        for token in tokens {
-            if !qp.operators.contains(&&token[..]) {
-                match self.idx.get(&token) {
-                    Some(pl) => stack.push(pl.to_vec()),
-                    None => stack.push(Vec::new()),
+    /// Adds `docs` as a new segment built by `num_threads` threads and
+    /// returns the doc id of the first one, the others follow in order.
+    /// Documents replace existing documents with the same key, and earlier
+    /// documents of `docs` with the same key. Fails, leaving the index
+    /// unchanged, if a field has different types in `docs` or the index.
+    pub fn add_documents(&mut self, docs: &[Document], num_threads: usize) -> io::Result<usize> {
+        // flushing first keeps doc ids in insertion order
+        self.flush();
+        self.install_merges(false);
+        let base = self.buffer_base();
+        if docs.is_empty() {
+            return Ok(base);
+        }
+        for doc in docs {
+            self.check_document(doc)?;
+        }
+        let id = self.new_segment_id();
+        let segment = indexer::build_segment(id, docs, &self.key_field, num_threads)?;
+        for doc in docs {
+            if let Some(key) = doc.keyword(&self.key_field) {
+                self.delete_key(key);
+            }
+        }
+        let mut live_docs = LiveDocs::new();
+        for (doc_id, doc) in docs.iter().enumerate() {
+            live_docs.add(doc_id);
+            if let Some(key) = doc.keyword(&self.key_field) {
+                if segment.doc_id(key) != Some(doc_id) {
+                    live_docs.delete(doc_id);
                 }
-            } else if token == "NOT" {
-                let pl = stack.pop().unwrap();
-                stack.push(InvertedIndex::intersect_not(&all_postings, &pl));
-            } else if token == "AND" {
-                let r = stack.pop().unwrap();
-                let l = stack.pop().unwrap();
-                stack.push(InvertedIndex::intersect(&l, &r));
-            } else if token == "OR" {
-                let r = stack.pop().unwrap();
-                let l = stack.pop().unwrap();
-                stack.push(InvertedIndex::merge(&l, &r));
             }
         }
-        stack.pop()
+        self.segments.push(SegmentEntry {
+            segment: Arc::new(segment),
+            live_docs: Arc::new(live_docs),
+            merging: false,
+        });
+        self.version += 1;
+        self.maybe_merge();
+        self.maybe_refresh();
+        Ok(base)
     }
 
-    fn intersect_not(p1: &Vec<usize>, p2: &Vec<usize>) -> Vec<usize> {
-        let mut res: Vec<usize> = Vec::new();
-        let mut i = 0;
-        let mut j = 0;
+    /// Deletes the document identified by `key`, if any, then adds `doc`.
+    /// Nothing is deleted if `doc` can't be added.
+    pub fn update_document(&mut self, key: &str, doc: &Document) -> io::Result<usize> {
+        self.check_document(doc)?;
+        self.delete_key(key);
+        self.add_document(doc)
+    }
 
-        while i < p1.len() && j < p2.len() {
-            if p1[i] == p2[j] {
-                i += 1;
-                j += 1;
-            } else if p1[i] < p2[j] {
-                res.push(p1[i]);
-                i += 1;
-            } else {
-                j += 1;
-            }
+    // checks that the fields of `doc` have the same type in every segment
+    fn check_document(&self, doc: &Document) -> io::Result<()> {
+        for entry in self.segments.iter() {
+            entry.segment.doc_values().check_document(doc)?;
         }
-        while i < p1.len() {
-            res.push(p1[i]);
-            i += 1;
-        }
-        res
+        self.buffer.doc_values().check_document(doc)
     }
 
-    fn intersect(p1: &Vec<usize>, p2: &Vec<usize>) -> Vec<usize> {
-        let mut res = Vec::new();
-        let mut i = 0;
-        let mut j = 0;
+    /// Returns `false` if no live document has this key
+    pub fn delete_document(&mut self, key: &str) -> bool {
+        let deleted = self.delete_key(key);
+        self.maybe_refresh();
+        deleted
+    }
 
-        while i < p1.len() && j < p2.len() {
-            if p1[i] == p2[j] {
-                res.push(p1[i]);
-                i += 1;
-                j += 1;
-            } else if p1[i] < p2[j] {
-                i += 1;
-            } else {
-                j += 1;
+    fn delete_key(&mut self, key: &str) -> bool {
+        let mut deleted = false;
+        for entry in self.segments.iter_mut() {
+            if let Some(doc_id) = entry.segment.doc_id(key) {
+                if entry.live_docs.is_live(doc_id) {
+                    deleted |= Arc::make_mut(&mut entry.live_docs).delete(doc_id);
+                }
             }
         }
-        res
+        if let Some(doc_id) = self.buffer.doc_id(key) {
+            deleted |= self.buffer_live_docs.delete(doc_id);
+        }
+        if deleted {
+            self.version += 1;
+        }
+        deleted
     }
 
-    fn merge(p1: &Vec<usize>, p2: &Vec<usize>) -> Vec<usize> {
-        let mut res = Vec::new();
-        let mut i = 0;
-        let mut j = 0;
+    /// Writes the buffered documents to a new segment and looks for merges
+    pub fn flush(&mut self) {
+        if self.buffer.max_doc() == 0 {
+            return;
+        }
+        let id = self.new_segment_id();
+        let segment = mem::replace(&mut self.buffer, Segment::new(id));
+        let live_docs = mem::take(&mut self.buffer_live_docs);
+        self.version += 1;
+        self.segments.push(SegmentEntry {
+            segment: Arc::new(segment),
+            live_docs: Arc::new(live_docs),
+            merging: false,
+        });
+        self.maybe_merge();
+    }
 
-        while i < p1.len() {
-            res.push(p1[i]);
-            i += 1;
+    /// Flushes the indexing buffer to a small segment so that its documents
+    /// become visible to readers, and publishes a new snapshot for the next
+    /// searchers
+    pub fn refresh(&mut self) {
+        self.flush();
+        self.install_merges(false);
+        if self.snapshot.version() != self.version {
+            let segments = self
+                .segments
+                .iter()
+                .map(|entry| (entry.segment.clone(), entry.live_docs.clone()))
+                .collect();
+            self.snapshot = Arc::new(IndexReader::new(segments, &self.key_field, self.version));
         }
-        while j < p2.len() {
-            res.push(p2[j]);
-            j += 1;
+        self.last_refresh = Instant::now();
+    }
+
+    /// Refreshes if the refresh interval elapsed since the last refresh
+    pub fn maybe_refresh(&mut self) -> bool {
+        match self.refresh_interval {
+            Some(interval) if self.last_refresh.elapsed() >= interval => {
+                self.refresh();
+                true
+            }
+            _ => false,
         }
-        res.dedup();
-        res
     }
 
-    fn lowercase_filter(tokens: Vec<String>) -> Vec<String> {
-        let mut res: Vec<String> = Vec::with_capacity(tokens.len());
+    /// Reader over the index as of the last refresh, refreshing first if
+    /// the refresh interval elapsed
+    pub fn reader(&mut self) -> IndexReader {
+        self.maybe_refresh();
+        self.snapshot.as_ref().clone()
+    }
+
+    /// Searcher over the index as of the last refresh, refreshing first if
+    /// the refresh interval elapsed
+    pub fn searcher(&mut self) -> Searcher {
+        self.maybe_refresh();
+        Searcher::new(self.snapshot.clone())
+    }
 
-        for token in tokens {
-            res.push(token.to_lowercase());
+    /// Starts the merges picked by the merge policy, each in its own thread.
+    /// Segments with no live documents left are dropped right away.
+    pub fn maybe_merge(&mut self) {
+        self.install_merges(false);
+        self.segments
+            .retain(|entry| entry.merging || entry.live_docs.num_live() > 0);
+        let infos: Vec<SegmentInfo> = self
+            .segments
+            .iter()
+            .filter(|entry| !entry.merging)
+            .map(|entry| SegmentInfo {
+                id: entry.segment.id(),
+                max_doc: entry.segment.max_doc(),
+                num_deleted: entry.live_docs.num_deleted(),
+                size_in_bytes: entry.segment.size_in_bytes(),
+            })
+            .collect();
+        for merge in self.merge_policy.find_merges(&infos) {
+            let mut sources = Vec::with_capacity(merge.len());
+            for entry in self.segments.iter_mut() {
+                if merge.contains(&entry.segment.id()) && !entry.merging {
+                    entry.merging = true;
+                    sources.push((entry.segment.clone(), entry.live_docs.clone()));
+                }
+            }
+            let id = self.new_segment_id();
+            let snapshot = sources.clone();
+            let handle = thread::spawn(move || {
+                let sources: Vec<(&Segment, &LiveDocs)> = snapshot
+                    .iter()
+                    .map(|(segment, live_docs)| (segment.as_ref(), live_docs.as_ref()))
+                    .collect();
+                Segment::merge(id, &sources)
+            });
+            self.merges.push(RunningMerge {
+                sources: sources
+                    .into_iter()
+                    .map(|(segment, live_docs)| (segment.id(), live_docs))
+                    .collect(),
+                handle,
+            });
         }
-        res
     }
 
-    fn tokenizer(phrase: String) -> Vec<String> {
-        phrase
-            .split_terminator(|c: char| !c.is_alphanumeric())
-            .map(|s| s.to_string())
-            .collect()
+    /// Blocks until running merges are done and installs them. Returns the
+    /// error of the first merge that failed since the last call, or commit.
+    pub fn wait_for_merges(&mut self) -> io::Result<()> {
+        self.install_merges(true);
+        self.take_merge_error()
     }
 
-    fn sorted_insert(p: &mut Vec<usize>, v: usize) {
-        let mut low = 0;
-        let mut high = p.len() - 1;
-        let ipos;
+    fn take_merge_error(&mut self) -> io::Result<()> {
+        match self.merge_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 
-        while low <= high {
-            let mid = (high + low) / 2;
-            if p[mid] < v {
-                low = mid + 1
-            } else if p[mid] > v {
-                high = mid - 1
+    // swaps finished merges in, deletions that happened while merging are
+    // carried over to the merged segment. Failed merges are dropped, their
+    // segments stay as they were.
+    fn install_merges(&mut self, wait: bool) {
+        let mut i = 0;
+        while i < self.merges.len() {
+            if !wait && !self.merges[i].handle.is_finished() {
+                i += 1;
+                continue;
+            }
+            let merge = self.merges.remove(i);
+            let (merged, doc_maps) = match merge.handle.join().expect("merge thread panicked") {
+                Ok(merged) => merged,
+                Err(err) => {
+                    log::error!("merge failed: {}", err);
+                    for entry in self.segments.iter_mut() {
+                        if merge
+                            .sources
+                            .iter()
+                            .any(|(id, _)| *id == entry.segment.id())
+                        {
+                            entry.merging = false;
+                        }
+                    }
+                    self.merge_error.get_or_insert(err);
+                    continue;
+                }
+            };
+            self.version += 1;
+            let mut live_docs = LiveDocs::new();
+            for doc_id in 0..merged.max_doc() {
+                live_docs.add(doc_id);
+            }
+            let mut position = self.segments.len();
+            for ((id, snapshot), doc_map) in merge.sources.iter().zip(doc_maps.iter()) {
+                let idx = match self.segments.iter().position(|e| e.segment.id() == *id) {
+                    Some(idx) => idx,
+                    None => panic!("merged segment {} not found", id),
+                };
+                let entry = self.segments.remove(idx);
+                for doc_id in snapshot.iter() {
+                    if !entry.live_docs.is_live(doc_id) {
+                        if let Some(new_id) = doc_map[doc_id] {
+                            live_docs.delete(new_id);
+                        }
+                    }
+                }
+                position = position.min(idx);
+            }
+            if live_docs.num_live() > 0 {
+                self.segments.insert(
+                    position.min(self.segments.len()),
+                    SegmentEntry {
+                        segment: Arc::new(merged),
+                        live_docs: Arc::new(live_docs),
+                        merging: false,
+                    },
+                );
             }
         }
-        if low > high {
-            ipos = high + 1;
-        } else {
-            ipos = low
+    }
+
+    pub fn set_key_field(&mut self, field: &str) {
+        self.key_field = field.to_string();
+    }
+
+    pub fn set_max_buffered_docs(&mut self, max_buffered_docs: usize) {
+        self.max_buffered_docs = max_buffered_docs;
+    }
+
+    pub fn set_merge_policy(&mut self, merge_policy: Box<dyn MergePolicy>) {
+        self.merge_policy = merge_policy;
+    }
+
+    /// Documents are made visible to readers once that long passed since the
+    /// last refresh, checked on every change and whenever a reader or
+    /// searcher is taken. Refreshes only happen on explicit calls when unset.
+    pub fn set_refresh_interval(&mut self, refresh_interval: Option<Duration>) {
+        self.refresh_interval = refresh_interval;
+    }
+
+    pub fn new() -> InvertedIndex {
+        InvertedIndex {
+            segments: Vec::new(),
+            buffer: Segment::new(0),
+            buffer_live_docs: LiveDocs::new(),
+            next_segment_id: 1,
+            key_field: DEFAULT_KEY_FIELD.to_string(),
+            max_buffered_docs: DEFAULT_MAX_BUFFERED_DOCS,
+            merge_policy: Box::new(TieredMergePolicy::default()),
+            merges: Vec::new(),
+            version: 0,
+            snapshot: Arc::default(),
+            refresh_interval: None,
+            last_refresh: Instant::now(),
+            directory: None,
+            generation: 0,
+            merge_error: None,
         }
-        let upper_bound = v + 1;
-        p.splice(ipos..ipos, v..upper_bound);
+    }
+
+    /// Number of segments, the indexing buffer excluded
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Number of live documents
+    pub fn num_docs(&self) -> usize {
+        self.view().num_docs()
+    }
+
+    pub fn search(&self, query: &str) -> Option<Vec<usize>> {
+        self.view().search(query)
+    }
+
+    /// Searches and returns a single page of hits
+    pub fn search_with_options(&self, query: &str, options: &SearchOptions) -> Option<TopDocs> {
+        self.view().search_with_options(query, options)
+    }
+
+    /// Computes `aggs` over the documents matching a search
+    pub fn aggregate(&self, docs: &[usize], aggs: &[Aggregation]) -> Vec<AggregationResult> {
+        self.view().aggregate(docs, aggs)
+    }
+
+    /// Doc id of the live document identified by `key`
+    pub fn doc_id(&self, key: &str) -> Option<usize> {
+        self.view().doc_id(key)
+    }
+
+    pub fn is_live(&self, doc_id: usize) -> bool {
+        self.view().is_live(doc_id)
+    }
+
+    pub fn keyword(&self, doc_id: usize, field: &str) -> Option<&str> {
+        self.view().keyword(doc_id, field)
+    }
+
+    pub fn numeric(&self, doc_id: usize, field: &str) -> Option<i64> {
+        self.view().numeric(doc_id, field)
+    }
+
+    fn view(&self) -> SegmentsView<'_> {
+        SegmentsView::new(self.readers().collect(), &self.key_field)
+    }
+
+    /// Flushed segments followed by the indexing buffer, in doc id order
+    fn readers(&self) -> impl Iterator<Item = (&Segment, &LiveDocs)> {
+        self.segments
+            .iter()
+            .map(|entry| (entry.segment.as_ref(), entry.live_docs.as_ref()))
+            .chain(std::iter::once((&self.buffer, &self.buffer_live_docs)))
+    }
+
+    fn buffer_base(&self) -> usize {
+        self.segments.iter().map(|e| e.segment.max_doc()).sum()
+    }
+
+    fn new_segment_id(&mut self) -> u64 {
+        let id = self.next_segment_id;
+        self.next_segment_id += 1;
+        id
+    }
+}
+
+fn read_json<T: DeserializeOwned>(fp: &str) -> io::Result<T> {
+    let br = BufReader::new(File::open(fp)?);
+    Ok(serde_json::from_reader(br)?)
+}
+
+impl Default for InvertedIndex {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::doc_values::{SortField, SortOrder};
+    use crate::document::LENGTH_FIELD;
+    use crate::merge_policy::{LogByteSizeMergePolicy, NoMergePolicy};
+    use std::env;
+    use uuid::Uuid;
+
+    fn doc(id: &str, text: &str) -> Document {
+        let mut doc = Document::new();
+        doc.add_keyword("id", id).unwrap();
+        doc.add_text("body", text).unwrap();
+        doc
+    }
+
+    #[test]
+    fn test_open_unsegmented_index() {
+        let path = env::temp_dir().join(format!("fts-{}.json", Uuid::new_v4()));
+        let fp = path.to_str().unwrap();
+        fs::write(&path, r#"{"boat": [0, 2], "red": [0, 1], "car": [3]}"#).unwrap();
+        let mut ii = InvertedIndex::new();
+        // doc 4 has no token, it still counts as a document
+        ii.open_unsegmented(fp, 5).unwrap();
+        assert_eq!(ii.num_segments(), 1);
+        assert_eq!(ii.search("boat"), Some(vec![0, 2]));
+        assert_eq!(ii.search("red AND NOT boat"), Some(vec![1]));
+        assert_eq!(ii.search("NOT red"), Some(vec![2, 3, 4]));
+        assert_eq!(ii.add_document(&doc("e", "red car")).unwrap(), 5);
+        assert_eq!(ii.search("car"), Some(vec![3, 5]));
+
+        let err = InvertedIndex::new().open(fp).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = InvertedIndex::new().open_unsegmented(fp, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, r#"{"boat": "not postings"}"#).unwrap();
+        let err = InvertedIndex::new().open_unsegmented(fp, 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_search() {}
+
+    #[test]
+    fn test_search_sorted() {
+        let docs = [
+            WikiDoc::new("Sailing", "u0", "a boat on the sea"),
+            WikiDoc::new("Anchor", "u1", "the boat anchor"),
+            WikiDoc::new("Boat", "u2", "boat"),
+            WikiDoc::new("Tree", "u3", "a tree"),
+        ];
+        let mut ii = InvertedIndex::new();
+        for doc in docs.iter() {
+            ii.add_wiki_doc(doc).unwrap();
+        }
+
+        let doc_ids = |options: &SearchOptions| -> Vec<usize> {
+            let top_docs = ii.search_with_options("boat", options).unwrap();
+            top_docs.hits.iter().map(|hit| hit.doc_id).collect()
+        };
+        let options = SearchOptions {
+            sort: vec![SortField::new("title", SortOrder::Asc)],
+            ..Default::default()
+        };
+        assert_eq!(doc_ids(&options), vec![1, 2, 0]);
+
+        let options = SearchOptions {
+            sort: vec![SortField::new(LENGTH_FIELD, SortOrder::Desc)],
+            ..Default::default()
+        };
+        assert_eq!(doc_ids(&options), vec![0, 1, 2]);
+        assert_eq!(doc_ids(&SearchOptions::default()), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_search_paging() {
+        let mut ii = InvertedIndex::new();
+        ii.set_merge_policy(Box::new(NoMergePolicy));
+        ii.set_max_buffered_docs(4);
+        for i in 0..25 {
+            // ties are broken on urls, padded to sort like doc ids
+            let doc = WikiDoc::new(
+                &format!("title {:02}", 24 - i),
+                &format!("{:02}", i),
+                "boat",
+            );
+            ii.add_wiki_doc(&doc).unwrap();
+        }
+        assert_eq!(ii.num_segments(), 6);
+        let doc_ids = |top_docs: &TopDocs| -> Vec<usize> {
+            top_docs.hits.iter().map(|hit| hit.doc_id).collect()
+        };
+
+        let options = SearchOptions {
+            offset: 10,
+            limit: Some(5),
+            ..Default::default()
+        };
+        let page = ii.search_with_options("boat", &options).unwrap();
+        assert_eq!(page.total_hits, 25);
+        assert_eq!(doc_ids(&page), vec![10, 11, 12, 13, 14]);
+
+        for sort in [vec![], vec![SortField::new("title", SortOrder::Asc)]] {
+            let mut options = SearchOptions {
+                sort: sort.clone(),
+                limit: Some(10),
+                ..Default::default()
+            };
+            let mut seen = Vec::new();
+            loop {
+                let page = ii.search_with_options("boat", &options).unwrap();
+                assert_eq!(page.total_hits, 25);
+                if page.hits.is_empty() {
+                    break;
+                }
+                assert!(page.hits.len() <= 10);
+                seen.extend(doc_ids(&page));
+                options.search_after = page.search_after();
+            }
+            let mut expected: Vec<usize> = (0..25).collect();
+            if !sort.is_empty() {
+                expected.reverse();
+            }
+            assert_eq!(seen, expected);
+        }
+
+        let options = SearchOptions {
+            offset: 30,
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert!(ii
+            .search_with_options("boat", &options)
+            .unwrap()
+            .hits
+            .is_empty());
+    }
+
+    #[test]
+    fn test_search_after_merge() {
+        for sort in [vec![], vec![SortField::new(LENGTH_FIELD, SortOrder::Asc)]] {
+            let mut ii = InvertedIndex::new();
+            ii.set_key_field("id");
+            ii.set_merge_policy(Box::new(NoMergePolicy));
+            ii.set_max_buffered_docs(1);
+            for (id, len) in [("a", 1), ("b", 2), ("c", 2), ("d", 2), ("e", 3), ("f", 3)] {
+                ii.add_document(&doc(id, &vec!["boat"; len].join(" ")))
+                    .unwrap();
+            }
+            let keys = |ii: &InvertedIndex, top_docs: &TopDocs| -> Vec<String> {
+                top_docs
+                    .hits
+                    .iter()
+                    .map(|hit| ii.keyword(hit.doc_id, "id").unwrap().to_string())
+                    .collect()
+            };
+            let mut options = SearchOptions {
+                sort,
+                limit: Some(3),
+                ..Default::default()
+            };
+            let page = ii.search_with_options("boat", &options).unwrap();
+            assert_eq!(keys(&ii, &page), vec!["a", "b", "c"]);
+            options.search_after = page.search_after();
+
+            // dropping the emptied segment of "a" moves every doc id down
+            ii.delete_document("a");
+            ii.maybe_merge();
+            assert_eq!(ii.doc_id("c"), Some(1));
+            let page = ii.search_with_options("boat", &options).unwrap();
+            assert_eq!(keys(&ii, &page), vec!["d", "e", "f"]);
+        }
+    }
+
+    #[test]
+    fn test_update_delete() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        assert_eq!(ii.add_document(&doc("a", "red boat")).unwrap(), 0);
+        assert_eq!(ii.add_document(&doc("b", "blue boat")).unwrap(), 1);
+        assert_eq!(ii.add_document(&doc("c", "red car")).unwrap(), 2);
+        assert_eq!(ii.search("boat"), Some(vec![0, 1]));
+
+        assert!(ii.delete_document("b"));
+        assert!(!ii.delete_document("b"));
+        assert!(!ii.delete_document("z"));
+        assert_eq!(ii.search("boat"), Some(vec![0]));
+        assert_eq!(ii.search("NOT boat"), Some(vec![2]));
+
+        assert_eq!(ii.update_document("a", &doc("a", "green car")).unwrap(), 3);
+        assert_eq!(ii.doc_id("a"), Some(3));
+        assert_eq!(ii.search("boat"), Some(vec![]));
+        assert_eq!(ii.search("car"), Some(vec![2, 3]));
+        assert_eq!(ii.numeric(3, LENGTH_FIELD), Some(2));
+
+        // adding a document with an existing key replaces it
+        assert_eq!(ii.add_document(&doc("c", "red bike")).unwrap(), 4);
+        assert_eq!(ii.search("red"), Some(vec![4]));
+        assert!(!ii.is_live(2));
+    }
+
+    #[test]
+    fn test_add_documents() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        ii.set_merge_policy(Box::new(NoMergePolicy));
+        ii.add_document(&doc("a", "red boat")).unwrap();
+        ii.add_document(&doc("b", "blue boat")).unwrap();
+        let docs = [
+            doc("c", "red car"),
+            doc("a", "green boat"),
+            doc("d", "blue car"),
+            doc("c", "red bike"),
+            doc("e", "green car"),
+        ];
+        assert_eq!(ii.add_documents(&docs, 3).unwrap(), 2);
+        assert_eq!(ii.num_docs(), 5);
+        assert_eq!(ii.doc_id("a"), Some(3));
+        assert_eq!(ii.doc_id("c"), Some(5));
+        assert_eq!(ii.search("car"), Some(vec![4, 6]));
+        assert_eq!(ii.search("boat"), Some(vec![1, 3]));
+        assert_eq!(ii.add_documents(&[], 3).unwrap(), 7);
+        assert_eq!(ii.add_document(&doc("f", "red car")).unwrap(), 7);
+        assert_eq!(ii.search("red"), Some(vec![5, 7]));
+    }
+
+    #[test]
+    fn test_field_type_conflict() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        let mut year = doc("a", "red boat");
+        year.add_numeric("year", 1999).unwrap();
+        ii.add_document(&year).unwrap();
+        ii.flush();
+        let mut keyword_year = doc("a", "blue boat");
+        keyword_year.add_keyword("year", "1999").unwrap();
+
+        let err = ii.add_document(&keyword_year).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = ii.update_document("a", &keyword_year).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = ii
+            .add_documents(&[doc("b", "red car"), keyword_year], 2)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // nothing was deleted nor added
+        assert_eq!(ii.num_docs(), 1);
+        assert_eq!(ii.search("red"), Some(vec![0]));
+
+        // documents of a batch must agree too
+        let mut ii = InvertedIndex::new();
+        let mut keyword_year = doc("b", "red car");
+        keyword_year.add_keyword("year", "1999").unwrap();
+        let err = ii.add_documents(&[year, keyword_year], 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ii.num_docs(), 0);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        for (id, text, category) in [
+            ("0", "a boat on the sea", "sport"),
+            ("1", "the boat anchor", "nautical"),
+            ("2", "a tree", "nature"),
+        ] {
+            let mut doc = doc(id, text);
+            doc.add_keyword("category", category).unwrap();
+            ii.add_document(&doc).unwrap();
+            ii.flush();
+        }
+
+        let hits = ii.search("a OR anchor").unwrap();
+        let aggs = ii.aggregate(
+            &hits,
+            &[
+                Aggregation::terms("category", 10),
+                Aggregation::stats(LENGTH_FIELD),
+            ],
+        );
+        assert_eq!(
+            aggs[0],
+            AggregationResult::Terms(vec![
+                ("nature".to_string(), 1),
+                ("nautical".to_string(), 1),
+                ("sport".to_string(), 1),
+            ])
+        );
+        match &aggs[1] {
+            AggregationResult::Stats(stats) => {
+                assert_eq!(stats.count, 3);
+                assert_eq!(stats.min, Some(2));
+                assert_eq!(stats.max, Some(5));
+            }
+            _ => panic!("expected stats"),
+        }
+    }
+
+    #[test]
+    fn test_merges() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        ii.set_max_buffered_docs(2);
+        ii.set_merge_policy(Box::new(LogByteSizeMergePolicy {
+            merge_factor: 3,
+            min_merge_bytes: 1 << 20,
+            max_merge_bytes: 1 << 30,
+        }));
+        let keys = |ii: &InvertedIndex, query: &str| -> Vec<String> {
+            let mut keys: Vec<String> = ii
+                .search(query)
+                .unwrap()
+                .into_iter()
+                .map(|doc_id| ii.keyword(doc_id, "id").unwrap().to_string())
+                .collect();
+            keys.sort();
+            keys
+        };
+        for i in 0..10 {
+            let text = if i % 2 == 0 { "even" } else { "odd" };
+            ii.add_document(&doc(&i.to_string(), text)).unwrap();
+            if i == 5 {
+                // deleted while merges may be running
+                ii.delete_document("0");
+                ii.delete_document("3");
+            }
+        }
+        ii.flush();
+        ii.wait_for_merges().unwrap();
+        ii.maybe_merge();
+        ii.wait_for_merges().unwrap();
+        assert!(ii.num_segments() < 5);
+        assert_eq!(ii.num_docs(), 8);
+        assert_eq!(keys(&ii, "even"), vec!["2", "4", "6", "8"]);
+        assert_eq!(keys(&ii, "odd"), vec!["1", "5", "7", "9"]);
+        assert_eq!(keys(&ii, "NOT odd"), vec!["2", "4", "6", "8"]);
+
+        ii.update_document("7", &doc("7", "even")).unwrap();
+        ii.delete_document("1");
+        ii.flush();
+        ii.wait_for_merges().unwrap();
+        assert_eq!(keys(&ii, "even"), vec!["2", "4", "6", "7", "8"]);
+        assert_eq!(keys(&ii, "odd"), vec!["5", "9"]);
+        let doc_id = ii.doc_id("7").unwrap();
+        assert_eq!(ii.keyword(doc_id, "id"), Some("7"));
+    }
+
+    #[test]
+    fn test_failed_merge() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let mut ii = InvertedIndex::open_directory(&dir).unwrap();
+        ii.set_key_field("id");
+        // segments that disagree on the type of a field can only come from
+        // files, documents are checked when added
+        let mut numeric_year = doc("a", "red boat");
+        numeric_year.add_numeric("year", 1999).unwrap();
+        let mut keyword_year = doc("b", "red car");
+        keyword_year.add_keyword("year", "1999").unwrap();
+        for (id, doc) in [(1, numeric_year), (2, keyword_year)] {
+            let mut segment = Segment::new(id);
+            segment.add_document(&doc, "id").unwrap();
+            ii.segments.push(SegmentEntry {
+                segment: Arc::new(segment),
+                live_docs: Arc::new(LiveDocs::all_live(1)),
+                merging: false,
+            });
+        }
+        ii.set_merge_policy(Box::new(LogByteSizeMergePolicy {
+            merge_factor: 2,
+            min_merge_bytes: 1 << 20,
+            max_merge_bytes: 1 << 30,
+        }));
+        ii.maybe_merge();
+        let err = ii.wait_for_merges().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(ii.wait_for_merges().is_ok());
+        // the segments are left as they were and can be merged again
+        assert_eq!(ii.num_segments(), 2);
+        assert!(ii.segments.iter().all(|entry| !entry.merging));
+        assert_eq!(ii.search("red"), Some(vec![0, 1]));
+        ii.maybe_merge();
+        assert_eq!(ii.merges.len(), 1);
+        ii.install_merges(true);
+        let err = ii.commit().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ii.generation(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        ii.add_document(&doc("a", "red boat")).unwrap();
+        let reader = ii.reader();
+        assert_eq!(reader.num_docs(), 0);
+        assert_eq!(ii.search("boat"), Some(vec![0]));
+        assert!(reader.reopen(&mut ii).is_none());
+
+        ii.refresh();
+        let reader = reader.reopen(&mut ii).unwrap();
+        assert_eq!(reader.search("boat"), Some(vec![0]));
+
+        ii.delete_document("a");
+        ii.add_document(&doc("b", "blue boat")).unwrap();
+        assert_eq!(reader.num_docs(), 1);
+        assert_eq!(reader.keyword(0, "id"), Some("a"));
+
+        ii.set_refresh_interval(Some(Duration::ZERO));
+        ii.add_document(&doc("c", "red car")).unwrap();
+        let reader = reader.reopen(&mut ii).unwrap();
+        assert_eq!(reader.num_docs(), 2);
+        let keys: Vec<&str> = reader
+            .search("red OR boat")
+            .unwrap()
+            .into_iter()
+            .map(|doc_id| reader.keyword(doc_id, "id").unwrap())
+            .collect();
+        assert_eq!(keys, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_refresh_interval() {
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        ii.set_refresh_interval(Some(Duration::from_millis(200)));
+        ii.refresh();
+        let searcher = ii.searcher();
+        ii.add_document(&doc("a", "red boat")).unwrap();
+        assert_eq!(ii.reader().num_docs(), 0);
+        thread::sleep(Duration::from_millis(250));
+        // taking a searcher refreshes without another change
+        assert_eq!(ii.searcher().search("boat"), Some(vec![0]));
+        assert_eq!(ii.reader().search("boat"), Some(vec![0]));
+        assert_eq!(searcher.search("boat"), Some(vec![]));
+    }
+
+    #[test]
+    fn test_commit() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        assert!(InvertedIndex::new().commit().is_err());
+
+        let mut ii = InvertedIndex::open_directory(&dir).unwrap();
+        ii.set_key_field("id");
+        ii.add_document(&doc("a", "red boat")).unwrap();
+        ii.add_document(&doc("b", "blue boat")).unwrap();
+        ii.commit().unwrap();
+        ii.delete_document("a");
+        ii.commit().unwrap();
+        // not committed, lost on reopen
+        ii.add_document(&doc("c", "red car")).unwrap();
+        drop(ii);
+
+        let mut ii = InvertedIndex::open_directory(&dir).unwrap();
+        assert_eq!(ii.num_docs(), 1);
+        assert_eq!(ii.reader().num_docs(), 1);
+        let doc_id = ii.doc_id("b").unwrap();
+        assert_eq!(ii.search("boat"), Some(vec![doc_id]));
+        assert_eq!(ii.search("car"), Some(vec![]));
+        assert_eq!(ii.generation(), 2);
+
+        // a torn commit point is rolled back to the last good commit, and
+        // kept
+        fs::write(dir.join("segments_3"), "{\"generation\":3,\"key_fi").unwrap();
+        let mut ii = InvertedIndex::open_directory(&dir).unwrap();
+        assert_eq!(ii.generation(), 2);
+        assert_eq!(ii.num_docs(), 1);
+        assert!(dir.join("segments_3").exists());
+        ii.add_document(&doc("c", "red car")).unwrap();
+        ii.commit().unwrap();
+        // committed past it, the torn commit is now older and deleted
+        assert_eq!(ii.generation(), 4);
+        assert!(!dir.join("segments_3").exists());
+        let ii = InvertedIndex::open_directory(&dir).unwrap();
+        assert_eq!(ii.num_docs(), 2);
+        assert_eq!(ii.generation(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_directory_error() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        let mut ii = InvertedIndex::open_directory(&dir).unwrap();
+        ii.set_key_field("id");
+        ii.add_document(&doc("a", "red boat")).unwrap();
+        ii.commit().unwrap();
+        ii.add_document(&doc("b", "blue boat")).unwrap();
+        ii.commit().unwrap();
+        drop(ii);
+
+        // a missing file is not a torn write, nothing is rolled back
+        let commit = CommitPoint::load(&dir, 2).unwrap().0;
+        let live_docs = dir.join(commit.live_docs_file_name(commit.segments[0]));
+        fs::remove_file(&live_docs).unwrap();
+        let err = InvertedIndex::open_directory(&dir).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(dir.join("segments_2").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,52 @@
+//! Boolean retrieval over Wikipedia abstracts, and `Db`, a LevelDB style
+//! key-value store
+
+mod aggregations;
+mod analysis;
+mod arena;
+mod block;
+mod coding;
+mod commit;
+mod compaction;
+mod comparator;
+mod concurrent_skiplist;
+mod crc;
+mod cursor;
+mod db;
+mod doc_values;
+mod document;
+mod filename;
+mod index_reader;
+mod indexer;
+mod internal_key;
+mod inverted_index;
+mod live_docs;
+mod memtable;
+mod merge_policy;
+mod merger;
+mod postings;
+mod query_parser;
+mod search;
+mod searcher;
+mod segment;
+mod skiplist;
+mod table;
+mod table_cache;
+mod version;
+mod version_edit;
+mod version_set;
+mod wal;
+mod wiki;
+mod write_batch;
+
+pub use block::CompressionType;
+pub use compaction::{CompactionStats, CompactionStrategy, LeveledCompaction, UniversalCompaction};
+pub use comparator::{BytewiseComparator, Comparator, ReverseBytewiseComparator};
+pub use db::{Db, Options, ReadOptions, Snapshot, WriteOptions};
+pub use inverted_index::InvertedIndex;
+pub use merge_policy::{
+    LogByteSizeMergePolicy, MergePolicy, NoMergePolicy, SegmentInfo, TieredMergePolicy,
+};
+pub use search::SearchOptions;
+pub use wiki::{parse_documents, WikiDoc};
+pub use write_batch::WriteBatch;
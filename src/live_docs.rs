@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+/// Bitmap of the documents that haven't been deleted
+///
+/// Deleting a document only clears its bit, postings and doc values are left
+/// untouched and deleted documents are filtered out of search results.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct LiveDocs {
+    bits: Vec<u64>,
+    len: usize,
+    deleted: usize,
+}
+
+impl LiveDocs {
+    pub fn new() -> LiveDocs {
+        LiveDocs {
+            bits: Vec::new(),
+            len: 0,
+            deleted: 0,
+        }
+    }
+
+    /// Doc ids `0..max_doc`, all live
+    pub fn all_live(max_doc: usize) -> LiveDocs {
+        let mut live_docs = LiveDocs::new();
+        (0..max_doc).for_each(|doc_id| live_docs.add(doc_id));
+        live_docs
+    }
+
+    /// Number of doc ids tracked, live or deleted
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn num_deleted(&self) -> usize {
+        self.deleted
+    }
+
+    pub fn num_live(&self) -> usize {
+        self.len - self.deleted
+    }
+
+    /// Marks `doc_id` as live, growing the bitmap if needed.
+    /// Doc ids in between are considered deleted.
+    pub fn add(&mut self, doc_id: usize) {
+        if doc_id >= self.len {
+            self.deleted += doc_id - self.len;
+            self.len = doc_id + 1;
+            self.bits.resize(self.len.div_ceil(64), 0);
+        } else if self.is_live(doc_id) {
+            return;
+        } else {
+            self.deleted -= 1;
+        }
+        self.bits[doc_id / 64] |= 1 << (doc_id % 64);
+    }
+
+    pub fn is_live(&self, doc_id: usize) -> bool {
+        doc_id < self.len && self.bits[doc_id / 64] & (1 << (doc_id % 64)) != 0
+    }
+
+    /// Returns `false` if the document was already deleted
+    pub fn delete(&mut self, doc_id: usize) -> bool {
+        if !self.is_live(doc_id) {
+            return false;
+        }
+        self.bits[doc_id / 64] &= !(1 << (doc_id % 64));
+        self.deleted += 1;
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |doc_id| self.is_live(*doc_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_live_docs() {
+        let mut ld = LiveDocs::new();
+        for i in 0..70 {
+            ld.add(i);
+        }
+        ld.add(100);
+        assert_eq!(ld.len(), 101);
+        assert_eq!(ld.num_deleted(), 30);
+        assert!(ld.is_live(64));
+        assert!(!ld.is_live(80));
+        assert!(ld.delete(64));
+        assert!(!ld.delete(64));
+        assert!(!ld.delete(200));
+        assert!(!ld.is_live(64));
+        assert_eq!(ld.num_live(), 70);
+        assert_eq!(
+            ld.iter().filter(|d| *d >= 60).collect::<Vec<_>>(),
+            vec![60, 61, 62, 63, 65, 66, 67, 68, 69, 100]
+        );
+    }
+}
@@ -1,11 +1,8 @@
-mod inverted_index;
-mod query_parser;
-mod skiplist;
-mod wiki;
-
-use inverted_index::InvertedIndex;
+use boolean_retrieval::{parse_documents, InvertedIndex, SearchOptions, WikiDoc};
+use std::collections::HashMap;
 use std::io::{stdin, stdout, Write};
-use wiki::WikiDoc;
+
+const PAGE_SIZE: usize = 10;
 
 fn naive_search<'a>(query: &'a str, docs: &'a Vec<WikiDoc>) -> Vec<&'a WikiDoc> {
     let unwanted: &[_] = &[' ', '\t', '\n'];
@@ -20,24 +17,33 @@ fn naive_search<'a>(query: &'a str, docs: &'a Vec<WikiDoc>) -> Vec<&'a WikiDoc>
 }
 
 fn main() {
-    let docs = match wiki::parse_documents(
+    let docs = match parse_documents(
         "/Users/mc/Documents/boolean_retrieval/enwiki-latest-abstract1.xml",
     ) {
         Ok(d) => d,
         Err(e) => panic!("err : {}", e),
     };
-    let mut ii = InvertedIndex::new(docs.len());
-    match ii.open("inv_idx.json") {
+    let docs_by_url: HashMap<&str, &WikiDoc> =
+        docs.iter().map(|doc| (doc.url.as_str(), doc)).collect();
+    let mut ii = InvertedIndex::new();
+    // saved before the index was split in segments, use `open` once saved
+    // again below
+    match ii.open_unsegmented("inv_idx.json", docs.len()) {
         Ok(_) => println!("succesfully loaded inv idx - {} docs", docs.len()),
         Err(e) => panic!("err : {}", e),
     };
-    // for i in 0..docs.len() {
-    //     ii.add_wiki_doc(&docs[i], i);
-    // }
+    // let documents: Vec<Document> = docs.iter().map(Document::from).collect();
+    // ii.add_documents(&documents, thread::available_parallelism().map_or(1, |n| n.get()));
     // match ii.save("/Users/mc/Documents/boolean_retrieval/inv_idx.json") {
     //     Ok(_) => println!("saved file succesfully"),
     //     Err(e) => panic!("err : {}", e),
     // };
+    let mut query = String::new();
+    let mut options = SearchOptions {
+        limit: Some(PAGE_SIZE),
+        ..Default::default()
+    };
+    let mut shown = 0;
     loop {
         let mut s = String::new();
         print!("> ");
@@ -51,12 +57,48 @@ fn main() {
         //     println!("\n---- {} ----\n{}\n", r.title, r.r#abstract);
         // }
         // println!("{} hits", res.len());
-        match ii.search(&s) {
-            Some(results) => {
-                for r in &results {
-                    println!("\n---- {} ----\n{}\n", docs[*r].title, docs[*r].r#abstract);
+        if s.trim() == ":next" {
+            if query.is_empty() {
+                println!("No previous query\n");
+                continue;
+            }
+        } else {
+            query = s;
+            options.search_after = None;
+            shown = 0;
+        }
+        match ii.search_with_options(&query, &options) {
+            Some(top_docs) => {
+                if top_docs.hits.is_empty() && shown > 0 {
+                    println!("No more hits\n");
+                    continue;
+                }
+                for hit in &top_docs.hits {
+                    // doc ids change as segments get merged, urls don't. The
+                    // unsegmented index has no urls, its doc ids are
+                    // positions in the dump.
+                    let doc = match ii.keyword(hit.doc_id, "url") {
+                        Some(url) => docs_by_url.get(url).copied(),
+                        None => docs.get(hit.doc_id),
+                    };
+                    match doc {
+                        Some(doc) => {
+                            println!("\n---- {} ----\n{}\n", doc.title, doc.r#abstract)
+                        }
+                        None => println!("\n---- doc {} is not in the dump ----\n", hit.doc_id),
+                    }
+                }
+                println!(
+                    "hits {} to {} of {}\n",
+                    (shown + 1).min(top_docs.total_hits),
+                    shown + top_docs.hits.len(),
+                    top_docs.total_hits
+                );
+                shown += top_docs.hits.len();
+                if shown < top_docs.total_hits {
+                    println!("type :next for the next page\n");
                 }
-                println!("{} hits\n", results.len());
+                options.search_after = top_docs.search_after();
             }
             None => println!("Nothing found / Invalid query"),
         };
@@ -1,29 +1,281 @@
-mod skiplist;
-use serde::{Deserialize, Serialize};
-use skiplist::SkipList;
+use crate::comparator::Comparator;
+use crate::concurrent_skiplist::{ConcurrentSkipList, ALLOCATION_OVERHEAD};
+use crate::internal_key::{self, InternalKeyComparator, SequenceNumber, ValueType};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicUsize};
 
-const MAX_CAPACITY: usize = 1024 * 1024 * 1024;
+pub const MAX_CAPACITY: usize = 1024 * 1024 * 1024;
 
-struct MemTable {
-    skip_list: SkipList<Key>,
-    size: usize,
+/// Outcome of a `MemTable` lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetResult {
+    Found(Vec<u8>),
+    /// The most recent entry of the key is a deletion, older tables must
+    /// not be searched
+    Deleted,
+    NotFound,
 }
 
-// private
-impl MemTable {
-    fn new() -> MemTable {
-        skip_list = SkipList::new(16, 4);
+/// Entry of the skip list, ordered by its internal key
+#[derive(Debug)]
+struct Entry {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Borrow<[u8]> for Entry {
+    fn borrow(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+#[derive(Clone)]
+struct EntryComparator(InternalKeyComparator);
+
+impl Comparator<[u8]> for EntryComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.0.compare(a, b)
+    }
+
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+}
+
+impl Comparator<Entry> for EntryComparator {
+    fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        self.0.compare(&a.key, &b.key)
     }
 
-    fn write_to_wal(block: Block) {}
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+}
 
-    fn insert_skip_list(k: Vec<u8>) {}
+/// In-memory table of the most recent writes, like LevelDB's `MemTable`
+///
+/// Every put and delete is added as a new entry keyed by its internal key,
+/// so a lookup at a sequence number sees the state of the key at that
+/// point. Entries can be added while other threads read.
+pub struct MemTable {
+    skip_list: ConcurrentSkipList<Entry, EntryComparator>,
+    /// Bytes allocated for the keys and values, the skip list counts the
+    /// rest
+    size: AtomicUsize,
+    capacity: usize,
 }
 
-// pub
 impl MemTable {
-    pub fn add(key: Vec<u8>, value: Vec<u8>) {
-        self.write_to_wal();
-        self.insert_skip_list(key);
+    /// `capacity` is the approximate size in bytes past which the table is
+    /// full and should be flushed
+    pub fn with_capacity(comparator: InternalKeyComparator, capacity: usize) -> MemTable {
+        MemTable {
+            skip_list: ConcurrentSkipList::new(12, 4, EntryComparator(comparator)),
+            size: AtomicUsize::new(0),
+            capacity,
+        }
+    }
+
+    /// Adds an entry setting `key` to `value`, or deleting it, at
+    /// `sequence`. The value of a deletion is ignored. Returns false, adding
+    /// nothing, if the table already holds an entry of `key` at `sequence`.
+    pub fn add(
+        &self,
+        sequence: SequenceNumber,
+        value_type: ValueType,
+        key: &[u8],
+        value: &[u8],
+    ) -> bool {
+        let value = match value_type {
+            ValueType::Value => value.to_vec(),
+            ValueType::Deletion => Vec::new(),
+        };
+        let entry = Entry {
+            key: internal_key::encode(key, sequence, value_type),
+            value,
+        };
+        let size = buffer_size(&entry.key) + buffer_size(&entry.value);
+        let inserted = self.skip_list.insert(entry);
+        if inserted {
+            self.size.fetch_add(size, atomic::Ordering::Relaxed);
+        }
+        inserted
+    }
+
+    /// Looks up the most recent entry of `key` with a sequence number less
+    /// than or equal to `sequence`
+    pub fn get(&self, key: &[u8], sequence: SequenceNumber) -> GetResult {
+        let lookup_key = internal_key::encode(key, sequence, internal_key::VALUE_TYPE_FOR_SEEK);
+        let entry = match self.skip_list.find_greater_or_equal(lookup_key.as_slice()) {
+            Some(entry) => entry,
+            None => return GetResult::NotFound,
+        };
+        let parsed = internal_key::parse(&entry.key).expect("corrupted memtable key");
+        let user_comparator = self.comparator().user_comparator();
+        if user_comparator.compare(parsed.user_key, key) != Ordering::Equal {
+            return GetResult::NotFound;
+        }
+        match parsed.value_type {
+            ValueType::Value => GetResult::Found(entry.value.clone()),
+            ValueType::Deletion => GetResult::Deleted,
+        }
+    }
+
+    /// Entries as (internal key, value) in internal key order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.skip_list
+            .iter()
+            .map(|entry| (entry.key.as_slice(), entry.value.as_slice()))
+    }
+
+    pub fn comparator(&self) -> &InternalKeyComparator {
+        &self.skip_list.comparator().0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.skip_list.is_empty()
+    }
+
+    /// Approximate size in bytes of the table: its entries, the nodes of
+    /// the skip list holding them and the allocator overhead
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.skip_list.approximate_memory_usage() + self.size.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Whether the table reached its capacity
+    pub fn is_full(&self) -> bool {
+        self.approximate_memory_usage() >= self.capacity
+    }
+}
+
+/// Bytes allocated for `buffer`, nothing if empty
+fn buffer_size(buffer: &Vec<u8>) -> usize {
+    match buffer.capacity() {
+        0 => 0,
+        capacity => capacity + ALLOCATION_OVERHEAD,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+    use std::mem;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_get() {
+        let mem = MemTable::with_capacity(InternalKeyComparator::default(), MAX_CAPACITY);
+        assert!(mem.is_empty());
+        assert_eq!(mem.get(b"a", 10), GetResult::NotFound);
+        mem.add(1, ValueType::Value, b"a", b"a1");
+        mem.add(2, ValueType::Value, b"b", b"b2");
+        mem.add(3, ValueType::Value, b"a", b"a3");
+        mem.add(4, ValueType::Deletion, b"b", b"ignored");
+        mem.add(5, ValueType::Value, b"ab", b"ab5");
+        assert_eq!(mem.iter().count(), 5);
+        let size = mem.approximate_memory_usage();
+        assert!(!mem.add(3, ValueType::Value, b"a", b"other"));
+        assert_eq!(mem.iter().count(), 5);
+        assert_eq!(mem.approximate_memory_usage(), size);
+
+        assert_eq!(mem.get(b"a", 0), GetResult::NotFound);
+        assert_eq!(mem.get(b"a", 1), GetResult::Found(b"a1".to_vec()));
+        assert_eq!(mem.get(b"a", 2), GetResult::Found(b"a1".to_vec()));
+        assert_eq!(mem.get(b"a", 10), GetResult::Found(b"a3".to_vec()));
+        assert_eq!(mem.get(b"b", 3), GetResult::Found(b"b2".to_vec()));
+        assert_eq!(mem.get(b"b", 4), GetResult::Deleted);
+        assert_eq!(mem.get(b"ab", 4), GetResult::NotFound);
+        assert_eq!(mem.get(b"ab", 5), GetResult::Found(b"ab5".to_vec()));
+        assert_eq!(mem.get(b"c", 10), GetResult::NotFound);
+
+        let entries: Vec<_> = mem
+            .iter()
+            .map(|(key, value)| {
+                let parsed = internal_key::parse(key).unwrap();
+                (parsed.user_key, parsed.sequence, value)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (&b"a"[..], 3, &b"a3"[..]),
+                (b"a", 1, b"a1"),
+                (b"ab", 5, b"ab5"),
+                (b"b", 4, b""),
+                (b"b", 2, b"b2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mem = MemTable::with_capacity(InternalKeyComparator::default(), 4096);
+        let empty = mem.approximate_memory_usage();
+        assert_eq!(empty, mem.skip_list.approximate_memory_usage());
+        let mut sequence = 0;
+        while !mem.is_full() {
+            sequence += 1;
+            mem.add(
+                sequence,
+                ValueType::Value,
+                &sequence.to_be_bytes(),
+                &[0; 100],
+            );
+        }
+        // keys of 8 bytes and a tag, values of 100 bytes
+        let buffers_size = 8 + 8 + 100 + 2 * ALLOCATION_OVERHEAD;
+        let usage = mem.approximate_memory_usage();
+        assert_eq!(
+            usage,
+            mem.skip_list.approximate_memory_usage() + sequence as usize * buffers_size
+        );
+        // each entry also costs its node, with at least one link
+        let min_entry_size = buffers_size + mem::size_of::<Entry>() + 8 + 2 * ALLOCATION_OVERHEAD;
+        assert!(usage >= empty + sequence as usize * min_entry_size);
+        assert!(sequence as usize <= (4096 - empty).div_ceil(min_entry_size));
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let mem = Arc::new(MemTable::with_capacity(
+            InternalKeyComparator::default(),
+            MAX_CAPACITY,
+        ));
+        let rounds: u64 = if cfg!(miri) { 20 } else { 1_000 };
+        let reader = {
+            let mem = mem.clone();
+            thread::spawn(move || loop {
+                // keys are added in order, readers see a prefix of them
+                let keys: Vec<u64> = mem
+                    .iter()
+                    .map(|(key, _)| {
+                        let user_key = internal_key::user_key(key);
+                        u64::from_be_bytes(user_key.try_into().unwrap())
+                    })
+                    .collect();
+                assert!(keys.iter().copied().eq(1..=keys.len() as u64));
+                if let Some(last) = keys.last() {
+                    assert_eq!(
+                        mem.get(&last.to_be_bytes(), *last),
+                        GetResult::Found(b"value".to_vec())
+                    );
+                }
+                if keys.len() as u64 == rounds {
+                    break;
+                }
+            })
+        };
+        for i in 1..=rounds {
+            mem.add(i, ValueType::Value, &i.to_be_bytes(), b"value");
+        }
+        reader.join().unwrap();
+        assert_eq!(mem.iter().count() as u64, rounds);
     }
 }
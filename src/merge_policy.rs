@@ -0,0 +1,259 @@
+/// What a `MergePolicy` knows about a segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentInfo {
+    pub id: u64,
+    pub max_doc: usize,
+    pub num_deleted: usize,
+    pub size_in_bytes: usize,
+}
+
+/// Picks the segments to merge together
+///
+/// Segments are given in index order and only include segments that are not
+/// already being merged. Each returned merge is a list of segment ids,
+/// a segment belongs to at most one merge.
+pub trait MergePolicy: Send + Sync {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>>;
+}
+
+/// Merges segments of roughly equal size, allowing a budget of
+/// `segments_per_tier` segments per tier of size, much like Lucene's
+/// `TieredMergePolicy`. Merged segments need not be adjacent.
+pub struct TieredMergePolicy {
+    pub segments_per_tier: usize,
+    pub max_merge_at_once: usize,
+    /// Segments smaller than this are considered this size
+    pub floor_segment_bytes: usize,
+    pub max_merged_segment_bytes: usize,
+    /// Segments with more deleted docs than this are merged on their own
+    pub deletes_pct_allowed: f64,
+}
+
+/// Groups adjacent segments into levels by the log of their size and merges
+/// `merge_factor` segments of the same level, like Lucene's
+/// `LogByteSizeMergePolicy`.
+pub struct LogByteSizeMergePolicy {
+    pub merge_factor: usize,
+    /// Segments smaller than this all belong to the lowest level
+    pub min_merge_bytes: usize,
+    /// Segments larger than this are never merged
+    pub max_merge_bytes: usize,
+}
+
+/// Never merges, every flush adds a segment
+pub struct NoMergePolicy;
+
+impl SegmentInfo {
+    fn deleted_pct(&self) -> f64 {
+        if self.max_doc == 0 {
+            0.0
+        } else {
+            100.0 * self.num_deleted as f64 / self.max_doc as f64
+        }
+    }
+
+    /// Size in bytes, discounted by the ratio of deleted docs
+    fn live_size(&self) -> f64 {
+        self.size_in_bytes as f64 * (1.0 - self.deleted_pct() / 100.0)
+    }
+}
+
+impl Default for TieredMergePolicy {
+    fn default() -> Self {
+        TieredMergePolicy {
+            segments_per_tier: 10,
+            max_merge_at_once: 10,
+            floor_segment_bytes: 2 * 1024 * 1024,
+            max_merged_segment_bytes: 5 * 1024 * 1024 * 1024,
+            deletes_pct_allowed: 33.0,
+        }
+    }
+}
+
+impl TieredMergePolicy {
+    fn floor_size(&self, segment: &SegmentInfo) -> f64 {
+        segment.live_size().max(self.floor_segment_bytes as f64)
+    }
+
+    // number of segments the index may have given its total size
+    fn allowed_segment_count(&self, eligible: &[&SegmentInfo]) -> usize {
+        let mut bytes_left: f64 = eligible.iter().map(|s| self.floor_size(s)).sum();
+        let mut level_size = eligible
+            .iter()
+            .map(|s| self.floor_size(s))
+            .fold(f64::MAX, f64::min);
+        let segments_per_tier = self.segments_per_tier as f64;
+        let mut allowed = 0.0;
+        loop {
+            let segment_count = bytes_left / level_size;
+            if segment_count < segments_per_tier
+                || level_size >= self.max_merged_segment_bytes as f64
+            {
+                allowed += segment_count.ceil();
+                break;
+            }
+            allowed += segments_per_tier;
+            bytes_left -= segments_per_tier * level_size;
+            level_size *= self.max_merge_at_once as f64;
+        }
+        (allowed as usize).max(self.segments_per_tier)
+    }
+}
+
+impl MergePolicy for TieredMergePolicy {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>> {
+        let mut merges = Vec::new();
+        let mut eligible: Vec<&SegmentInfo> = Vec::new();
+        for segment in segments {
+            if segment.deleted_pct() > self.deletes_pct_allowed {
+                merges.push(vec![segment.id]);
+            } else if segment.live_size() < self.max_merged_segment_bytes as f64 / 2.0 {
+                eligible.push(segment);
+            }
+        }
+        if eligible.len() < 2 || eligible.len() <= self.allowed_segment_count(&eligible) {
+            return merges;
+        }
+        // largest first, so that windows group segments of similar size
+        eligible.sort_by(|a, b| b.live_size().partial_cmp(&a.live_size()).unwrap());
+        let window = self.max_merge_at_once.min(eligible.len());
+        let mut best: Option<(f64, usize)> = None;
+        for start in 0..=eligible.len() - window {
+            let candidate = &eligible[start..start + window];
+            let total: f64 = candidate.iter().map(|s| s.live_size()).sum();
+            if total > self.max_merged_segment_bytes as f64 {
+                continue;
+            }
+            // skew is 1 / window for perfectly balanced merges
+            let floored: f64 = candidate.iter().map(|s| self.floor_size(s)).sum();
+            let skew = self.floor_size(candidate[0]) / floored;
+            if best.is_none_or(|(score, _)| skew <= score) {
+                best = Some((skew, start));
+            }
+        }
+        if let Some((_, start)) = best {
+            merges.push(
+                eligible[start..start + window]
+                    .iter()
+                    .map(|s| s.id)
+                    .collect(),
+            );
+        }
+        merges
+    }
+}
+
+impl Default for LogByteSizeMergePolicy {
+    fn default() -> Self {
+        LogByteSizeMergePolicy {
+            merge_factor: 10,
+            min_merge_bytes: 1024 * 1024,
+            max_merge_bytes: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl MergePolicy for LogByteSizeMergePolicy {
+    fn find_merges(&self, segments: &[SegmentInfo]) -> Vec<Vec<u64>> {
+        let norm = (self.merge_factor as f64).ln();
+        let level_floor = (self.min_merge_bytes as f64).ln() / norm;
+        let levels: Vec<f64> = segments
+            .iter()
+            .map(|s| s.live_size().max(self.min_merge_bytes as f64).ln() / norm)
+            .collect();
+
+        let mut merges = Vec::new();
+        let mut start = 0;
+        while start < segments.len() {
+            let max_level = levels[start..].iter().cloned().fold(f64::MIN, f64::max);
+            let level_bottom = if max_level <= level_floor {
+                -1.0
+            } else {
+                (max_level - 0.75).max(level_floor)
+            };
+            // the level spans up to the last segment that belongs to it
+            let mut upto = segments.len() - 1;
+            while upto > start && levels[upto] < level_bottom {
+                upto -= 1;
+            }
+            let mut end = start + self.merge_factor;
+            while end <= upto + 1 {
+                let candidate = &segments[start..end];
+                if candidate
+                    .iter()
+                    .all(|s| s.live_size() <= self.max_merge_bytes as f64)
+                {
+                    merges.push(candidate.iter().map(|s| s.id).collect());
+                }
+                start = end;
+                end = start + self.merge_factor;
+            }
+            start = upto + 1;
+        }
+        merges
+    }
+}
+
+impl MergePolicy for NoMergePolicy {
+    fn find_merges(&self, _segments: &[SegmentInfo]) -> Vec<Vec<u64>> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segments(sizes: &[usize]) -> Vec<SegmentInfo> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| SegmentInfo {
+                id: i as u64,
+                max_doc: 100,
+                num_deleted: 0,
+                size_in_bytes: *size,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tiered() {
+        let policy = TieredMergePolicy::default();
+        assert!(policy.find_merges(&segments(&[1000; 10])).is_empty());
+
+        let mut infos = segments(&[1000; 12]);
+        infos[0].size_in_bytes = 3 * 1024 * 1024;
+        let merges = policy.find_merges(&infos);
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].len(), 10);
+        assert!(!merges[0].contains(&0));
+
+        infos[3].num_deleted = 50;
+        let merges = policy.find_merges(&infos);
+        assert_eq!(merges[0], vec![3]);
+    }
+
+    #[test]
+    fn test_log_byte_size() {
+        let policy = LogByteSizeMergePolicy {
+            merge_factor: 3,
+            min_merge_bytes: 10,
+            max_merge_bytes: 1 << 20,
+        };
+        assert!(policy.find_merges(&segments(&[5, 5])).is_empty());
+        assert_eq!(
+            policy.find_merges(&segments(&[5, 5, 5, 5, 5, 5, 5])),
+            vec![vec![0, 1, 2], vec![3, 4, 5]]
+        );
+        // the large segment is its own level, small ones are merged
+        assert_eq!(
+            policy.find_merges(&segments(&[10_000, 5, 5, 5])),
+            vec![vec![1, 2, 3]]
+        );
+        assert_eq!(
+            policy.find_merges(&segments(&[2 << 20, 2 << 20, 2 << 20])),
+            Vec::<Vec<u64>>::new()
+        );
+    }
+}
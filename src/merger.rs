@@ -0,0 +1,143 @@
+use crate::comparator::Comparator;
+use crate::cursor::Cursor;
+use std::cmp::Ordering;
+use std::io;
+use std::sync::Arc;
+
+/// Cursor over the entries of several cursors, in the order of
+/// `comparator`
+///
+/// Entries with equal keys are all returned, those of the first children
+/// first.
+pub struct MergingCursor<'a> {
+    comparator: Arc<dyn Comparator<[u8]>>,
+    children: Vec<Box<dyn Cursor + 'a>>,
+    /// Child holding the smallest key
+    current: Option<usize>,
+}
+
+impl<'a> MergingCursor<'a> {
+    pub fn new(
+        comparator: Arc<dyn Comparator<[u8]>>,
+        children: Vec<Box<dyn Cursor + 'a>>,
+    ) -> MergingCursor<'a> {
+        MergingCursor {
+            comparator,
+            children,
+            current: None,
+        }
+    }
+
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if !child.valid() {
+                continue;
+            }
+            let is_smaller = smallest.is_none_or(|j| {
+                self.comparator.compare(child.key(), self.children[j].key()) == Ordering::Less
+            });
+            if is_smaller {
+                smallest = Some(i);
+            }
+        }
+        self.current = smallest;
+    }
+}
+
+impl Cursor for MergingCursor<'_> {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek_to_first(&mut self) {
+        for child in self.children.iter_mut() {
+            child.seek_to_first();
+        }
+        self.find_smallest();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        for child in self.children.iter_mut() {
+            child.seek(target);
+        }
+        self.find_smallest();
+    }
+
+    fn next(&mut self) {
+        let current = self.current.expect("next on an invalid cursor");
+        self.children[current].next();
+        self.find_smallest();
+    }
+
+    fn key(&self) -> &[u8] {
+        let current = self.current.expect("key of an invalid cursor");
+        self.children[current].key()
+    }
+
+    fn value(&self) -> &[u8] {
+        let current = self.current.expect("value of an invalid cursor");
+        self.children[current].value()
+    }
+
+    fn status(&mut self) -> io::Result<()> {
+        for child in self.children.iter_mut() {
+            child.status()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::comparator::BytewiseComparator;
+
+    #[test]
+    fn test_merging_cursor() {
+        let comparator: Arc<dyn Comparator<[u8]>> = Arc::new(BytewiseComparator);
+        let blocks: Vec<_> = [&["a", "d", "e"][..], &[], &["b", "d", "f", "g"]]
+            .iter()
+            .enumerate()
+            .map(|(i, keys)| {
+                let mut builder = BlockBuilder::default();
+                for key in keys.iter() {
+                    builder.add(key.as_bytes(), i.to_string().as_bytes());
+                }
+                crate::block::Block::new(builder.finish()).unwrap()
+            })
+            .collect();
+        let children = blocks
+            .iter()
+            .map(|block| Box::new(block.cursor(comparator.clone())) as Box<dyn Cursor>)
+            .collect();
+        let mut cursor = MergingCursor::new(comparator, children);
+        assert!(!cursor.valid());
+        cursor.seek_to_first();
+        let mut entries = Vec::new();
+        while cursor.valid() {
+            entries.push((cursor.key().to_vec(), cursor.value().to_vec()));
+            cursor.next();
+        }
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = [
+            ("a", "0"),
+            ("b", "2"),
+            ("d", "0"),
+            ("d", "2"),
+            ("e", "0"),
+            ("f", "2"),
+            ("g", "2"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(entries, expected);
+        cursor.seek(b"c");
+        assert_eq!(cursor.key(), b"d");
+        assert_eq!(cursor.value(), b"0");
+        cursor.seek(b"h");
+        assert!(!cursor.valid());
+        assert!(cursor.status().is_ok());
+    }
+}
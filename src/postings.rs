@@ -0,0 +1,85 @@
+//! Boolean operations over sorted posting lists of doc ids
+
+/// Doc ids of `p1` missing from `p2`
+pub fn intersect_not(p1: &[usize], p2: &[usize]) -> Vec<usize> {
+    let mut res: Vec<usize> = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < p1.len() && j < p2.len() {
+        if p1[i] == p2[j] {
+            i += 1;
+            j += 1;
+        } else if p1[i] < p2[j] {
+            res.push(p1[i]);
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    while i < p1.len() {
+        res.push(p1[i]);
+        i += 1;
+    }
+    res
+}
+
+pub fn intersect(p1: &[usize], p2: &[usize]) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < p1.len() && j < p2.len() {
+        if p1[i] == p2[j] {
+            res.push(p1[i]);
+            i += 1;
+            j += 1;
+        } else if p1[i] < p2[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    res
+}
+
+/// Union of `p1` and `p2`
+pub fn merge_postings(p1: &[usize], p2: &[usize]) -> Vec<usize> {
+    let mut res = Vec::with_capacity(p1.len() + p2.len());
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < p1.len() && j < p2.len() {
+        if p1[i] == p2[j] {
+            res.push(p1[i]);
+            i += 1;
+            j += 1;
+        } else if p1[i] < p2[j] {
+            res.push(p1[i]);
+            i += 1;
+        } else {
+            res.push(p2[j]);
+            j += 1;
+        }
+    }
+    res.extend_from_slice(&p1[i..]);
+    res.extend_from_slice(&p2[j..]);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_postings() {
+        let p1 = [1, 3, 4, 7, 9];
+        let p2 = [2, 3, 7, 8];
+        assert_eq!(intersect(&p1, &p2), vec![3, 7]);
+        assert_eq!(intersect_not(&p1, &p2), vec![1, 4, 9]);
+        assert_eq!(merge_postings(&p1, &p2), vec![1, 2, 3, 4, 7, 8, 9]);
+        assert_eq!(intersect(&p1, &[]), Vec::<usize>::new());
+        assert_eq!(intersect_not(&p1, &[]), p1.to_vec());
+        assert_eq!(merge_postings(&[], &p2), p2.to_vec());
+    }
+}
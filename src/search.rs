@@ -0,0 +1,267 @@
+use crate::aggregations::{Aggregation, AggregationResult};
+use crate::doc_values::{DocValues, SortField};
+use crate::document::FieldValue;
+use crate::live_docs::LiveDocs;
+use crate::query_parser::QueryParser;
+use crate::segment::Segment;
+use std::cmp::Ordering;
+
+#[derive(Default)]
+pub struct SearchOptions {
+    /// Fields to sort hits by, hits are ranked by score when empty
+    pub sort: Vec<SortField>,
+    /// Number of hits to skip
+    pub offset: usize,
+    /// Maximum number of hits to return, all of them when `None`
+    pub limit: Option<usize>,
+    /// Only return hits ranked after this one, for deep paging
+    pub search_after: Option<SearchAfter>,
+}
+
+/// Boolean retrieval doesn't rank documents, every hit scores 1.0.
+/// Hits of equal score are ordered by key, hits without a key come last
+/// ordered by doc id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub doc_id: usize,
+    pub score: f32,
+}
+
+/// Cursor pointing at the last hit of a page
+///
+/// The hit is identified by its sort values and key rather than its doc id,
+/// which changes when segments are merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchAfter {
+    pub score: f32,
+    /// Values of the sort fields, `None` where the hit has none
+    pub sort_values: Vec<Option<FieldValue>>,
+    pub key: Option<String>,
+    /// Only used to order hits without a key
+    pub doc_id: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TopDocs {
+    /// Number of documents matching the query, regardless of paging
+    pub total_hits: usize,
+    pub hits: Vec<Hit>,
+    last: Option<SearchAfter>,
+}
+
+impl TopDocs {
+    /// Cursor to fetch the page following this one
+    pub fn search_after(&self) -> Option<SearchAfter> {
+        self.last.clone()
+    }
+}
+
+/// Hit along with its segment, resolved once before sorting
+struct SegmentHit<'a> {
+    hit: Hit,
+    doc_values: &'a DocValues,
+    /// Doc id in the segment
+    doc_id: usize,
+    key: Option<&'a str>,
+}
+
+/// Read only view over a list of segments, in doc id order
+///
+/// This is where searching happens, for both the index and its readers.
+pub struct SegmentsView<'a> {
+    segments: Vec<(&'a Segment, &'a LiveDocs)>,
+    key_field: &'a str,
+}
+
+impl<'a> SegmentsView<'a> {
+    pub fn new(segments: Vec<(&'a Segment, &'a LiveDocs)>, key_field: &'a str) -> SegmentsView<'a> {
+        SegmentsView {
+            segments,
+            key_field,
+        }
+    }
+
+    // here Option should be a Result in case query parsing errors
+    pub fn search(&self, query: &str) -> Option<Vec<usize>> {
+        let qp = QueryParser::new();
+        let tokens = qp.to_postfix(query)?;
+        let mut results = Vec::new();
+        let mut base = 0;
+        for (segment, live_docs) in self.segments.iter() {
+            let hits = segment.search(&qp, &tokens, live_docs);
+            results.extend(hits.into_iter().map(|doc_id| base + doc_id));
+            base += segment.max_doc();
+        }
+        Some(results)
+    }
+
+    /// Computes `aggs` over the documents matching a search
+    pub fn aggregate(&self, docs: &[usize], aggs: &[Aggregation]) -> Vec<AggregationResult> {
+        let mut per_segment: Vec<(&Segment, Vec<usize>)> = self
+            .segments
+            .iter()
+            .map(|(segment, _)| (*segment, Vec::new()))
+            .collect();
+        for doc_id in docs {
+            let mut doc_id = *doc_id;
+            for (segment, docs) in per_segment.iter_mut() {
+                if doc_id < segment.max_doc() {
+                    docs.push(doc_id);
+                    break;
+                }
+                doc_id -= segment.max_doc();
+            }
+        }
+        let segments: Vec<_> = per_segment
+            .iter()
+            .map(|(segment, docs)| (segment.doc_values(), &docs[..]))
+            .collect();
+        aggs.iter().map(|agg| agg.collect(&segments)).collect()
+    }
+
+    /// Searches and returns a single page of hits
+    ///
+    /// When `search_after` is set, hits up to and including the cursor are
+    /// skipped without being sorted, so only the requested page is ordered.
+    pub fn search_with_options(&self, query: &str, options: &SearchOptions) -> Option<TopDocs> {
+        let qp = QueryParser::new();
+        let tokens = qp.to_postfix(query)?;
+        let mut hits: Vec<SegmentHit> = Vec::new();
+        let mut base = 0;
+        for (segment, live_docs) in self.segments.iter() {
+            let doc_values = segment.doc_values();
+            for doc_id in segment.search(&qp, &tokens, live_docs) {
+                hits.push(SegmentHit {
+                    hit: Hit {
+                        doc_id: base + doc_id,
+                        score: 1.0,
+                    },
+                    doc_values,
+                    doc_id,
+                    key: doc_values.keyword(self.key_field, doc_id),
+                });
+            }
+            base += segment.max_doc();
+        }
+        let total_hits = hits.len();
+        let sort = &options.sort;
+        if let Some(after) = &options.search_after {
+            hits.retain(|hit| Self::compare_to_cursor(hit, after, sort) == Ordering::Greater);
+        }
+        let end = match options.limit {
+            Some(limit) => options.offset.saturating_add(limit),
+            None => hits.len(),
+        };
+        if end == 0 {
+            hits.clear();
+        } else if end < hits.len() {
+            hits.select_nth_unstable_by(end - 1, |a, b| Self::compare_hits(a, b, sort));
+            hits.truncate(end);
+        }
+        hits.sort_unstable_by(|a, b| Self::compare_hits(a, b, sort));
+        hits.drain(..options.offset.min(hits.len()));
+        let last = hits.last().map(|last| SearchAfter {
+            score: last.hit.score,
+            sort_values: last.doc_values.sort_values(last.doc_id, sort),
+            key: last.key.map(str::to_string),
+            doc_id: last.hit.doc_id,
+        });
+        Some(TopDocs {
+            total_hits,
+            hits: hits.into_iter().map(|hit| hit.hit).collect(),
+            last,
+        })
+    }
+
+    fn compare_hits(a: &SegmentHit, b: &SegmentHit, sort: &[SortField]) -> Ordering {
+        let ord = if sort.is_empty() {
+            b.hit
+                .score
+                .partial_cmp(&a.hit.score)
+                .unwrap_or(Ordering::Equal)
+        } else {
+            a.doc_values
+                .compare_with(a.doc_id, b.doc_values, b.doc_id, sort)
+        };
+        ord.then_with(|| Self::tiebreak((a.key, a.hit.doc_id), (b.key, b.hit.doc_id)))
+    }
+
+    fn compare_to_cursor(hit: &SegmentHit, after: &SearchAfter, sort: &[SortField]) -> Ordering {
+        let ord = if sort.is_empty() {
+            after
+                .score
+                .partial_cmp(&hit.hit.score)
+                .unwrap_or(Ordering::Equal)
+        } else {
+            hit.doc_values
+                .compare_to_values(hit.doc_id, &after.sort_values, sort)
+        };
+        ord.then_with(|| {
+            Self::tiebreak(
+                (hit.key, hit.hit.doc_id),
+                (after.key.as_deref(), after.doc_id),
+            )
+        })
+    }
+
+    // keys are unique among live documents and survive merges, doc ids only
+    // order the documents without a key
+    fn tiebreak(a: (Option<&str>, usize), b: (Option<&str>, usize)) -> Ordering {
+        match (a.0, b.0) {
+            (Some(ka), Some(kb)) => ka.cmp(kb),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.1.cmp(&b.1),
+        }
+    }
+
+    /// Doc id of the live document identified by `key`
+    pub fn doc_id(&self, key: &str) -> Option<usize> {
+        let mut base = 0;
+        for (segment, live_docs) in self.segments.iter() {
+            if let Some(doc_id) = segment.doc_id(key) {
+                if live_docs.is_live(doc_id) {
+                    return Some(base + doc_id);
+                }
+            }
+            base += segment.max_doc();
+        }
+        None
+    }
+
+    pub fn is_live(&self, doc_id: usize) -> bool {
+        match self.locate(doc_id) {
+            Some((_, live_docs, doc_id)) => live_docs.is_live(doc_id),
+            None => false,
+        }
+    }
+
+    /// Number of live documents
+    pub fn num_docs(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|(_, live_docs)| live_docs.num_live())
+            .sum()
+    }
+
+    pub fn keyword(&self, doc_id: usize, field: &str) -> Option<&'a str> {
+        let (segment, _, doc_id) = self.locate(doc_id)?;
+        segment.doc_values().keyword(field, doc_id)
+    }
+
+    pub fn numeric(&self, doc_id: usize, field: &str) -> Option<i64> {
+        let (segment, _, doc_id) = self.locate(doc_id)?;
+        segment.doc_values().numeric(field, doc_id)
+    }
+
+    /// Segment of a global doc id, and its doc id in that segment
+    fn locate(&self, mut doc_id: usize) -> Option<(&'a Segment, &'a LiveDocs, usize)> {
+        for (segment, live_docs) in self.segments.iter().copied() {
+            if doc_id < segment.max_doc() {
+                return Some((segment, live_docs, doc_id));
+            }
+            doc_id -= segment.max_doc();
+        }
+        None
+    }
+}
@@ -0,0 +1,114 @@
+use crate::index_reader::IndexReader;
+use crate::search::{SearchOptions, TopDocs};
+use std::sync::Arc;
+
+/// Serves queries from the snapshot of an `InvertedIndex` published by its
+/// last refresh when the searcher was taken
+///
+/// The snapshot never changes, so doc ids of hits can be resolved with the
+/// same searcher. Clones share the snapshot and can be sent to other
+/// threads. Changes refreshed later are seen by asking the index for a new
+/// searcher.
+#[derive(Clone, Default)]
+pub struct Searcher {
+    reader: Arc<IndexReader>,
+}
+
+impl Searcher {
+    pub(crate) fn new(reader: Arc<IndexReader>) -> Searcher {
+        Searcher { reader }
+    }
+
+    /// Snapshot of the index this searcher serves
+    pub fn reader(&self) -> &IndexReader {
+        &self.reader
+    }
+
+    /// Version of the index the snapshot was taken on
+    pub fn version(&self) -> u64 {
+        self.reader.version()
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.reader.num_docs()
+    }
+
+    pub fn search(&self, query: &str) -> Option<Vec<usize>> {
+        self.reader.search(query)
+    }
+
+    pub fn search_with_options(&self, query: &str, options: &SearchOptions) -> Option<TopDocs> {
+        self.reader.search_with_options(query, options)
+    }
+
+    pub fn keyword(&self, doc_id: usize, field: &str) -> Option<&str> {
+        self.reader.keyword(doc_id, field)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::document::Document;
+    use crate::inverted_index::InvertedIndex;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_search() {
+        assert_send_sync::<Searcher>();
+        let mut ii = InvertedIndex::new();
+        ii.set_key_field("id");
+        ii.set_max_buffered_docs(7);
+        let first = ii.searcher();
+        assert_eq!(first.num_docs(), 0);
+
+        let (senders, handles): (Vec<_>, Vec<_>) = (0..4)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel::<Searcher>();
+                let handle = thread::spawn(move || {
+                    let mut version = 0;
+                    for searcher in receiver {
+                        assert!(searcher.version() >= version);
+                        version = searcher.version();
+                        // every document is either even or odd
+                        let even = searcher.search("even").unwrap();
+                        let odd = searcher.search("odd").unwrap();
+                        assert_eq!(even.len() + odd.len(), searcher.num_docs());
+                        assert!(odd.len() <= even.len() && even.len() <= odd.len() + 1);
+                        for doc_id in even {
+                            let id: usize =
+                                searcher.keyword(doc_id, "id").unwrap().parse().unwrap();
+                            assert_eq!(id % 2, 0);
+                        }
+                    }
+                    version
+                });
+                (sender, handle)
+            })
+            .unzip();
+        for i in 0..60 {
+            let mut doc = Document::new();
+            doc.add_keyword("id", &i.to_string()).unwrap();
+            doc.add_text("body", if i % 2 == 0 { "even" } else { "odd" })
+                .unwrap();
+            ii.add_document(&doc).unwrap();
+            if i % 2 == 1 {
+                ii.refresh();
+                for sender in senders.iter() {
+                    sender.send(ii.searcher()).unwrap();
+                }
+            }
+        }
+        drop(senders);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), ii.searcher().version());
+        }
+        // searchers keep the snapshot they were taken on
+        assert_eq!(first.num_docs(), 0);
+        assert_eq!(ii.searcher().num_docs(), 60);
+        assert_eq!(ii.searcher().search("odd").unwrap().len(), 30);
+    }
+}
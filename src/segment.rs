@@ -0,0 +1,255 @@
+use crate::analysis;
+use crate::doc_values::DocValues;
+use crate::document::{Document, FieldValue, LENGTH_FIELD};
+use crate::live_docs::LiveDocs;
+use crate::postings;
+use crate::query_parser::QueryParser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+
+/// A `Segment` is a self contained inverted index over a subset of the
+/// documents, with doc ids local to the segment.
+///
+/// Segments are filled by the indexing buffer and never modified once
+/// flushed. Deletions are tracked aside in `LiveDocs`, and deleted documents
+/// are only purged when segments are merged together.
+#[derive(Serialize, Deserialize)]
+pub struct Segment {
+    id: u64,
+    postings: HashMap<String, Vec<usize>>,
+    doc_values: DocValues,
+    keys: HashMap<String, usize>,
+    max_doc: usize,
+    size_in_bytes: usize,
+}
+
+/// Maps the doc ids of a merged segment to their new doc id,
+/// `None` for deleted documents
+pub type DocMap = Vec<Option<usize>>;
+
+impl Segment {
+    pub fn new(id: u64) -> Segment {
+        Segment {
+            id,
+            postings: HashMap::new(),
+            doc_values: DocValues::new(),
+            keys: HashMap::new(),
+            max_doc: 0,
+            size_in_bytes: 0,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Number of documents in the segment, deleted ones included
+    pub fn max_doc(&self) -> usize {
+        self.max_doc
+    }
+
+    /// Rough estimate of the memory used by the segment
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_bytes
+    }
+
+    pub fn doc_values(&self) -> &DocValues {
+        &self.doc_values
+    }
+
+    /// Doc id of the last document added with this key, it may be deleted
+    pub fn doc_id(&self, key: &str) -> Option<usize> {
+        self.keys.get(key).copied()
+    }
+
+    /// Indexes `doc` under the next doc id and returns it. Fails, leaving
+    /// the segment unchanged, if a field of `doc` has another type here.
+    pub fn add_document(&mut self, doc: &Document, key_field: &str) -> io::Result<usize> {
+        self.doc_values.check_document(doc)?;
+        let doc_id = self.max_doc;
+        let mut length = 0;
+        for (field, value) in doc.fields() {
+            match value {
+                FieldValue::Text(text) => {
+                    let tokens = analysis::tokenizer(text);
+                    let lowered_tokens = analysis::lowercase_filter(tokens);
+                    length += lowered_tokens.len();
+                    for lt in lowered_tokens {
+                        self.add_posting(lt, doc_id);
+                    }
+                }
+                FieldValue::Keyword(keyword) => {
+                    if field == key_field {
+                        self.keys.insert(keyword.clone(), doc_id);
+                    }
+                    self.size_in_bytes += keyword.len() + mem::size_of::<String>();
+                    self.doc_values
+                        .set_keyword(field, doc_id, keyword.clone())?;
+                }
+                FieldValue::Numeric(n) => {
+                    self.size_in_bytes += mem::size_of::<i64>();
+                    self.doc_values.set_numeric(field, doc_id, *n)?;
+                }
+            }
+        }
+        self.doc_values
+            .set_numeric(LENGTH_FIELD, doc_id, length as i64)?;
+        self.max_doc += 1;
+        Ok(doc_id)
+    }
+
+    /// Evaluates a query in postfix notation, returns the matching live docs
+    pub fn search(&self, qp: &QueryParser, tokens: &[String], live_docs: &LiveDocs) -> Vec<usize> {
+        let all_postings: Vec<usize> = live_docs.iter().collect();
+        let mut stack: Vec<Vec<usize>> = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            if !qp.operators.contains(&&token[..]) {
+                match self.postings.get(token) {
+                    Some(pl) => stack.push(pl.to_vec()),
+                    None => stack.push(Vec::new()),
+                }
+            } else if token == "NOT" {
+                let pl = stack.pop().unwrap();
+                stack.push(postings::intersect_not(&all_postings, &pl));
+            } else if token == "AND" {
+                let r = stack.pop().unwrap();
+                let l = stack.pop().unwrap();
+                stack.push(postings::intersect(&l, &r));
+            } else if token == "OR" {
+                let r = stack.pop().unwrap();
+                let l = stack.pop().unwrap();
+                stack.push(postings::merge_postings(&l, &r));
+            }
+        }
+        let mut results = stack.pop().unwrap_or_default();
+        results.retain(|doc_id| live_docs.is_live(*doc_id));
+        results
+    }
+
+    /// Builds segment `id` out of the live documents of `sources`, in order.
+    /// Also returns the doc map of each source. Fails if a field has
+    /// different types in different sources.
+    pub fn merge(id: u64, sources: &[(&Segment, &LiveDocs)]) -> io::Result<(Segment, Vec<DocMap>)> {
+        let mut merged = Segment::new(id);
+        let mut doc_maps = Vec::with_capacity(sources.len());
+        for (segment, live_docs) in sources {
+            let mut doc_map: DocMap = vec![None; segment.max_doc];
+            for doc_id in live_docs.iter() {
+                merged
+                    .doc_values
+                    .copy_doc(merged.max_doc, &segment.doc_values, doc_id)?;
+                doc_map[doc_id] = Some(merged.max_doc);
+                merged.max_doc += 1;
+            }
+            // doc ids grow across sources so postings stay sorted
+            for (term, pl) in &segment.postings {
+                let new_ids: Vec<usize> = pl.iter().filter_map(|doc_id| doc_map[*doc_id]).collect();
+                if !new_ids.is_empty() {
+                    merged.append_postings(term, new_ids);
+                }
+            }
+            for (key, doc_id) in &segment.keys {
+                if let Some(new_id) = doc_map[*doc_id] {
+                    merged.keys.insert(key.clone(), new_id);
+                }
+            }
+            doc_maps.push(doc_map);
+        }
+        for (key, _) in merged.keys.iter() {
+            merged.size_in_bytes += key.len() + mem::size_of::<String>();
+        }
+        Ok((merged, doc_maps))
+    }
+
+    fn add_posting(&mut self, term: String, doc_id: usize) {
+        if let Some(postings) = self.postings.get_mut(&term) {
+            if postings.last() != Some(&doc_id) {
+                postings.push(doc_id);
+                self.size_in_bytes += mem::size_of::<usize>();
+            }
+        } else {
+            self.size_in_bytes += term.len() + mem::size_of::<String>() + mem::size_of::<usize>();
+            self.postings.insert(term, vec![doc_id]);
+        }
+    }
+
+    /// Segment `id` of `max_doc` documents holding `postings`, as saved by
+    /// the index before it was split in segments. It has no doc values nor
+    /// keys.
+    pub fn from_postings(
+        id: u64,
+        postings: HashMap<String, Vec<usize>>,
+        max_doc: usize,
+    ) -> io::Result<Segment> {
+        let mut segment = Segment::new(id);
+        segment.max_doc = max_doc;
+        for (term, mut doc_ids) in postings {
+            doc_ids.sort_unstable();
+            doc_ids.dedup();
+            if doc_ids.last().is_some_and(|&last| last >= max_doc) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("postings of {} past the {} saved documents", term, max_doc),
+                ));
+            }
+            segment.append_postings(&term, doc_ids);
+        }
+        Ok(segment)
+    }
+
+    // `doc_ids` must be sorted and greater than the doc ids already indexed
+    fn append_postings(&mut self, term: &str, mut doc_ids: Vec<usize>) {
+        self.size_in_bytes += doc_ids.len() * mem::size_of::<usize>();
+        if let Some(postings) = self.postings.get_mut(term) {
+            postings.append(&mut doc_ids);
+        } else {
+            self.size_in_bytes += term.len() + mem::size_of::<String>();
+            self.postings.insert(term.to_string(), doc_ids);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn doc(key: &str, text: &str) -> Document {
+        let mut doc = Document::new();
+        doc.add_keyword("id", key).unwrap();
+        doc.add_text("body", text).unwrap();
+        doc
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut s1 = Segment::new(1);
+        s1.add_document(&doc("a", "red boat"), "id").unwrap();
+        s1.add_document(&doc("b", "blue boat"), "id").unwrap();
+        s1.add_document(&doc("c", "red car"), "id").unwrap();
+        let mut l1 = LiveDocs::new();
+        (0..3).for_each(|d| l1.add(d));
+        l1.delete(1);
+        let mut s2 = Segment::new(2);
+        s2.add_document(&doc("d", "boat OR car"), "id").unwrap();
+        let mut l2 = LiveDocs::new();
+        l2.add(0);
+
+        let (merged, doc_maps) = Segment::merge(3, &[(&s1, &l1), (&s2, &l2)]).unwrap();
+        assert_eq!(merged.max_doc(), 3);
+        assert_eq!(doc_maps, vec![vec![Some(0), None, Some(1)], vec![Some(2)]]);
+        assert_eq!(merged.doc_id("b"), None);
+        assert_eq!(merged.doc_id("d"), Some(2));
+        assert_eq!(merged.doc_values().keyword("id", 1), Some("c"));
+
+        let qp = QueryParser::new();
+        let mut live = LiveDocs::new();
+        (0..3).for_each(|d| live.add(d));
+        let search = |query: &str| merged.search(&qp, &qp.to_postfix(query).unwrap(), &live);
+        assert_eq!(search("boat"), vec![0, 2]);
+        assert_eq!(search("blue"), Vec::<usize>::new());
+        assert_eq!(search("red OR car"), vec![0, 1, 2]);
+        assert_eq!(search("NOT red"), vec![2]);
+    }
+}
@@ -1,8 +1,15 @@
+use crate::arena::Arena;
+use crate::comparator::{BytewiseComparator, Comparator};
 use rand::Rng;
+use std::alloc::Layout;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::fmt;
-use std::iter;
+use std::iter::FromIterator;
 use std::marker::PhantomData;
-use std::ptr::NonNull;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::ptr::{self, NonNull};
 
 type Link<T> = Option<NonNull<Node<T>>>;
 
@@ -11,7 +18,14 @@ type Link<T> = Option<NonNull<Node<T>>>;
 /// The `SkipList` allows search, insertion and deletion
 /// in *O*(*log*(*n*)) time.
 /// It keeps its members in sorted order.
-pub struct SkipList<T> {
+///
+/// Elements are ordered by a `Comparator`, by default their `Ord`
+/// implementation. Nodes are allocated in an arena along with their tower
+/// of links, memory of removed nodes is only reclaimed when the list is
+/// dropped.
+pub struct SkipList<T, C = BytewiseComparator> {
+    arena: Arena,
+    comparator: C,
     head: Link<T>,
     k_max_height: u16,
     max_height: u16,
@@ -21,9 +35,12 @@ pub struct SkipList<T> {
     marker: PhantomData<Box<Node<T>>>,
 }
 
+// the `height` links of the tower are allocated right after the node
+#[repr(C)]
 struct Node<T> {
     element: T,
-    next: Vec<Link<T>>,
+    height: u16,
+    next: [Link<T>; 0],
 }
 
 #[derive(Clone)]
@@ -33,57 +50,81 @@ pub struct Iter<'a, T> {
     marker: PhantomData<&'a Node<T>>,
 }
 
-// #[derive(Clone)]
-pub struct IntoIter<T> {
-    list: SkipList<T>,
+pub struct IntoIter<T, C = BytewiseComparator> {
+    list: SkipList<T, C>,
+}
+
+/// Iterates over the elements of a `SkipList` within a range
+pub struct Range<'a, T> {
+    node: Link<T>,
+    /// First node past the range
+    end: Link<T>,
+    marker: PhantomData<&'a Node<T>>,
+}
+
+/// A position in a `SkipList` that can move in both directions
+///
+/// Like LevelDB's memtable iterator, a cursor is either positioned on an
+/// element or invalid. It starts out invalid, moving past either end of the
+/// list makes it invalid and moving an invalid cursor keeps it invalid.
+/// Moving backwards costs a search, *O*(*log*(*n*)), as nodes only link
+/// forward.
+pub struct Cursor<'a, T, C = BytewiseComparator> {
+    list: &'a SkipList<T, C>,
+    node: Link<T>,
 }
 
-impl<T: Default> Node<T> {
-    fn new(elt: T, height: u16) -> Self {
-        Node {
-            element: elt,
-            next: iter::repeat(None).take(height.into()).collect(),
+impl<T> Node<T> {
+    fn layout(height: u16) -> Layout {
+        let tower = mem::size_of::<Link<T>>() * height as usize;
+        let size = (mem::offset_of!(Node<T>, next) + tower).max(mem::size_of::<Node<T>>());
+        Layout::from_size_align(size, mem::align_of::<Node<T>>()).expect("invalid node layout")
+    }
+
+    fn alloc(arena: &mut Arena, element: T, height: u16) -> NonNull<Node<T>> {
+        let node = arena.allocate(Self::layout(height)).cast::<Node<T>>();
+        unsafe {
+            ptr::addr_of_mut!((*node.as_ptr()).element).write(element);
+            ptr::addr_of_mut!((*node.as_ptr()).height).write(height);
+            for level in 0..height as usize {
+                Self::set_next(node, level, None);
+            }
         }
+        node
     }
 
-    pub fn into_element(self: Box<Self>) -> T {
-        self.element
+    // the following must only be called on nodes of the list, and on levels
+    // below their height
+
+    unsafe fn link(node: NonNull<Node<T>>, level: usize) -> *mut Link<T> {
+        debug_assert!(level < Self::height(node));
+        ptr::addr_of_mut!((*node.as_ptr()).next)
+            .cast::<Link<T>>()
+            .add(level)
     }
 
-    fn new_head(height: u16) -> Link<T> {
-        // any value will do
-        let node = Box::new(Self::new(Default::default(), height));
-        Some(Box::leak(node).into())
-    }
-
-    //    fn key(&self) -> Option<&T> {
-    //        self.key.as_ref()
-    //    }
-    //
-    //    fn next(&self, height: u16) -> LinkRef<T> {
-    //        assert!(height >= 0);
-    //        self.next[height as usize].as_ref()
-    //    }
-    //
-    //    fn set_next(&mut self, height: u16, n: Link<T>) -> () {
-    //        assert!(height >= 0);
-    //        self.next[height as usize] = n;
-    //    }
-}
+    unsafe fn next(node: NonNull<Node<T>>, level: usize) -> Link<T> {
+        *Self::link(node, level)
+    }
+
+    unsafe fn set_next(node: NonNull<Node<T>>, level: usize, next: Link<T>) {
+        Self::link(node, level).write(next)
+    }
+
+    unsafe fn height(node: NonNull<Node<T>>) -> usize {
+        (*node.as_ptr()).height as usize
+    }
 
-// impl<T: Clone> Clone for Node<T> {
-//     fn clone(&self) -> Node<T> {
-//         Node {
-//             key: self.key.clone(),
-//             next: self.next.clone(),
-//         }
-//     }
-// }
+    unsafe fn element<'a>(node: NonNull<Node<T>>) -> &'a T {
+        &(*node.as_ptr()).element
+    }
+}
 
 // private methods
-impl<T> SkipList<T>
+impl<T, C> SkipList<T, C>
 where
-    T: Ord + Default,
+    T: Default,
+    C: Comparator<T>,
 {
     fn random_height(&self) -> u16 {
         let mut lvl = 1;
@@ -93,102 +134,306 @@ where
         lvl
     }
 
-    // node must be initialised with heght = 0
-    fn insert_node(&mut self, mut node: Box<Node<T>>) {
-        unsafe {
-            let mut update: Vec<Link<T>> = iter::repeat(None)
-                .take(self.k_max_height as usize)
-                .collect();
-            let mut x = self.head;
-            for i in (0..self.max_height).rev() {
-                loop {
-                    assert!(x.is_some());
-                    let x_node = x.unwrap().as_ptr();
-                    let x_next = (*x_node).next[i as usize];
-                    if x_next.is_none() {
-                        break;
-                    } else {
-                        let x_next_node = x_next.unwrap().as_ptr();
-                        if node.element < (*x_next_node).element {
-                            // cannot insert duplicates
-                            assert!(!(node.element == (*x_next_node).element));
-                            break;
-                        }
+    fn compare<Q>(&self, element: &T, elt: &Q) -> Ordering
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.comparator.compare(element.borrow(), elt)
+    }
+
+    // Returns the last node before `elt`, the head if there is none. Also
+    // records the last node before `elt` at each level in `prev`.
+    fn find_less_than_node<Q>(&self, elt: &Q, mut prev: Option<&mut [Link<T>]>) -> NonNull<Node<T>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        // head is never none
+        let mut x = self.head.unwrap();
+        let mut level = self.max_height as usize - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            match next {
+                Some(next)
+                    if self.compare(unsafe { Node::element(next) }, elt) == Ordering::Less =>
+                {
+                    x = next
+                }
+                _ => {
+                    if let Some(prev) = prev.as_deref_mut() {
+                        prev[level] = Some(x);
                     }
-                    x = x_next;
+                    if level == 0 {
+                        return x;
+                    }
+                    level -= 1;
                 }
-                update[i as usize] = x;
             }
-            // Here we will know if elt is in list or not as we keep values in a HashMap
-            let height = self.random_height();
-            if height > self.max_height {
-                for i in self.max_height..height {
-                    update[i as usize] = self.head;
-                }
-                self.max_height = height;
+        }
+    }
+
+    fn find_greater_or_equal_node<Q>(&self, elt: &Q) -> Link<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let x = self.find_less_than_node(elt, None);
+        unsafe { Node::next(x, 0) }
+    }
+
+    fn find_greater_node<Q>(&self, elt: &Q) -> Link<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let node = self.find_greater_or_equal_node(elt)?;
+        if self.compare(unsafe { Node::element(node) }, elt) == Ordering::Equal {
+            unsafe { Node::next(node, 0) }
+        } else {
+            Some(node)
+        }
+    }
+
+    fn last_node(&self) -> Link<T> {
+        let mut x = self.head.unwrap();
+        for level in (0..self.max_height as usize).rev() {
+            while let Some(next) = unsafe { Node::next(x, level) } {
+                x = next;
             }
-            for i in 0..height {
-                let u = update[i as usize].unwrap().as_ptr();
-                node.next.push((*u).next[i as usize]);
+        }
+        Some(x).filter(|x| Some(*x) != self.head)
+    }
+
+    fn find_equal_node<Q>(&self, elt: &Q) -> Link<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find_greater_or_equal_node(elt)
+            .filter(|node| self.compare(unsafe { Node::element(*node) }, elt) == Ordering::Equal)
+    }
+
+    // returns false and drops `elt` if an equal element is already in the
+    // list
+    fn insert_node(&mut self, elt: T) -> bool {
+        let mut update: Vec<Link<T>> = vec![None; self.k_max_height as usize];
+        let x = self.find_less_than_node(&elt, Some(&mut update));
+        if let Some(next) = unsafe { Node::next(x, 0) } {
+            if self
+                .comparator
+                .compare(unsafe { Node::element(next) }, &elt)
+                == Ordering::Equal
+            {
+                return false;
             }
-            let node = Some(Box::leak(node).into());
-            for i in 0..height {
-                let u = update[i as usize].unwrap().as_ptr();
-                (*u).next[i as usize] = node;
+        }
+        let height = self.random_height();
+        if height > self.max_height {
+            for i in self.max_height..height {
+                update[i as usize] = self.head;
+            }
+            self.max_height = height;
+        }
+        let node = Node::alloc(&mut self.arena, elt, height);
+        for (i, u) in update.iter().take(height as usize).enumerate() {
+            unsafe {
+                Node::set_next(node, i, Node::next(u.unwrap(), i));
+                Node::set_next(u.unwrap(), i, Some(node));
             }
-            self.len += 1;
         }
+        self.len += 1;
+        true
+    }
+
+    /// Memory allocated for the nodes of the list, heap memory owned by
+    /// the elements themselves excluded
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.arena.memory_usage()
     }
 }
 
-impl<T> Default for SkipList<T>
+impl<T, C> Default for SkipList<T, C>
 where
-    T: Ord + Default,
+    T: Default,
+    C: Comparator<T> + Default,
 {
     /// Creates an empty `SkipList<T>`
     #[inline]
     fn default() -> Self {
         // supposed to be good up to 2^16 elements
-        Self::new(16, 4)
+        Self::new(16, 4, C::default())
     }
 }
 
-impl<T> SkipList<T>
+impl<T, C> SkipList<T, C>
 where
-    T: Ord + Default,
+    T: Default,
+    C: Comparator<T>,
 {
-    pub fn contains() {}
-    pub fn new(max_height: u16, branching_factor: u16) -> SkipList<T> {
+    /// Nodes get up to `max_height` levels, each one with a probability of
+    /// 1 / `branching_factor`. Panics unless `max_height` is at least 1 and
+    /// `branching_factor` at least 2.
+    pub fn new(max_height: u16, branching_factor: u16, comparator: C) -> SkipList<T, C> {
+        assert!(max_height >= 1, "skip list max height must be at least 1");
+        assert!(
+            branching_factor >= 2,
+            "skip list branching factor must be at least 2"
+        );
+        let mut arena = Arena::new();
+        // any value will do
+        let head = Node::alloc(&mut arena, T::default(), max_height);
         SkipList {
+            arena,
+            comparator,
             max_height: 1,
             k_max_height: max_height,
-            branching_factor: branching_factor,
-            head: Node::new_head(max_height),
+            branching_factor,
+            head: Some(head),
             inverse_branching: 1.0 / branching_factor as f64,
             len: 0,
             marker: PhantomData,
         }
     }
 
-    pub fn insert(&mut self, elt: T) {
-        self.insert_node(Box::new(Node::new(elt, 0)));
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `false` and leaves the list untouched if an equal element is
+    /// already in the list
+    pub fn insert(&mut self, elt: T) -> bool {
+        self.insert_node(elt)
+    }
+
+    pub fn contains<Q>(&self, elt: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find_equal_node(elt).is_some()
+    }
+
+    /// Returns the element equal to `elt`, if any
+    pub fn get<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find_equal_node(elt)
+            .map(|node| unsafe { Node::element(node) })
+    }
+
+    /// Removes the element equal to `elt` and returns it, if any
+    pub fn remove<Q>(&mut self, elt: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut update: Vec<Link<T>> = vec![None; self.k_max_height as usize];
+        let x = self.find_less_than_node(elt, Some(&mut update));
+        let target = unsafe { Node::next(x, 0) }?;
+        if self.compare(unsafe { Node::element(target) }, elt) != Ordering::Equal {
+            return None;
+        }
+        // nodes before target at each level of its tower point to it
+        for (i, u) in update
+            .iter()
+            .take(unsafe { Node::height(target) })
+            .enumerate()
+        {
+            unsafe { Node::set_next(u.unwrap(), i, Node::next(target, i)) };
+        }
+        self.shrink_max_height();
+        self.len -= 1;
+        Some(unsafe { ptr::read(Node::element(target)) })
+    }
+
+    /// Returns the first element greater than or equal to `elt`
+    pub fn find_greater_or_equal<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find_greater_or_equal_node(elt)
+            .map(|node| unsafe { Node::element(node) })
     }
 
-    // fn find_greater_or_equal(k: &T) -> Node<T> {}
+    /// Returns the last element less than `elt`
+    pub fn find_less_than<Q>(&self, elt: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let x = self.find_less_than_node(elt, None);
+        if Some(x) == self.head {
+            None
+        } else {
+            Some(unsafe { Node::element(x) })
+        }
+    }
 
-    //     fn find_less_than(&self, k: &T) -> Node<T> {
-    //         let x = self.head;
-    //         let mut lvl = self.get_max_height();
-    //         let last_not_after = None;
-    //         while x.is_some() {
-    //             let next = x.unwrap();
-    //         }
-    //         Node::new(T::default())
-    //     }
+    pub fn last(&self) -> Option<&T> {
+        self.last_node().map(|node| unsafe { Node::element(node) })
+    }
+
+    /// Returns an invalid cursor, to be positioned with one of its seeks
+    pub fn cursor(&self) -> Cursor<'_, T, C> {
+        Cursor {
+            list: self,
+            node: None,
+        }
+    }
 
-    // fn find_last() -> Node<T> {}
+    /// Iterates over the elements within `range`, in order
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+        R: RangeBounds<Q>,
+    {
+        let first = match range.start_bound() {
+            Bound::Included(start) => self.find_greater_or_equal_node(start),
+            Bound::Excluded(start) => self.find_greater_node(start),
+            Bound::Unbounded => unsafe { Node::next(self.head.unwrap(), 0) },
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.find_greater_node(end),
+            Bound::Excluded(end) => self.find_greater_or_equal_node(end),
+            Bound::Unbounded => None,
+        };
+        // the first node is past the end when the range is empty
+        let node = first.filter(|node| {
+            let element = unsafe { Node::element(*node) };
+            match range.end_bound() {
+                Bound::Included(end) => self.compare(element, end) != Ordering::Greater,
+                Bound::Excluded(end) => self.compare(element, end) == Ordering::Less,
+                Bound::Unbounded => true,
+            }
+        });
+        Range {
+            node,
+            end,
+            marker: PhantomData,
+        }
+    }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter {
             head: self.head,
             len: self.len,
@@ -206,63 +451,216 @@ impl<'a, T> Iterator for Iter<'a, T> {
             unsafe {
                 // head should never be none
                 assert!(self.head.is_some());
-                let head_node = self.head.unwrap().as_ptr();
-                self.head = (*head_node).next[0];
+                self.head = Node::next(self.head.unwrap(), 0);
                 // this won't panic because len > 0
-                let head_node = self.head.unwrap().as_ptr();
                 self.len -= 1;
-                Some(&(*head_node).element)
+                Some(Node::element(self.head.unwrap()))
             }
         }
     }
 }
 
-// impl<T> IntoIterator for SkipList<T> {
-//     type Item = T;
-//     type IntoIter = IntoIter<T>;
-//
-//     /// Consumes the list into an iterator yielding elements by value.
-//     #[inline]
-//     fn into_iter(self) -> IntoIter<T> {
-//         IntoIter { list: self }
-//     }
-// }
-
-// impl<'a, T> IntoIterator for &'a SkipList<T>
-// where
-//     T: Ord,
-// {
-//     type Item = &'a T;
-//     type IntoIter = Iter<'a, T>;
-//
-//     fn into_iter(self) -> Iter<'a, T> {
-//         self.iter()
-//     }
-// }
-
-// impl<T: fmt::Debug> fmt::Debug for SkipList<T> {
-//     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//         f.debug_list().entries(self).finish()
-//     }
-// }
-
-// impl<T> Clone for SkipList<T>
-// where
-//     T: Clone,
-//     T: Ord,
-// {
-//     fn clone(&self) -> Self {
-//         self.iter().cloned().collect()
-//     }
-// }
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.node.filter(|node| Some(*node) != self.end)?;
+        self.node = unsafe { Node::next(node, 0) };
+        Some(unsafe { Node::element(node) })
+    }
+}
+
+impl<'a, T, C> Cursor<'a, T, C>
+where
+    T: Default,
+    C: Comparator<T>,
+{
+    pub fn valid(&self) -> bool {
+        self.node.is_some()
+    }
+
+    /// Element the cursor is positioned on, `None` if invalid
+    pub fn key(&self) -> Option<&'a T> {
+        self.node.map(|node| unsafe { Node::element(node) })
+    }
+
+    pub fn next(&mut self) {
+        if let Some(node) = self.node {
+            self.node = unsafe { Node::next(node, 0) };
+        }
+    }
+
+    pub fn prev(&mut self) {
+        if let Some(node) = self.node {
+            let element = unsafe { Node::element(node) };
+            let x = self.list.find_less_than_node(element, None);
+            self.node = Some(x).filter(|x| Some(*x) != self.list.head);
+        }
+    }
+
+    /// Positions the cursor on the first element greater than or equal to
+    /// `target`
+    pub fn seek<Q>(&mut self, target: &Q)
+    where
+        T: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.node = self.list.find_greater_or_equal_node(target);
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.node = unsafe { Node::next(self.list.head.unwrap(), 0) };
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.last_node();
+    }
+}
+
+impl<T, C> SkipList<T, C> {
+    /// Removes the first element and returns it
+    pub fn pop_first(&mut self) -> Option<T> {
+        let head = self.head.unwrap();
+        let first = unsafe { Node::next(head, 0) }?;
+        // the head points to the first node on every level of its tower
+        for i in 0..unsafe { Node::height(first) } {
+            unsafe { Node::set_next(head, i, Node::next(first, i)) };
+        }
+        self.shrink_max_height();
+        self.len -= 1;
+        Some(unsafe { ptr::read(Node::element(first)) })
+    }
 
+    fn shrink_max_height(&mut self) {
+        let head = self.head.unwrap();
+        while self.max_height > 1
+            && unsafe { Node::next(head, self.max_height as usize - 1) }.is_none()
+        {
+            self.max_height -= 1;
+        }
+    }
+}
+
+impl<T, C> Drop for SkipList<T, C> {
+    fn drop(&mut self) {
+        // the arena frees the nodes, their elements must be dropped first
+        let mut x = self.head.take();
+        while let Some(node) = x {
+            unsafe {
+                x = Node::next(node, 0);
+                ptr::drop_in_place(ptr::addr_of_mut!((*node.as_ptr()).element));
+            }
+        }
+    }
+}
+
+// nodes are owned by the list, it is as thread safe as a `Vec<T>`
+unsafe impl<T: Send, C: Send> Send for SkipList<T, C> {}
+unsafe impl<T: Sync, C: Sync> Sync for SkipList<T, C> {}
+unsafe impl<T: Sync> Send for Iter<'_, T> {}
+unsafe impl<T: Sync> Sync for Iter<'_, T> {}
+
+impl<T, C> Iterator for IntoIter<T, C> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.list.pop_first()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.len, Some(self.list.len))
+    }
+}
+
+impl<T, C> IntoIterator for SkipList<T, C> {
+    type Item = T;
+    type IntoIter = IntoIter<T, C>;
+
+    /// Consumes the list into an iterator yielding elements by value.
+    #[inline]
+    fn into_iter(self) -> IntoIter<T, C> {
+        IntoIter { list: self }
+    }
+}
+
+impl<'a, T, C> IntoIterator for &'a SkipList<T, C>
+where
+    T: Default,
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, C> fmt::Debug for SkipList<T, C>
+where
+    T: Default + fmt::Debug,
+    C: Comparator<T>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self).finish()
+    }
+}
+
+impl<T, C> Clone for SkipList<T, C>
+where
+    T: Default + Clone,
+    C: Comparator<T> + Clone,
+{
+    fn clone(&self) -> Self {
+        let mut list = SkipList::new(
+            self.k_max_height,
+            self.branching_factor,
+            self.comparator.clone(),
+        );
+        list.extend(self.iter().cloned());
+        list
+    }
+}
+
+impl<T, C> Extend<T> for SkipList<T, C>
+where
+    T: Default,
+    C: Comparator<T>,
+{
+    /// Elements already in the list are skipped
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for elt in iter {
+            self.insert(elt);
+        }
+    }
+}
+
+impl<T, C> FromIterator<T> for SkipList<T, C>
+where
+    T: Default,
+    C: Comparator<T> + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = SkipList::default();
+        list.extend(iter);
+        list
+    }
+}
+
+// these tests are meant to pass `cargo +nightly miri test skiplist`
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::comparator::ReverseBytewiseComparator;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::BTreeSet;
+    use std::rc::Rc;
 
     #[test]
     fn test_insert() {
-        let mut sl: SkipList<String> = SkipList::new(16, 4);
+        let mut sl: SkipList<String> = SkipList::new(16, 4, BytewiseComparator);
         sl.insert("wewt".to_string());
         sl.insert("blblblb".to_string());
         sl.insert("azerty".to_string());
@@ -272,4 +670,235 @@ mod test {
         assert_eq!(iterator.next(), Some(&"wewt".to_string()));
         assert_eq!(iterator.next(), None);
     }
+
+    #[test]
+    #[should_panic(expected = "skip list max height must be at least 1")]
+    fn test_zero_max_height() {
+        let _: SkipList<u8> = SkipList::new(0, 4, BytewiseComparator);
+    }
+
+    #[test]
+    #[should_panic(expected = "skip list branching factor must be at least 2")]
+    fn test_branching_factor_one() {
+        let _: SkipList<u8> = SkipList::new(12, 1, BytewiseComparator);
+    }
+
+    #[test]
+    fn test_lookups() {
+        let mut sl: SkipList<u32> = SkipList::default();
+        assert_eq!(sl.last(), None);
+        assert_eq!(sl.find_greater_or_equal(&0), None);
+        for elt in [10, 30, 20, 50, 40] {
+            sl.insert(elt);
+        }
+        assert!(sl.contains(&30));
+        assert!(!sl.contains(&35));
+        assert_eq!(sl.get(&40), Some(&40));
+        assert_eq!(sl.find_greater_or_equal(&30), Some(&30));
+        assert_eq!(sl.find_greater_or_equal(&31), Some(&40));
+        assert_eq!(sl.find_greater_or_equal(&51), None);
+        assert_eq!(sl.find_less_than(&30), Some(&20));
+        assert_eq!(sl.find_less_than(&10), None);
+        assert_eq!(sl.last(), Some(&50));
+        assert_eq!(sl.remove(&50), Some(50));
+        assert_eq!(sl.remove(&50), None);
+        assert_eq!(sl.last(), Some(&40));
+        assert_eq!(sl.len(), 4);
+
+        let mut sl: SkipList<String> = SkipList::default();
+        sl.insert("azerty".to_string());
+        assert!(sl.contains("azerty"));
+        assert_eq!(sl.remove("azerty"), Some("azerty".to_string()));
+        assert!(sl.is_empty());
+    }
+
+    #[test]
+    fn test_against_btreeset() {
+        let mut rng = StdRng::seed_from_u64(35);
+        let mut sl: SkipList<u16> = SkipList::new(12, 4, BytewiseComparator);
+        let mut set: BTreeSet<u16> = BTreeSet::new();
+        // Miri is too slow for the full run
+        let ops = if cfg!(miri) { 1_000 } else { 20_000 };
+        for _ in 0..ops {
+            let elt = rng.gen_range(0..2000);
+            match rng.gen_range(0..6) {
+                0 | 1 => assert_eq!(sl.insert(elt), set.insert(elt)),
+                2 => assert_eq!(sl.remove(&elt), set.take(&elt)),
+                3 => {
+                    assert_eq!(sl.contains(&elt), set.contains(&elt));
+                    assert_eq!(sl.get(&elt), set.get(&elt));
+                }
+                4 => assert_eq!(sl.find_greater_or_equal(&elt), set.range(elt..).next()),
+                _ => assert_eq!(sl.find_less_than(&elt), set.range(..elt).next_back()),
+            }
+            assert_eq!(sl.len(), set.len());
+            assert_eq!(sl.last(), set.iter().next_back());
+        }
+        assert!(sl.iter().eq(set.iter()));
+    }
+
+    #[test]
+    fn test_ownership() {
+        let tracker = Rc::new(());
+        let mut sl: SkipList<(u32, Rc<()>)> = (0..100).map(|i| (i, tracker.clone())).collect();
+        assert_eq!(Rc::strong_count(&tracker), 101);
+        // duplicates are dropped right away
+        assert!(!sl.insert((42, tracker.clone())));
+        sl.extend((95..105).map(|i| (i, tracker.clone())));
+        assert_eq!(sl.len(), 105);
+        assert_eq!(Rc::strong_count(&tracker), 106);
+
+        let cloned = sl.clone();
+        assert_eq!(Rc::strong_count(&tracker), 211);
+        assert!(cloned.iter().eq(sl.iter()));
+        drop(cloned);
+        assert_eq!(sl.remove(&(7, tracker.clone())).map(|(i, _)| i), Some(7));
+        assert_eq!(Rc::strong_count(&tracker), 105);
+
+        let mut into_iter = sl.into_iter();
+        assert_eq!(into_iter.next().map(|(i, _)| i), Some(0));
+        assert_eq!(into_iter.size_hint(), (103, Some(103)));
+        let rest: Vec<u32> = into_iter.by_ref().take(10).map(|(i, _)| i).collect();
+        assert_eq!(rest, vec![1, 2, 3, 4, 5, 6, 8, 9, 10, 11]);
+        // dropping a partially consumed iterator frees the remaining nodes
+        drop(into_iter);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+
+        let sl: SkipList<u8> = [3, 1, 2, 1].iter().copied().collect();
+        assert_eq!(format!("{:?}", sl), "[1, 2, 3]");
+        let mut sl: SkipList<u8> = SkipList::new(4, 2, BytewiseComparator);
+        sl.extend(0..=255);
+        while sl.pop_first().is_some() {}
+        assert!(sl.is_empty());
+        assert_eq!(sl.last(), None);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let sl: SkipList<u32> = (1..=5).map(|i| i * 10).collect();
+        let mut cursor = sl.cursor();
+        assert!(!cursor.valid());
+        cursor.seek(&25);
+        assert_eq!(cursor.key(), Some(&30));
+        cursor.prev();
+        assert_eq!(cursor.key(), Some(&20));
+        cursor.prev();
+        cursor.prev();
+        assert!(!cursor.valid());
+        cursor.next();
+        assert!(!cursor.valid());
+
+        cursor.seek_to_last();
+        let mut backwards = Vec::new();
+        while let Some(key) = cursor.key() {
+            backwards.push(*key);
+            cursor.prev();
+        }
+        assert_eq!(backwards, vec![50, 40, 30, 20, 10]);
+        cursor.seek_to_first();
+        cursor.next();
+        assert_eq!(cursor.key(), Some(&20));
+        cursor.seek(&51);
+        assert!(!cursor.valid());
+
+        let empty: SkipList<u32> = SkipList::default();
+        let mut cursor = empty.cursor();
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+        cursor.seek_to_last();
+        assert!(!cursor.valid());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut rng = StdRng::seed_from_u64(37);
+        let set: BTreeSet<u16> = (0..200).map(|_| rng.gen_range(0..500)).collect();
+        let sl: SkipList<u16> = set.iter().copied().collect();
+        assert!(sl.range(..).eq(set.iter()));
+        assert_eq!(sl.range(10..10).next(), None);
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 30..10;
+        assert_eq!(sl.range(reversed).next(), None);
+        let rounds = if cfg!(miri) { 20 } else { 1_000 };
+        for _ in 0..rounds {
+            let a = rng.gen_range(0..520);
+            let b = rng.gen_range(a..520);
+            assert!(sl.range(a..b).eq(set.range(a..b)));
+            assert!(sl.range(a..=b).eq(set.range(a..=b)));
+            assert!(sl.range(a..).eq(set.range(a..)));
+            assert!(sl.range(..b).eq(set.range(..b)));
+            let bounds = (Bound::Excluded(a), Bound::Included(b));
+            assert!(sl.range(bounds).eq(set.range(bounds)));
+        }
+
+        let sl: SkipList<String> = ["apple", "banana", "cherry"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let fruits: Vec<&String> = sl
+            .range::<str, _>((Bound::Excluded("apple"), Bound::Unbounded))
+            .collect();
+        assert_eq!(fruits, vec!["banana", "cherry"]);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut sl: SkipList<u64> = SkipList::default();
+        let empty = sl.approximate_memory_usage();
+        assert!(empty > 0);
+        let n = if cfg!(miri) { 500 } else { 10_000 };
+        sl.extend(0..n);
+        let usage = sl.approximate_memory_usage();
+        // a node is an element, a height and at least one link
+        assert!(usage >= empty + n as usize * 24);
+        assert!(usage < empty + n as usize * 64);
+        // memory is only reclaimed when the list is dropped
+        while sl.pop_first().is_some() {}
+        assert_eq!(sl.approximate_memory_usage(), usage);
+    }
+
+    // user key ascending, then sequence number descending
+    struct InternalKeyComparator;
+
+    impl Comparator<(Vec<u8>, u64)> for InternalKeyComparator {
+        fn compare(&self, a: &(Vec<u8>, u64), b: &(Vec<u8>, u64)) -> Ordering {
+            a.0.cmp(&b.0).then(b.1.cmp(&a.1))
+        }
+
+        fn name(&self) -> &str {
+            "test.InternalKeyComparator"
+        }
+    }
+
+    #[test]
+    fn test_comparator() {
+        let mut sl: SkipList<Vec<u8>, ReverseBytewiseComparator> = SkipList::default();
+        sl.extend([b"a".to_vec(), b"c".to_vec(), b"b".to_vec(), b"ab".to_vec()]);
+        let keys: Vec<&[u8]> = sl.iter().map(|k| k.as_slice()).collect();
+        assert_eq!(keys, vec![&b"c"[..], b"b", b"ab", b"a"]);
+        assert_eq!(
+            sl.find_greater_or_equal(&b"bb".to_vec()),
+            Some(&b"b".to_vec())
+        );
+        assert_eq!(sl.find_less_than(&b"b".to_vec()), Some(&b"c".to_vec()));
+        let range: Vec<&Vec<u8>> = sl.range(b"b".to_vec()..b"a".to_vec()).collect();
+        assert_eq!(range, vec![&b"b".to_vec(), &b"ab".to_vec()]);
+        assert_eq!(sl.range(b"a".to_vec()..b"b".to_vec()).next(), None);
+
+        let mut sl = SkipList::new(12, 4, InternalKeyComparator);
+        for (key, seq) in [("b", 1), ("a", 2), ("b", 3), ("a", 7), ("c", 5)] {
+            assert!(sl.insert((key.as_bytes().to_vec(), seq)));
+        }
+        assert!(!sl.insert((b"b".to_vec(), 3)));
+        let order: Vec<(&[u8], u64)> = sl.iter().map(|(k, s)| (k.as_slice(), *s)).collect();
+        assert_eq!(
+            order,
+            vec![(&b"a"[..], 7), (b"a", 2), (b"b", 3), (b"b", 1), (b"c", 5)]
+        );
+        // latest entry of "b" visible at sequence number 2
+        assert_eq!(
+            sl.find_greater_or_equal(&(b"b".to_vec(), 2)),
+            Some(&(b"b".to_vec(), 1))
+        );
+    }
 }
@@ -0,0 +1,474 @@
+//! Sorted string tables, immutable files of sorted key-value pairs in
+//! LevelDB's table format
+//!
+//! ```text
+//! +--------------+-----+--------------+-----------------+-------------+--------+
+//! | data block 1 | ... | data block n | metaindex block | index block | footer |
+//! +--------------+-----+--------------+-----------------+-------------+--------+
+//! ```
+//!
+//! Data blocks hold the entries in key order. The index block maps the
+//! last key of every data block to its handle (offset and size in the
+//! file), the metaindex block maps the names of meta blocks to their
+//! handles. Every block is followed by a trailer holding its compression
+//! type and its CRC, see `block::pack`. The fixed-size footer
+//! holds the handles of the metaindex and index blocks, padded, and a
+//! magic number.
+
+use crate::block::{self, Block, BlockBuilder, BlockCursor, CompressionType};
+use crate::coding;
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::cursor::Cursor;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+/// `echo http://code.google.com/p/leveldb/ | sha1sum`, first 64 bits
+const TABLE_MAGIC_NUMBER: u64 = 0xdb47_7524_8b80_fb57;
+
+/// Two varint64s
+const MAX_BLOCK_HANDLE_LEN: usize = 10 + 10;
+
+pub const FOOTER_SIZE: usize = 2 * MAX_BLOCK_HANDLE_LEN + 8;
+
+#[derive(Clone)]
+pub struct TableOptions {
+    /// Orders the keys of the table
+    pub comparator: Arc<dyn Comparator<[u8]>>,
+    /// Approximate size in bytes of the data blocks before compression
+    pub block_size: usize,
+    /// Entries between restart points in data blocks
+    pub block_restart_interval: usize,
+    pub compression: CompressionType,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            comparator: Arc::new(BytewiseComparator),
+            block_size: 4096,
+            block_restart_interval: block::RESTART_INTERVAL,
+            compression: CompressionType::Snappy,
+        }
+    }
+}
+
+/// Location of a block in a table file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockHandle {
+    pub offset: u64,
+    /// Size without the trailer
+    pub size: u64,
+}
+
+impl BlockHandle {
+    pub fn encode_to(&self, buf: &mut Vec<u8>) {
+        coding::put_varint(buf, self.offset);
+        coding::put_varint(buf, self.size);
+    }
+
+    pub fn decode_from(input: &mut &[u8]) -> Option<BlockHandle> {
+        Some(BlockHandle {
+            offset: coding::get_varint(input)?,
+            size: coding::get_varint(input)?,
+        })
+    }
+}
+
+fn corruption(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Writes a table to `dest`, keys must be added in increasing order
+pub struct TableBuilder<W: Write> {
+    options: TableOptions,
+    dest: W,
+    offset: u64,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    last_key: Vec<u8>,
+    num_entries: u64,
+    /// Handle of the last data block, added to the index with the first key
+    /// of the next block
+    pending_handle: Option<BlockHandle>,
+}
+
+impl<W: Write> TableBuilder<W> {
+    pub fn new(options: TableOptions, dest: W) -> TableBuilder<W> {
+        TableBuilder {
+            dest,
+            offset: 0,
+            data_block: BlockBuilder::new(options.block_restart_interval),
+            // index lookups binary search every entry
+            index_block: BlockBuilder::new(1),
+            last_key: Vec::new(),
+            num_entries: 0,
+            pending_handle: None,
+            options,
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        if self.num_entries > 0 {
+            let order = self.options.comparator.compare(key, &self.last_key);
+            assert_eq!(order, Ordering::Greater, "keys added out of order");
+        }
+        self.add_pending_index_entry();
+        self.data_block.add(key, value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.num_entries += 1;
+        if self.data_block.current_size_estimate() >= self.options.block_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the current data block
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        let contents = self.data_block.finish();
+        self.pending_handle = Some(self.write_block(&contents)?);
+        Ok(())
+    }
+
+    /// Writes the rest of the table, returns `dest` and the size of the file
+    pub fn finish(mut self) -> io::Result<(W, u64)> {
+        self.flush()?;
+        self.add_pending_index_entry();
+        let metaindex_handle = self.write_block(&BlockBuilder::default().finish())?;
+        let index_contents = self.index_block.finish();
+        let index_handle = self.write_block(&index_contents)?;
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        metaindex_handle.encode_to(&mut footer);
+        index_handle.encode_to(&mut footer);
+        footer.resize(2 * MAX_BLOCK_HANDLE_LEN, 0);
+        footer.extend_from_slice(&TABLE_MAGIC_NUMBER.to_le_bytes());
+        self.dest.write_all(&footer)?;
+        self.dest.flush()?;
+        self.offset += FOOTER_SIZE as u64;
+        Ok((self.dest, self.offset))
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    /// Size of the file written so far
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    fn add_pending_index_entry(&mut self) {
+        if let Some(handle) = self.pending_handle.take() {
+            let mut encoded = Vec::with_capacity(MAX_BLOCK_HANDLE_LEN);
+            handle.encode_to(&mut encoded);
+            self.index_block.add(&self.last_key, &encoded);
+        }
+    }
+
+    fn write_block(&mut self, contents: &[u8]) -> io::Result<BlockHandle> {
+        let packed = block::pack(contents, self.options.compression);
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: (packed.len() - block::TRAILER_SIZE) as u64,
+        };
+        self.dest.write_all(&packed)?;
+        self.offset += packed.len() as u64;
+        Ok(handle)
+    }
+}
+
+/// Table opened for reading, can be shared between threads
+pub struct Table {
+    options: TableOptions,
+    file: File,
+    index_block: Block,
+}
+
+impl Table {
+    /// Opens the table stored in the `size` bytes of `file`
+    pub fn open(options: TableOptions, file: File, size: u64) -> io::Result<Table> {
+        if size < FOOTER_SIZE as u64 {
+            return Err(corruption("file is too short to be a table"));
+        }
+        let mut footer = [0; FOOTER_SIZE];
+        file.read_exact_at(&mut footer, size - FOOTER_SIZE as u64)?;
+        let magic = u64::from_le_bytes(footer[2 * MAX_BLOCK_HANDLE_LEN..].try_into().unwrap());
+        if magic != TABLE_MAGIC_NUMBER {
+            return Err(corruption("not a table (bad magic number)"));
+        }
+        let mut input = &footer[..];
+        let _metaindex_handle =
+            BlockHandle::decode_from(&mut input).ok_or_else(|| corruption("bad footer"))?;
+        let index_handle =
+            BlockHandle::decode_from(&mut input).ok_or_else(|| corruption("bad footer"))?;
+        let index_block = read_block(&file, &index_handle, true)?;
+        Ok(Table {
+            options,
+            file,
+            index_block,
+        })
+    }
+
+    /// Cursor over the entries of the table
+    pub fn cursor(&self, verify_checksums: bool) -> TableCursor<'_> {
+        TableCursor {
+            table: self,
+            verify_checksums,
+            index: self.index_block.cursor(self.options.comparator.clone()),
+            data: None,
+            err: None,
+        }
+    }
+
+    /// First entry with a key greater than or equal to `key`
+    pub fn seek(
+        &self,
+        key: &[u8],
+        verify_checksums: bool,
+    ) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut cursor = self.cursor(verify_checksums);
+        cursor.seek(key);
+        if cursor.valid() {
+            return Ok(Some((cursor.key().to_vec(), cursor.value().to_vec())));
+        }
+        cursor.status().map(|_| None)
+    }
+
+    fn read_block(&self, handle: &BlockHandle, verify_checksums: bool) -> io::Result<Block> {
+        read_block(&self.file, handle, verify_checksums)
+    }
+}
+
+fn read_block(file: &File, handle: &BlockHandle, verify_checksums: bool) -> io::Result<Block> {
+    let mut packed = vec![0; handle.size as usize + block::TRAILER_SIZE];
+    file.read_exact_at(&mut packed, handle.offset)?;
+    block::unpack(packed, verify_checksums)
+}
+
+/// Cursor going through the index block and the data blocks it points to
+pub struct TableCursor<'a> {
+    table: &'a Table,
+    verify_checksums: bool,
+    index: BlockCursor,
+    /// Cursor over the data block the index cursor points to
+    data: Option<BlockCursor>,
+    err: Option<io::Error>,
+}
+
+impl TableCursor<'_> {
+    fn init_data_block(&mut self) {
+        self.data = None;
+        if !self.index.valid() {
+            return;
+        }
+        let mut input = self.index.value();
+        let handle = match BlockHandle::decode_from(&mut input) {
+            Some(handle) => handle,
+            None => {
+                self.err = Some(corruption("bad block handle"));
+                return;
+            }
+        };
+        match self.table.read_block(&handle, self.verify_checksums) {
+            Ok(block) => self.data = Some(block.cursor(self.table.options.comparator.clone())),
+            Err(e) => self.err = Some(e),
+        }
+    }
+
+    // Moves to the first entry of the next non-empty data blocks if the
+    // current data block is done
+    fn skip_empty_data_blocks_forward(&mut self) {
+        loop {
+            if let Some(data) = self.data.as_mut() {
+                if data.valid() {
+                    return;
+                }
+                if let Err(e) = data.status() {
+                    self.err = Some(e);
+                }
+            }
+            if self.err.is_some() || !self.index.valid() {
+                self.data = None;
+                return;
+            }
+            self.index.next();
+            self.init_data_block();
+            if let Some(data) = self.data.as_mut() {
+                data.seek_to_first();
+            }
+        }
+    }
+}
+
+impl Cursor for TableCursor<'_> {
+    fn valid(&self) -> bool {
+        self.data.as_ref().is_some_and(|data| data.valid())
+    }
+
+    fn seek_to_first(&mut self) {
+        self.err = None;
+        self.index.seek_to_first();
+        self.init_data_block();
+        if let Some(data) = self.data.as_mut() {
+            data.seek_to_first();
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.err = None;
+        self.index.seek(target);
+        self.init_data_block();
+        if let Some(data) = self.data.as_mut() {
+            data.seek(target);
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn next(&mut self) {
+        self.data
+            .as_mut()
+            .expect("next on an invalid cursor")
+            .next();
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn key(&self) -> &[u8] {
+        self.data.as_ref().expect("key of an invalid cursor").key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.data
+            .as_ref()
+            .expect("value of an invalid cursor")
+            .value()
+    }
+
+    fn status(&mut self) -> io::Result<()> {
+        if let Some(e) = self.err.take() {
+            return Err(e);
+        }
+        self.index.status()?;
+        match self.data.as_mut() {
+            Some(data) => data.status(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use uuid::Uuid;
+
+    fn build_table(path: &std::path::Path, n: usize, options: &TableOptions) -> u64 {
+        let file = File::create(path).unwrap();
+        let mut builder = TableBuilder::new(options.clone(), file);
+        for i in 0..n {
+            let key = format!("key{:06}", i * 10);
+            builder
+                .add(key.as_bytes(), format!("value{}", i).as_bytes())
+                .unwrap();
+        }
+        assert_eq!(builder.num_entries(), n as u64);
+        let (file, size) = builder.finish().unwrap();
+        file.sync_all().unwrap();
+        assert_eq!(fs::metadata(path).unwrap().len(), size);
+        size
+    }
+
+    #[test]
+    fn test_table() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("000001.ldb");
+        let options = TableOptions {
+            block_size: 256,
+            ..TableOptions::default()
+        };
+        let size = build_table(&path, 1000, &options);
+        let table = Table::open(options.clone(), File::open(&path).unwrap(), size).unwrap();
+
+        let mut cursor = table.cursor(true);
+        cursor.seek_to_first();
+        for i in 0..1000 {
+            assert_eq!(cursor.key(), format!("key{:06}", i * 10).as_bytes());
+            assert_eq!(cursor.value(), format!("value{}", i).as_bytes());
+            cursor.next();
+        }
+        assert!(!cursor.valid());
+        assert!(cursor.status().is_ok());
+
+        cursor.seek(b"key000095");
+        assert_eq!(cursor.key(), b"key000100");
+        cursor.seek(b"key009990");
+        assert_eq!(cursor.value(), b"value999");
+        cursor.seek(b"key009991");
+        assert!(!cursor.valid());
+        assert_eq!(
+            table.seek(b"key00500", false).unwrap(),
+            Some((b"key005000".to_vec(), b"value500".to_vec()))
+        );
+        assert_eq!(table.seek(b"z", false).unwrap(), None);
+
+        // an empty table
+        let empty_path = dir.join("000002.ldb");
+        let size = build_table(&empty_path, 0, &options);
+        let table = Table::open(options.clone(), File::open(&empty_path).unwrap(), size).unwrap();
+        let mut cursor = table.cursor(true);
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corruption() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("000001.ldb");
+        let options = TableOptions {
+            block_size: 256,
+            ..TableOptions::default()
+        };
+        let size = build_table(&path, 100, &options);
+
+        // flip a bit in the first data block
+        let mut contents = fs::read(&path).unwrap();
+        contents[10] ^= 1;
+        fs::write(&path, &contents).unwrap();
+        let table = Table::open(options.clone(), File::open(&path).unwrap(), size).unwrap();
+        let mut cursor = table.cursor(true);
+        cursor.seek_to_first();
+        assert!(!cursor.valid());
+        assert_eq!(
+            cursor.status().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        // checksums are only checked when asked to
+        let mut cursor = table.cursor(false);
+        cursor.seek(b"key000500");
+        assert_eq!(cursor.key(), b"key000500");
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0; 8], size - 8).unwrap();
+        let err = Table::open(options.clone(), File::open(&path).unwrap(), size)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Table::open(options, File::open(&path).unwrap(), 10)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+use crate::filename;
+use crate::table::{Table, TableOptions};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Open tables of a `Db` directory, by file number
+///
+/// Tables stay open until evicted, once deleted by a compaction.
+pub struct TableCache {
+    dir: PathBuf,
+    options: TableOptions,
+    tables: Mutex<HashMap<u64, Arc<Table>>>,
+}
+
+impl TableCache {
+    pub fn new(dir: PathBuf, options: TableOptions) -> TableCache {
+        TableCache {
+            dir,
+            options,
+            tables: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Table `number` of `file_size` bytes, opened if not cached yet
+    pub fn get(&self, number: u64, file_size: u64) -> io::Result<Arc<Table>> {
+        if let Some(table) = self
+            .tables
+            .lock()
+            .expect("table cache poisoned")
+            .get(&number)
+        {
+            return Ok(table.clone());
+        }
+        // opened unlocked, a concurrent open of the same table is harmless
+        let file = File::open(filename::table_file_name(&self.dir, number))?;
+        let table = Arc::new(Table::open(self.options.clone(), file, file_size)?);
+        let mut tables = self.tables.lock().expect("table cache poisoned");
+        Ok(tables.entry(number).or_insert(table).clone())
+    }
+
+    pub fn evict(&self, number: u64) {
+        self.tables
+            .lock()
+            .expect("table cache poisoned")
+            .remove(&number);
+    }
+
+    pub fn options(&self) -> &TableOptions {
+        &self.options
+    }
+}
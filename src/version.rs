@@ -0,0 +1,231 @@
+use crate::comparator::Comparator;
+use crate::internal_key::{self, InternalKeyComparator, SequenceNumber};
+use std::cmp::{Ordering, Reverse};
+
+/// Levels of tables, level 0 receives the flushed memtables
+pub const NUM_LEVELS: usize = 7;
+
+/// Table of a `Version`, keys are internal keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    /// Range of the sequence numbers of the entries
+    pub smallest_sequence: SequenceNumber,
+    pub largest_sequence: SequenceNumber,
+}
+
+/// Tables of a `Db` at some point in time, by level
+///
+/// The tables of level 0 may overlap, their sequence numbers don't: the
+/// most recent has the largest ones. The tables of the other levels are
+/// sorted by key and don't overlap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub files: Vec<Vec<FileMetaData>>,
+}
+
+impl Version {
+    pub fn new() -> Version {
+        Version {
+            files: vec![Vec::new(); NUM_LEVELS],
+        }
+    }
+
+    /// Tables of `level` that may hold `user_key`, in the order they must
+    /// be searched
+    pub fn tables_for_key<'a>(
+        &'a self,
+        level: usize,
+        user_key: &[u8],
+        user_comparator: &dyn Comparator<[u8]>,
+    ) -> Vec<&'a FileMetaData> {
+        let contains = |file: &FileMetaData| {
+            user_comparator.compare(user_key, internal_key::user_key(&file.smallest))
+                != Ordering::Less
+                && user_comparator.compare(user_key, internal_key::user_key(&file.largest))
+                    != Ordering::Greater
+        };
+        let files = &self.files[level];
+        if level == 0 {
+            let mut tables: Vec<_> = files.iter().filter(|file| contains(file)).collect();
+            tables.sort_unstable_by_key(|file| Reverse(file.largest_sequence));
+            return tables;
+        }
+        let index = files.partition_point(|file| {
+            user_comparator.compare(internal_key::user_key(&file.largest), user_key)
+                == Ordering::Less
+        });
+        files[index..]
+            .first()
+            .filter(|file| contains(file))
+            .into_iter()
+            .collect()
+    }
+
+    pub fn num_files(&self, level: usize) -> usize {
+        self.files[level].len()
+    }
+
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.files[level].iter().map(|file| file.file_size).sum()
+    }
+
+    /// Tables of level 0, most recent first
+    pub fn level0_by_age(&self) -> Vec<&FileMetaData> {
+        let mut files: Vec<_> = self.files[0].iter().collect();
+        files.sort_unstable_by_key(|file| Reverse(file.largest_sequence));
+        files
+    }
+
+    /// Sizes of the sorted runs, tables or levels whose keys don't
+    /// overlap: each table of level 0, then each non-empty level
+    pub fn sorted_run_sizes(&self) -> Vec<u64> {
+        let level0 = self.level0_by_age().into_iter().map(|file| file.file_size);
+        let levels = (1..NUM_LEVELS)
+            .map(|level| self.level_size(level))
+            .filter(|size| *size > 0);
+        level0.chain(levels).collect()
+    }
+
+    /// Tables of `level` overlapping the user key range `[begin, end]`,
+    /// unbounded on the `None` sides. On level 0 the range is extended to
+    /// the overlapping tables, so that a newer entry of a key is never
+    /// left behind an older one.
+    pub fn overlapping_inputs(
+        &self,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+        user_comparator: &dyn Comparator<[u8]>,
+    ) -> Vec<FileMetaData> {
+        let mut begin = begin.map(<[u8]>::to_vec);
+        let mut end = end.map(<[u8]>::to_vec);
+        let mut inputs = Vec::new();
+        let mut i = 0;
+        while i < self.files[level].len() {
+            let file = &self.files[level][i];
+            i += 1;
+            let smallest = internal_key::user_key(&file.smallest);
+            let largest = internal_key::user_key(&file.largest);
+            let before_begin = begin
+                .as_ref()
+                .is_some_and(|begin| user_comparator.compare(largest, begin) == Ordering::Less);
+            let after_end = end
+                .as_ref()
+                .is_some_and(|end| user_comparator.compare(smallest, end) == Ordering::Greater);
+            if before_begin || after_end {
+                continue;
+            }
+            if level == 0 {
+                let extends_begin = begin.as_ref().is_some_and(|begin| {
+                    user_comparator.compare(smallest, begin) == Ordering::Less
+                });
+                let extends_end = end
+                    .as_ref()
+                    .is_some_and(|end| user_comparator.compare(largest, end) == Ordering::Greater);
+                if extends_begin || extends_end {
+                    if extends_begin {
+                        begin = Some(smallest.to_vec());
+                    }
+                    if extends_end {
+                        end = Some(largest.to_vec());
+                    }
+                    inputs.clear();
+                    i = 0;
+                    continue;
+                }
+            }
+            inputs.push(file.clone());
+        }
+        inputs
+    }
+
+    /// Version with the tables `deleted`, as (level, number), removed and
+    /// the tables `added` inserted in their level
+    pub fn apply(
+        &self,
+        deleted: &[(usize, u64)],
+        added: Vec<(usize, FileMetaData)>,
+        comparator: &InternalKeyComparator,
+    ) -> Version {
+        let mut version = self.clone();
+        for (level, number) in deleted {
+            version.files[*level].retain(|file| file.number != *number);
+        }
+        for (level, file) in added {
+            version.files[level].push(file);
+        }
+        for files in version.files.iter_mut().skip(1) {
+            files.sort_by(|a, b| comparator.compare(&a.smallest, &b.smallest));
+        }
+        version
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+    use crate::internal_key::ValueType;
+
+    fn file(number: u64, smallest: &[u8], largest: &[u8]) -> FileMetaData {
+        FileMetaData {
+            number,
+            file_size: 100,
+            smallest: internal_key::encode(smallest, 10, ValueType::Value),
+            largest: internal_key::encode(largest, 1, ValueType::Value),
+            smallest_sequence: number * 10,
+            largest_sequence: number * 10 + 9,
+        }
+    }
+
+    #[test]
+    fn test_tables_for_key() {
+        let mut version = Version::new();
+        version.files[0] = vec![
+            file(1, b"a", b"m"),
+            file(5, b"k", b"z"),
+            file(3, b"c", b"e"),
+        ];
+        version.files[1] = vec![file(2, b"a", b"c"), file(4, b"f", b"h")];
+        let numbers = |level, key: &[u8]| -> Vec<u64> {
+            version
+                .tables_for_key(level, key, &BytewiseComparator)
+                .iter()
+                .map(|file| file.number)
+                .collect()
+        };
+        assert_eq!(numbers(0, b"l"), vec![5, 1]);
+        assert_eq!(numbers(0, b"d"), vec![3, 1]);
+        assert_eq!(numbers(0, b"zz"), Vec::<u64>::new());
+        assert_eq!(numbers(1, b"c"), vec![2]);
+        assert_eq!(numbers(1, b"d"), Vec::<u64>::new());
+        assert_eq!(numbers(1, b"f"), vec![4]);
+        assert_eq!(numbers(1, b"i"), Vec::<u64>::new());
+        assert_eq!(version.num_files(0), 3);
+        assert_eq!(version.level_size(1), 200);
+
+        // a merged table of level 0 gets a new number but keeps the
+        // sequence numbers of its entries
+        let mut merged = file(9, b"a", b"b");
+        merged.smallest_sequence = 0;
+        merged.largest_sequence = 5;
+        version.files[0].push(merged);
+        let numbers: Vec<u64> = version
+            .tables_for_key(0, b"a", &BytewiseComparator)
+            .iter()
+            .map(|file| file.number)
+            .collect();
+        assert_eq!(numbers, vec![1, 9]);
+        assert_eq!(version.sorted_run_sizes(), vec![100, 100, 100, 100, 200]);
+    }
+}
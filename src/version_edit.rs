@@ -0,0 +1,216 @@
+//! Changes between two versions, as logged in the manifest
+//!
+//! An edit is encoded as a sequence of fields, each a varint tag followed
+//! by its value. Numbers are varints, keys are length prefixed:
+//!
+//! ```text
+//! Comparator       name
+//! LogNumber        number
+//! NextFileNumber   number
+//! LastSequence     sequence
+//! CompactPointer   level key
+//! DeletedFile      level number
+//! NewFile          level number file_size smallest largest
+//!                  smallest_sequence largest_sequence
+//! ```
+//!
+//! Fields that are not set keep their value of the previous edits.
+
+use crate::coding;
+use crate::internal_key::SequenceNumber;
+use crate::version::{FileMetaData, NUM_LEVELS};
+use std::io;
+
+const COMPARATOR: u64 = 1;
+const LOG_NUMBER: u64 = 2;
+const NEXT_FILE_NUMBER: u64 = 3;
+const LAST_SEQUENCE: u64 = 4;
+const COMPACT_POINTER: u64 = 5;
+const DELETED_FILE: u64 = 6;
+const NEW_FILE: u64 = 7;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// Name of the user comparator, checked when the database is opened
+    pub comparator: Option<String>,
+    /// Logs older than this one are flushed to the tables
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    /// Last sequence number flushed to the tables
+    pub last_sequence: Option<SequenceNumber>,
+    /// Largest key compacted last in a level, as (level, internal key)
+    pub compact_pointers: Vec<(usize, Vec<u8>)>,
+    /// Tables removed, as (level, number)
+    pub deleted_files: Vec<(usize, u64)>,
+    pub new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub fn new() -> VersionEdit {
+        VersionEdit::default()
+    }
+
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(comparator) = &self.comparator {
+            coding::put_varint(&mut buf, COMPARATOR);
+            coding::put_length_prefixed(&mut buf, comparator.as_bytes());
+        }
+        let numbers = [
+            (LOG_NUMBER, self.log_number),
+            (NEXT_FILE_NUMBER, self.next_file_number),
+            (LAST_SEQUENCE, self.last_sequence),
+        ];
+        for (tag, number) in numbers {
+            if let Some(number) = number {
+                coding::put_varint(&mut buf, tag);
+                coding::put_varint(&mut buf, number);
+            }
+        }
+        for (level, key) in &self.compact_pointers {
+            coding::put_varint(&mut buf, COMPACT_POINTER);
+            coding::put_varint(&mut buf, *level as u64);
+            coding::put_length_prefixed(&mut buf, key);
+        }
+        for (level, number) in &self.deleted_files {
+            coding::put_varint(&mut buf, DELETED_FILE);
+            coding::put_varint(&mut buf, *level as u64);
+            coding::put_varint(&mut buf, *number);
+        }
+        for (level, file) in &self.new_files {
+            coding::put_varint(&mut buf, NEW_FILE);
+            coding::put_varint(&mut buf, *level as u64);
+            coding::put_varint(&mut buf, file.number);
+            coding::put_varint(&mut buf, file.file_size);
+            coding::put_length_prefixed(&mut buf, &file.smallest);
+            coding::put_length_prefixed(&mut buf, &file.largest);
+            coding::put_varint(&mut buf, file.smallest_sequence);
+            coding::put_varint(&mut buf, file.largest_sequence);
+        }
+        buf
+    }
+
+    /// Edit read back from a manifest record, checking it is well formed
+    pub fn decode(mut input: &[u8]) -> io::Result<VersionEdit> {
+        let corruption = |field: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed version edit: {}", field),
+            )
+        };
+        let get_level = |input: &mut &[u8]| {
+            coding::get_varint(input)
+                .map(|level| level as usize)
+                .filter(|level| *level < NUM_LEVELS)
+        };
+        let mut edit = VersionEdit::new();
+        while !input.is_empty() {
+            let tag = coding::get_varint(&mut input).ok_or_else(|| corruption("tag"))?;
+            match tag {
+                COMPARATOR => {
+                    let name = coding::get_length_prefixed(&mut input)
+                        .and_then(|name| String::from_utf8(name.to_vec()).ok())
+                        .ok_or_else(|| corruption("comparator"))?;
+                    edit.comparator = Some(name);
+                }
+                LOG_NUMBER => {
+                    let number = coding::get_varint(&mut input);
+                    edit.log_number = Some(number.ok_or_else(|| corruption("log number"))?);
+                }
+                NEXT_FILE_NUMBER => {
+                    let number = coding::get_varint(&mut input);
+                    edit.next_file_number =
+                        Some(number.ok_or_else(|| corruption("next file number"))?);
+                }
+                LAST_SEQUENCE => {
+                    let sequence = coding::get_varint(&mut input);
+                    edit.last_sequence = Some(sequence.ok_or_else(|| corruption("last sequence"))?);
+                }
+                COMPACT_POINTER => {
+                    let pointer = get_level(&mut input).and_then(|level| {
+                        Some((level, coding::get_length_prefixed(&mut input)?.to_vec()))
+                    });
+                    edit.compact_pointers
+                        .push(pointer.ok_or_else(|| corruption("compact pointer"))?);
+                }
+                DELETED_FILE => {
+                    let file = get_level(&mut input)
+                        .and_then(|level| Some((level, coding::get_varint(&mut input)?)));
+                    edit.deleted_files
+                        .push(file.ok_or_else(|| corruption("deleted file"))?);
+                }
+                NEW_FILE => {
+                    let file = get_level(&mut input).and_then(|level| {
+                        let file = FileMetaData {
+                            number: coding::get_varint(&mut input)?,
+                            file_size: coding::get_varint(&mut input)?,
+                            smallest: coding::get_length_prefixed(&mut input)?.to_vec(),
+                            largest: coding::get_length_prefixed(&mut input)?.to_vec(),
+                            smallest_sequence: coding::get_varint(&mut input)?,
+                            largest_sequence: coding::get_varint(&mut input)?,
+                        };
+                        Some((level, file))
+                    });
+                    edit.new_files
+                        .push(file.ok_or_else(|| corruption("new file"))?);
+                }
+                _ => return Err(corruption("unknown tag")),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::internal_key::{self, ValueType};
+
+    #[test]
+    fn test_version_edit() {
+        let mut edit = VersionEdit::new();
+        assert_eq!(VersionEdit::decode(&edit.encode()).unwrap(), edit);
+        edit.comparator = Some("leveldb.BytewiseComparator".to_string());
+        edit.log_number = Some(12);
+        edit.next_file_number = Some(1 << 40);
+        edit.last_sequence = Some(300);
+        edit.compact_pointers
+            .push((2, internal_key::encode(b"k", 7, ValueType::Value)));
+        edit.delete_file(1, 4);
+        edit.delete_file(2, 5);
+        edit.add_file(
+            3,
+            FileMetaData {
+                number: 13,
+                file_size: 4096,
+                smallest: internal_key::encode(b"a", 9, ValueType::Deletion),
+                largest: internal_key::encode(b"z", 3, ValueType::Value),
+                smallest_sequence: 3,
+                largest_sequence: 9,
+            },
+        );
+        let encoded = edit.encode();
+        assert_eq!(VersionEdit::decode(&encoded).unwrap(), edit);
+
+        for len in 0..encoded.len() {
+            // a truncated edit either fails or decodes to fewer fields
+            if let Ok(decoded) = VersionEdit::decode(&encoded[..len]) {
+                assert_ne!(decoded, edit);
+            }
+        }
+        let mut bad_level = Vec::new();
+        coding::put_varint(&mut bad_level, DELETED_FILE);
+        coding::put_varint(&mut bad_level, NUM_LEVELS as u64);
+        coding::put_varint(&mut bad_level, 1);
+        assert!(VersionEdit::decode(&bad_level).is_err());
+        assert!(VersionEdit::decode(&[99]).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::commit;
+use crate::filename;
+use crate::internal_key::{InternalKeyComparator, SequenceNumber};
+use crate::version::{Version, NUM_LEVELS};
+use crate::version_edit::VersionEdit;
+use crate::wal;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+
+/// Versions of the tables of a `Db`, with the numbers persisted along with
+/// them
+///
+/// Every change is logged to the manifest as a `VersionEdit` before the
+/// resulting version becomes current. Each open of the database starts a
+/// new manifest with a snapshot of the recovered state, and points
+/// `CURRENT` at it once it is synced.
+///
+/// Versions are reference counted: a reader or a compaction keeps the
+/// version it started with alive, and the tables of every live version
+/// must stay on disk.
+pub struct VersionSet {
+    dir: PathBuf,
+    comparator: InternalKeyComparator,
+    current: Arc<Version>,
+    /// Replaced versions, some of which may still be read
+    previous: Vec<Weak<Version>>,
+    /// Logs older than this one are flushed to the tables
+    log_number: u64,
+    next_file_number: u64,
+    /// Last sequence number flushed to the tables
+    last_sequence: SequenceNumber,
+    /// Largest key compacted last in each level
+    compact_pointers: Vec<Vec<u8>>,
+    manifest_number: u64,
+    /// `None` until the first edit starts a new manifest
+    manifest: Option<wal::Writer<File>>,
+}
+
+impl VersionSet {
+    /// Version set of an empty database in `dir`
+    pub fn new(dir: PathBuf, comparator: InternalKeyComparator) -> VersionSet {
+        VersionSet {
+            dir,
+            comparator,
+            current: Arc::new(Version::new()),
+            previous: Vec::new(),
+            log_number: 0,
+            next_file_number: 1,
+            last_sequence: 0,
+            compact_pointers: vec![Vec::new(); NUM_LEVELS],
+            manifest_number: 0,
+            manifest: None,
+        }
+    }
+
+    /// Replays the edits of the manifest named by `CURRENT`
+    pub fn recover(&mut self) -> io::Result<()> {
+        let manifest_number = filename::read_current_file(&self.dir)?;
+        let path = filename::manifest_file_name(&self.dir, manifest_number);
+        let corruption = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        };
+        let mut reader = wal::Reader::new(BufReader::new(File::open(&path)?));
+        let mut version = Version::new();
+        let mut log_number = None;
+        let mut next_file_number = None;
+        let mut last_sequence = None;
+        while let Some(record) = reader.read_record()? {
+            let edit = VersionEdit::decode(&record).map_err(|err| corruption(&err.to_string()))?;
+            if let Some(name) = &edit.comparator {
+                let expected = self.comparator.user_comparator().name();
+                if name != expected {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("comparator {} does not match {}", expected, name),
+                    ));
+                }
+            }
+            log_number = edit.log_number.or(log_number);
+            next_file_number = edit.next_file_number.or(next_file_number);
+            last_sequence = edit.last_sequence.or(last_sequence);
+            for (level, key) in edit.compact_pointers {
+                self.compact_pointers[level] = key;
+            }
+            version = version.apply(&edit.deleted_files, edit.new_files, &self.comparator);
+        }
+        // the manifest is never written past a corrupted record
+        if reader.dropped_bytes() > 0 {
+            return Err(corruption("corrupted manifest"));
+        }
+        self.log_number = log_number.ok_or_else(|| corruption("missing log number"))?;
+        self.next_file_number =
+            next_file_number.ok_or_else(|| corruption("missing next file number"))?;
+        self.last_sequence = last_sequence.ok_or_else(|| corruption("missing last sequence"))?;
+        self.current = Arc::new(version);
+        self.manifest_number = manifest_number;
+        self.mark_file_number_used(manifest_number);
+        Ok(())
+    }
+
+    pub fn current(&self) -> &Arc<Version> {
+        &self.current
+    }
+
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    pub fn last_sequence(&self) -> SequenceNumber {
+        self.last_sequence
+    }
+
+    pub fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    pub fn compact_pointers(&self) -> &[Vec<u8>] {
+        &self.compact_pointers
+    }
+
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// Keeps `number`, found on disk, from being handed out again
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    /// Logs `edit` to the manifest then makes the version it leads to the
+    /// current one. The log number and last sequence keep their value if
+    /// the edit does not set them. The tables added by the edit must be
+    /// synced.
+    pub fn log_and_apply(&mut self, mut edit: VersionEdit) -> io::Result<()> {
+        let log_number = edit.log_number.unwrap_or(self.log_number);
+        let last_sequence = edit.last_sequence.unwrap_or(self.last_sequence);
+        edit.log_number = Some(log_number);
+        edit.last_sequence = Some(last_sequence);
+        let version = self.current.apply(
+            &edit.deleted_files,
+            edit.new_files.clone(),
+            &self.comparator,
+        );
+
+        let new_manifest = if self.manifest.is_none() {
+            let number = self.new_file_number();
+            if let Err(err) = self.create_manifest(number) {
+                let _ = fs::remove_file(filename::manifest_file_name(&self.dir, number));
+                return Err(err);
+            }
+            Some(number)
+        } else {
+            None
+        };
+        edit.next_file_number = Some(self.next_file_number);
+        if let Err(err) = self.write_edit(&edit, new_manifest) {
+            // the manifest may hold part of the edit, the next one starts
+            // over in a new manifest
+            self.manifest = None;
+            if let Some(number) = new_manifest {
+                let _ = fs::remove_file(filename::manifest_file_name(&self.dir, number));
+            }
+            return Err(err);
+        }
+        if let Some(number) = new_manifest {
+            self.manifest_number = number;
+        }
+
+        let previous = std::mem::replace(&mut self.current, Arc::new(version));
+        self.previous.retain(|version| version.strong_count() > 0);
+        self.previous.push(Arc::downgrade(&previous));
+        self.log_number = log_number;
+        self.last_sequence = last_sequence;
+        for (level, key) in edit.compact_pointers {
+            self.compact_pointers[level] = key;
+        }
+        Ok(())
+    }
+
+    /// Numbers of the tables of the live versions
+    pub fn live_files(&self) -> HashSet<u64> {
+        let versions = self
+            .previous
+            .iter()
+            .filter_map(Weak::upgrade)
+            .chain(Some(self.current.clone()));
+        let mut live = HashSet::new();
+        for version in versions {
+            live.extend(version.files.iter().flatten().map(|file| file.number));
+        }
+        live
+    }
+
+    /// Starts manifest `number` with a snapshot of the current state
+    fn create_manifest(&mut self, number: u64) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(filename::manifest_file_name(&self.dir, number))?;
+        let mut manifest = wal::Writer::new(file);
+        let mut snapshot = VersionEdit {
+            comparator: Some(self.comparator.user_comparator().name().to_string()),
+            log_number: Some(self.log_number),
+            next_file_number: Some(self.next_file_number),
+            last_sequence: Some(self.last_sequence),
+            ..VersionEdit::default()
+        };
+        for (level, key) in self.compact_pointers.iter().enumerate() {
+            if !key.is_empty() {
+                snapshot.compact_pointers.push((level, key.clone()));
+            }
+        }
+        for (level, files) in self.current.files.iter().enumerate() {
+            for file in files {
+                snapshot.add_file(level, file.clone());
+            }
+        }
+        manifest.add_record(&snapshot.encode())?;
+        self.manifest = Some(manifest);
+        Ok(())
+    }
+
+    fn write_edit(&mut self, edit: &VersionEdit, new_manifest: Option<u64>) -> io::Result<()> {
+        // the manifest must not name tables missing from the directory
+        if !edit.new_files.is_empty() {
+            commit::sync_dir(&self.dir)?;
+        }
+        let manifest = self.manifest.as_mut().expect("no manifest");
+        manifest.add_record(&edit.encode())?;
+        manifest.get_ref().sync_data()?;
+        if let Some(number) = new_manifest {
+            filename::set_current_file(&self.dir, number)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comparator::{BytewiseComparator, ReverseBytewiseComparator};
+    use crate::internal_key::{self, ValueType};
+    use crate::version::FileMetaData;
+    use std::env;
+    use uuid::Uuid;
+
+    fn file(number: u64, smallest: &[u8], largest: &[u8]) -> FileMetaData {
+        FileMetaData {
+            number,
+            file_size: 100,
+            smallest: internal_key::encode(smallest, number * 10, ValueType::Value),
+            largest: internal_key::encode(largest, number * 10 + 9, ValueType::Value),
+            smallest_sequence: number * 10,
+            largest_sequence: number * 10 + 9,
+        }
+    }
+
+    #[test]
+    fn test_version_set() {
+        let dir = env::temp_dir().join(format!("fts-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let comparator = InternalKeyComparator::new(Arc::new(BytewiseComparator));
+        let mut versions = VersionSet::new(dir.clone(), comparator.clone());
+        assert_eq!(versions.new_file_number(), 1);
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, file(2, b"a", b"m"));
+        edit.add_file(0, file(3, b"c", b"z"));
+        edit.log_number = Some(1);
+        edit.last_sequence = Some(39);
+        versions.log_and_apply(edit).unwrap();
+        // the first edit starts a manifest
+        assert_eq!(versions.manifest_number(), 2);
+        assert_eq!(filename::read_current_file(&dir).unwrap(), 2);
+        let first = versions.current().clone();
+        assert_eq!(first.num_files(0), 2);
+
+        let mut edit = VersionEdit::new();
+        edit.delete_file(0, 2);
+        edit.delete_file(0, 3);
+        edit.add_file(1, file(5, b"a", b"k"));
+        edit.add_file(1, file(4, b"l", b"z"));
+        edit.compact_pointers
+            .push((0, internal_key::encode(b"z", 39, ValueType::Value)));
+        versions.log_and_apply(edit).unwrap();
+        assert_eq!(versions.log_number(), 1);
+        assert_eq!(versions.last_sequence(), 39);
+        // the first version is still referenced, its tables are live
+        let live: HashSet<u64> = [2, 3, 4, 5].iter().copied().collect();
+        assert_eq!(versions.live_files(), live);
+        drop(first);
+        let live: HashSet<u64> = [4, 5].iter().copied().collect();
+        assert_eq!(versions.live_files(), live);
+        let expected = versions.current().clone();
+        let compact_pointers = versions.compact_pointers().to_vec();
+        let next_file_number = versions.new_file_number();
+        drop(versions);
+
+        let mut versions = VersionSet::new(dir.clone(), comparator.clone());
+        versions.recover().unwrap();
+        assert_eq!(versions.current(), &expected);
+        assert_eq!(versions.current().files[1][0].number, 5);
+        assert_eq!(versions.log_number(), 1);
+        assert_eq!(versions.last_sequence(), 39);
+        assert_eq!(versions.compact_pointers(), &compact_pointers[..]);
+        assert_eq!(versions.new_file_number(), next_file_number);
+
+        // reopening starts a new manifest from a snapshot
+        versions.log_and_apply(VersionEdit::new()).unwrap();
+        let manifest_number = versions.manifest_number();
+        assert!(manifest_number > 2);
+        assert_eq!(filename::read_current_file(&dir).unwrap(), manifest_number);
+        drop(versions);
+        let mut versions = VersionSet::new(dir.clone(), comparator);
+        versions.recover().unwrap();
+        assert_eq!(versions.current(), &expected);
+        assert_eq!(versions.compact_pointers(), &compact_pointers[..]);
+
+        let reverse = InternalKeyComparator::new(Arc::new(ReverseBytewiseComparator));
+        let mut versions = VersionSet::new(dir.clone(), reverse);
+        let err = versions.recover().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
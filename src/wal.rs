@@ -0,0 +1,395 @@
+//! Write-ahead log in LevelDB's log format
+//!
+//! The log is a sequence of 32 KiB blocks. Records are split into fragments
+//! so that no fragment crosses a block boundary, each fragment is written
+//! with a header:
+//!
+//! ```text
+//! +--------------+--------------+-----------+---------------+
+//! | crc32c (u32) | length (u16) | type (u8) | data (length) |
+//! +--------------+--------------+-----------+---------------+
+//! ```
+//!
+//! The type tells whether the fragment holds a FULL record or the FIRST,
+//! a MIDDLE or the LAST part of one. A block trailer too small for a header
+//! is filled with zeros. The CRC covers the type and the data, and is
+//! masked since CRCs of data holding CRCs are poorly distributed.
+
+use crate::crc;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+pub const BLOCK_SIZE: usize = 32 * 1024;
+
+/// checksum (4 bytes), length (2 bytes), type (1 byte)
+pub const HEADER_SIZE: usize = 4 + 2 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordType {
+    /// Preallocated file regions
+    Zero = 0,
+    Full = 1,
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl RecordType {
+    fn from_u8(b: u8) -> Option<RecordType> {
+        match b {
+            0 => Some(RecordType::Zero),
+            1 => Some(RecordType::Full),
+            2 => Some(RecordType::First),
+            3 => Some(RecordType::Middle),
+            4 => Some(RecordType::Last),
+            _ => None,
+        }
+    }
+}
+
+pub struct Writer<W: Write> {
+    dest: W,
+    /// Offset in the current block
+    block_offset: usize,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes a new log to the empty `dest`
+    pub fn new(dest: W) -> Writer<W> {
+        Self::with_offset(dest, 0)
+    }
+
+    /// Appends to a log already holding `dest_len` bytes
+    pub fn with_offset(dest: W, dest_len: u64) -> Writer<W> {
+        Writer {
+            dest,
+            block_offset: (dest_len % BLOCK_SIZE as u64) as usize,
+        }
+    }
+
+    /// Writes `record` and flushes it, syncing it is up to the caller
+    pub fn add_record(&mut self, record: &[u8]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(record.len() + HEADER_SIZE);
+        let mut left = record;
+        let mut begin = true;
+        // an empty record still gets a zero length fragment
+        loop {
+            let leftover = BLOCK_SIZE - self.block_offset;
+            if leftover < HEADER_SIZE {
+                buf.resize(buf.len() + leftover, 0);
+                self.block_offset = 0;
+            }
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_len = left.len().min(avail);
+            let end = fragment_len == left.len();
+            let record_type = match (begin, end) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+            let (fragment, rest) = left.split_at(fragment_len);
+            self.emit_physical_record(&mut buf, record_type, fragment);
+            left = rest;
+            begin = false;
+            if end {
+                break;
+            }
+        }
+        self.dest.write_all(&buf)?;
+        self.dest.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.dest
+    }
+
+    fn emit_physical_record(&mut self, buf: &mut Vec<u8>, record_type: RecordType, data: &[u8]) {
+        let crc = crc::extend(crc::value(&[record_type as u8]), data);
+        buf.extend_from_slice(&crc::mask(crc).to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        buf.push(record_type as u8);
+        buf.extend_from_slice(data);
+        self.block_offset += HEADER_SIZE + data.len();
+    }
+}
+
+enum PhysicalRecord {
+    Record(RecordType, usize, usize),
+    Eof,
+    /// Corrupted or skipped fragment
+    Bad,
+}
+
+/// Reads records back from a log
+///
+/// A truncated record at the end of the log, left by a crash in the middle
+/// of a write, is silently ignored. Corrupted fragments elsewhere are
+/// skipped along with the rest of their block and counted in
+/// `dropped_bytes`.
+pub struct Reader<R: Read> {
+    src: R,
+    block: Vec<u8>,
+    /// Start of the unread part of `block`
+    pos: usize,
+    eof: bool,
+    dropped_bytes: usize,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(src: R) -> Reader<R> {
+        Reader {
+            src,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            pos: 0,
+            eof: false,
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Next record, `None` at the end of the log
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut record = Vec::new();
+        let mut in_fragmented_record = false;
+        loop {
+            match self.read_physical_record()? {
+                PhysicalRecord::Record(record_type, start, end) => match record_type {
+                    RecordType::Full => {
+                        if in_fragmented_record {
+                            self.report(record.len(), "partial record without end");
+                        }
+                        return Ok(Some(self.block[start..end].to_vec()));
+                    }
+                    RecordType::First => {
+                        if in_fragmented_record {
+                            self.report(record.len(), "partial record without end");
+                        }
+                        record = self.block[start..end].to_vec();
+                        in_fragmented_record = true;
+                    }
+                    RecordType::Middle | RecordType::Last if !in_fragmented_record => {
+                        self.report(end - start, "missing start of fragmented record");
+                    }
+                    RecordType::Middle => record.extend_from_slice(&self.block[start..end]),
+                    RecordType::Last => {
+                        record.extend_from_slice(&self.block[start..end]);
+                        return Ok(Some(record));
+                    }
+                    RecordType::Zero => unreachable!(),
+                },
+                // a record cut short by the end of the log was being written
+                // during a crash, it was never acknowledged
+                PhysicalRecord::Eof => return Ok(None),
+                PhysicalRecord::Bad => {
+                    if in_fragmented_record {
+                        self.report(record.len(), "error in middle of record");
+                        record.clear();
+                        in_fragmented_record = false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bytes skipped because of corruptions
+    pub fn dropped_bytes(&self) -> usize {
+        self.dropped_bytes
+    }
+
+    fn read_physical_record(&mut self) -> io::Result<PhysicalRecord> {
+        loop {
+            let remaining = self.block.len() - self.pos;
+            if remaining < HEADER_SIZE {
+                if self.eof {
+                    // a header cut short, or the last block is done
+                    return Ok(PhysicalRecord::Eof);
+                }
+                // skip the block trailer
+                self.read_block()?;
+                continue;
+            }
+            let header = &self.block[self.pos..self.pos + HEADER_SIZE];
+            let expected_crc = crc::unmask(u32::from_le_bytes(header[..4].try_into().unwrap()));
+            let length = u16::from_le_bytes(header[4..6].try_into().unwrap()) as usize;
+            let type_byte = header[6];
+            if HEADER_SIZE + length > remaining {
+                self.pos = self.block.len();
+                if self.eof {
+                    return Ok(PhysicalRecord::Eof);
+                }
+                self.report(remaining, "bad record length");
+                return Ok(PhysicalRecord::Bad);
+            }
+            if type_byte == RecordType::Zero as u8 && length == 0 {
+                // preallocated space, not worth reporting
+                self.pos = self.block.len();
+                return Ok(PhysicalRecord::Bad);
+            }
+            let start = self.pos + HEADER_SIZE;
+            let end = start + length;
+            let actual_crc = crc::extend(crc::value(&[type_byte]), &self.block[start..end]);
+            if actual_crc != expected_crc {
+                // the length itself may be corrupted, drop the whole block
+                self.pos = self.block.len();
+                self.report(remaining, "checksum mismatch");
+                return Ok(PhysicalRecord::Bad);
+            }
+            self.pos = end;
+            return Ok(match RecordType::from_u8(type_byte) {
+                Some(RecordType::Zero) | None => {
+                    self.report(end - start + HEADER_SIZE, "unknown record type");
+                    PhysicalRecord::Bad
+                }
+                Some(record_type) => PhysicalRecord::Record(record_type, start, end),
+            });
+        }
+    }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        self.block.resize(BLOCK_SIZE, 0);
+        let mut len = 0;
+        while len < BLOCK_SIZE {
+            match self.src.read(&mut self.block[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.block.truncate(len);
+        self.pos = 0;
+        self.eof = len < BLOCK_SIZE;
+        Ok(())
+    }
+
+    fn report(&mut self, bytes: usize, reason: &str) {
+        log::warn!("log corruption, dropping {} bytes: {}", bytes, reason);
+        self.dropped_bytes += bytes;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record(i: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|j| (i * 31 + j) as u8).collect()
+    }
+
+    fn read_all(log: &[u8]) -> (Vec<Vec<u8>>, usize) {
+        let mut reader = Reader::new(log);
+        let mut records = Vec::new();
+        while let Some(record) = reader.read_record().unwrap() {
+            records.push(record);
+        }
+        (records, reader.dropped_bytes())
+    }
+
+    #[test]
+    fn test_read_write() {
+        let lens = [
+            0,
+            1,
+            100,
+            BLOCK_SIZE - HEADER_SIZE,
+            3 * BLOCK_SIZE + 17,
+            10_000,
+            5,
+        ];
+        let records: Vec<Vec<u8>> = lens
+            .iter()
+            .enumerate()
+            .map(|(i, len)| record(i, *len))
+            .collect();
+        let mut writer = Writer::new(Vec::new());
+        for record in records.iter() {
+            writer.add_record(record).unwrap();
+        }
+        let log = writer.get_ref().clone();
+        assert_eq!(read_all(&log), (records.clone(), 0));
+
+        // block trailers too small for a header are padded
+        let mut writer = Writer::new(Vec::new());
+        writer
+            .add_record(&record(0, BLOCK_SIZE - 2 * HEADER_SIZE + 1))
+            .unwrap();
+        writer.add_record(b"next").unwrap();
+        assert_eq!(writer.get_ref().len(), BLOCK_SIZE + HEADER_SIZE + 4);
+        assert_eq!(read_all(writer.get_ref()).0.len(), 2);
+
+        // appending to an existing log
+        let mut writer = Writer::with_offset(log.clone(), log.len() as u64);
+        writer.add_record(&record(9, 40_000)).unwrap();
+        let (read, dropped) = read_all(writer.get_ref());
+        assert_eq!(dropped, 0);
+        assert_eq!(read[..records.len()], records[..]);
+        assert_eq!(read[records.len()], record(9, 40_000));
+    }
+
+    #[test]
+    fn test_torn_tail() {
+        let records: Vec<Vec<u8>> = (0..10).map(|i| record(i, 800 * i)).collect();
+        let mut writer = Writer::new(Vec::new());
+        let mut last_start = 0;
+        for record in records.iter() {
+            last_start = writer.get_ref().len();
+            writer.add_record(record).unwrap();
+        }
+        // record 9 crosses a block boundary
+        let log = writer.get_ref();
+        assert!(last_start < BLOCK_SIZE && log.len() > BLOCK_SIZE);
+        // every truncation inside the last record only loses that record
+        for len in last_start..log.len() {
+            let (read, dropped) = read_all(&log[..len]);
+            assert_eq!(dropped, 0);
+            assert!(read == records[..9], "truncated at {}", len);
+        }
+        assert_eq!(read_all(log).0, records);
+    }
+
+    #[test]
+    fn test_corruption() {
+        let records: Vec<Vec<u8>> = (0..20).map(|i| record(i, 5000)).collect();
+        let mut writer = Writer::new(Vec::new());
+        for record in records.iter() {
+            writer.add_record(record).unwrap();
+        }
+        let mut log = writer.get_ref().clone();
+        // corrupt the first record of the second block, the rest of the
+        // block is dropped
+        log[BLOCK_SIZE + 1000] ^= 1;
+        let (read, dropped) = read_all(&log);
+        assert!(dropped > 0);
+        assert!(read.len() < records.len());
+        // the records of the first and third blocks are intact
+        assert_eq!(read[..6], records[..6]);
+        assert!(read.ends_with(&records[14..]));
+        assert!(read.iter().all(|r| records.contains(r)));
+
+        let mut bad_type = Writer::new(Vec::new());
+        bad_type.add_record(b"foo").unwrap();
+        let mut log = bad_type.get_ref().clone();
+        log[6] = 9;
+        let crc = crc::extend(crc::value(&[9]), b"foo");
+        log[..4].copy_from_slice(&crc::mask(crc).to_le_bytes());
+        assert_eq!(read_all(&log), (vec![], 3 + HEADER_SIZE));
+    }
+
+    #[test]
+    fn test_reader_io() {
+        let mut writer = Writer::new(Vec::new());
+        writer.add_record(&record(1, 100_000)).unwrap();
+        // reads returning a few bytes at a time
+        struct Trickle<'a>(Cursor<&'a [u8]>);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(1000);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        let mut reader = Reader::new(Trickle(Cursor::new(writer.get_ref())));
+        assert_eq!(reader.read_record().unwrap(), Some(record(1, 100_000)));
+        assert_eq!(reader.read_record().unwrap(), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::document::Document;
 use quick_xml::de::{from_str, DeError};
 use serde::Deserialize;
 use std::fs::File;
@@ -10,10 +11,32 @@ pub struct WikiDoc {
     #[serde(skip)]
     pub id: String,
     pub title: String,
-    url: String,
+    pub url: String,
     pub r#abstract: String,
 }
 
+impl WikiDoc {
+    pub fn new(title: &str, url: &str, r#abstract: &str) -> WikiDoc {
+        WikiDoc {
+            id: Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            url: url.to_string(),
+            r#abstract: r#abstract.to_string(),
+        }
+    }
+}
+
+impl From<&WikiDoc> for Document {
+    fn from(wiki_doc: &WikiDoc) -> Document {
+        // none of these field names is reserved
+        let mut doc = Document::new();
+        doc.add_keyword("url", &wiki_doc.url).unwrap();
+        doc.add_keyword("title", &wiki_doc.title).unwrap();
+        doc.add_text("abstract", &wiki_doc.r#abstract).unwrap();
+        doc
+    }
+}
+
 fn load_corpus(fp: &str) -> std::io::Result<String> {
     let file = File::open(fp)?;
     let mut br = BufReader::new(file);
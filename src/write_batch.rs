@@ -0,0 +1,245 @@
+use crate::coding;
+use crate::internal_key::{SequenceNumber, ValueType};
+use crate::memtable::MemTable;
+use std::convert::TryInto;
+use std::io;
+
+/// sequence number (8 bytes), count (4 bytes)
+const HEADER_SIZE: usize = 12;
+
+/// Puts and deletes applied atomically by `Db::write`
+///
+/// The batch is kept in the format it is logged in, a header followed by
+/// its entries in the order they were added:
+///
+/// ```text
+/// +----------------+-------------+---------+-----+---------------+
+/// | sequence (u64) | count (u32) | entry 1 | ... | entry (count) |
+/// +----------------+-------------+---------+-----+---------------+
+///
+/// entry := Value (u8) key value | Deletion (u8) key
+/// with key and value length prefixed by a varint
+/// ```
+///
+/// Entries are numbered from the sequence number of the batch, so a later
+/// entry of a key overrides an earlier one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+pub struct Iter<'a> {
+    input: &'a [u8],
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch {
+            rep: vec![0; HEADER_SIZE],
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Value as u8);
+        coding::put_length_prefixed(&mut self.rep, key);
+        coding::put_length_prefixed(&mut self.rep, value);
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Deletion as u8);
+        coding::put_length_prefixed(&mut self.rep, key);
+    }
+
+    /// Appends the entries of `other`
+    pub fn append(&mut self, other: &WriteBatch) {
+        self.set_count(self.count() + other.count());
+        self.rep.extend_from_slice(&other.rep[HEADER_SIZE..]);
+    }
+
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER_SIZE, 0);
+    }
+
+    /// Number of entries
+    pub fn count(&self) -> u32 {
+        u32::from_le_bytes(self.rep[8..HEADER_SIZE].try_into().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Size in bytes of the batch once logged
+    pub fn approximate_size(&self) -> usize {
+        self.rep.len()
+    }
+
+    /// Entries as (type, key, value), the value of deletions is empty
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            input: &self.rep[HEADER_SIZE..],
+        }
+    }
+
+    /// Sequence number of the first entry
+    pub(crate) fn sequence(&self) -> SequenceNumber {
+        u64::from_le_bytes(self.rep[..8].try_into().unwrap())
+    }
+
+    pub(crate) fn set_sequence(&mut self, sequence: SequenceNumber) {
+        self.rep[..8].copy_from_slice(&sequence.to_le_bytes());
+    }
+
+    pub(crate) fn contents(&self) -> &[u8] {
+        &self.rep
+    }
+
+    /// Batch read back from a log record, checking it is well formed
+    pub(crate) fn from_contents(contents: Vec<u8>) -> io::Result<WriteBatch> {
+        let corruption = || io::Error::new(io::ErrorKind::InvalidData, "malformed write batch");
+        if contents.len() < HEADER_SIZE {
+            return Err(corruption());
+        }
+        let batch = WriteBatch { rep: contents };
+        let mut input = &batch.rep[HEADER_SIZE..];
+        let mut count = 0;
+        while !input.is_empty() {
+            parse_entry(&mut input).ok_or_else(corruption)?;
+            count += 1;
+        }
+        if count != batch.count() {
+            return Err(corruption());
+        }
+        Ok(batch)
+    }
+
+    /// Adds the entries to `mem`, returns the sequence number of the last
+    /// one. Fails if `mem` already holds some of them, once the others are
+    /// added.
+    pub(crate) fn insert_into(&self, mem: &MemTable) -> io::Result<SequenceNumber> {
+        let mut sequence = self.sequence();
+        let mut duplicates = 0;
+        for (value_type, key, value) in self.iter() {
+            if !mem.add(sequence, value_type, key, value) {
+                duplicates += 1;
+            }
+            sequence += 1;
+        }
+        if duplicates > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} entries of the batch at sequence number {} already added",
+                    duplicates,
+                    self.sequence()
+                ),
+            ));
+        }
+        Ok(sequence - 1)
+    }
+
+    fn set_count(&mut self, count: u32) {
+        self.rep[8..HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+    }
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (ValueType, &'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None;
+        }
+        Some(parse_entry(&mut self.input).expect("malformed write batch"))
+    }
+}
+
+fn parse_entry<'a>(input: &mut &'a [u8]) -> Option<(ValueType, &'a [u8], &'a [u8])> {
+    let (value_type, rest) = input.split_first()?;
+    let value_type = ValueType::from_u8(*value_type)?;
+    let mut rest = rest;
+    let key = coding::get_length_prefixed(&mut rest)?;
+    let value = match value_type {
+        ValueType::Value => coding::get_length_prefixed(&mut rest)?,
+        ValueType::Deletion => &[],
+    };
+    *input = rest;
+    Some((value_type, key, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::internal_key::InternalKeyComparator;
+    use crate::memtable::{GetResult, MAX_CAPACITY};
+
+    #[test]
+    fn test_write_batch() {
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.put(b"baz", b"boo");
+        batch.put(b"foo", b"new");
+        batch.set_sequence(100);
+        assert_eq!(batch.count(), 4);
+        assert_eq!(batch.sequence(), 100);
+        let entries: Vec<_> = batch.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                (ValueType::Value, &b"foo"[..], &b"bar"[..]),
+                (ValueType::Deletion, b"box", b""),
+                (ValueType::Value, b"baz", b"boo"),
+                (ValueType::Value, b"foo", b"new"),
+            ]
+        );
+
+        let mem = MemTable::with_capacity(InternalKeyComparator::default(), MAX_CAPACITY);
+        assert_eq!(batch.insert_into(&mem).unwrap(), 103);
+        assert_eq!(mem.get(b"foo", 103), GetResult::Found(b"new".to_vec()));
+        assert_eq!(mem.get(b"foo", 102), GetResult::Found(b"bar".to_vec()));
+        assert_eq!(mem.get(b"box", 103), GetResult::Deleted);
+
+        let mut other = WriteBatch::new();
+        other.delete(b"foo");
+        batch.append(&other);
+        assert_eq!(batch.count(), 5);
+        assert_eq!(
+            batch.iter().last(),
+            Some((ValueType::Deletion, &b"foo"[..], &b""[..]))
+        );
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.approximate_size(), HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_from_contents() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"k", b"v");
+        batch.delete(b"k");
+        batch.set_sequence(7);
+        let contents = batch.contents().to_vec();
+        assert_eq!(WriteBatch::from_contents(contents.clone()).unwrap(), batch);
+
+        let truncated = contents[..contents.len() - 1].to_vec();
+        assert!(WriteBatch::from_contents(truncated).is_err());
+        assert!(WriteBatch::from_contents(vec![0; 4]).is_err());
+        let mut bad_count = contents.clone();
+        bad_count[8] = 3;
+        assert!(WriteBatch::from_contents(bad_count).is_err());
+        let mut bad_type = contents;
+        bad_type[HEADER_SIZE] = 9;
+        assert!(WriteBatch::from_contents(bad_type).is_err());
+    }
+}